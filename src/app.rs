@@ -1,21 +1,94 @@
-use crate::models::{ArchiveFile, ArchiveZone};
-use crate::parallel::compress_files_parallel;
-use crate::utils::{get_temp_dir, open_system_file};
+use crate::edits::{EntryChange, PendingChanges, WatchedEntry, WATCH_INTERVAL};
+use crate::extract::extract_entries;
+use crate::filters::{FilterSet, InputFilters};
+use crate::integrity::{test_archive, ArchiveTestReport};
+use crate::metadata::{is_root, Reproducible, RestorePolicy};
+use crate::listing::{ContentsIndex, ContentsView, EntryFilter, FilterMode, SortColumn};
+use crate::models::{ArchiveFile, ArchiveZone, SignatureStatus};
+use crate::preview::{Preview, PreviewContent, PreviewLoader, PREVIEW_CHUNK};
+use crate::parallel::{compress_files_parallel, estimate_compressed_size};
+use crate::search::{search_contents, SearchHit, SearchOptions};
+use crate::safety::{check_archive, check_extraction, check_output_space, SafetyLimits, SafetyViolation};
+use crate::reader::{comic_pages, looks_like_comic, seven_zip_pages, ComicReader};
+use crate::rewrite::{collect_named_entries, collect_new_entries, read_raw_entries, rewrite_archive, LinkPolicy, NewEntry, RawEntry};
+use crate::recovery::{scan_local_headers, write_repaired_archive, RecoveredEntry};
+use crate::sfx::{create_self_extractor, SfxOptions};
+use crate::signing::{self, PublicKey, SignatureMode};
+use crate::staging::StagingTree;
+use crate::sevenzip::{compress_to_seven_zip, is_seven_zip, unpack_to_zip};
+use crate::tarball::{compress_to_tar, tar_format};
+use crate::update::{update_archive, ChangeDetection, UpdateMode};
+use crate::utils::{archive_needs_password, get_formatted_size, get_temp_dir, open_system_file};
+use crate::volumes::{find_volume_set, is_volume_name, join_volumes, split_archive, VolumeStyle};
 use egui::{Window};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
 use zip::ZipArchive;
 
+const READ_ONLY_COPY: &str = "Error: archives joined from split volumes or unpacked from 7z are read-only; extract the entries instead";
+
 #[derive(Clone, Default)]
 pub struct ProgressState {
     pub compression_progress: Option<(f32, CompressionStats)>,
     pub extraction_progress: Option<(f32, ExtractionStats)>,
+    pub test_progress: Option<f32>,
+    pub test_report: Option<ArchiveTestReport>,
+    pub search_progress: Option<f32>,
+    /// Identifies the newest search; older workers drop their results
+    pub search_generation: u64,
+    /// Hits found since the UI last collected them
+    pub search_hits: Vec<SearchHit>,
+    pub rewrite_progress: Option<f32>,
+    pub archive_update: Option<ArchiveUpdate>,
+    /// What is being done and how far along, while a readable copy is made
+    pub copy_progress: Option<(&'static str, f32)>,
+    /// A readable copy made in the background, until the UI opens it
+    pub readable_copy: Option<ReadableCopy>,
+    /// Identifies the newest preview; older workers drop their results
+    pub preview_generation: u64,
+    /// Latest chunk of the entry being previewed, until the UI collects it
+    pub preview_result: Option<Result<Preview, String>>,
+    /// Identifies the newest listing of the compression list
+    pub compression_preview_generation: u64,
+    /// Entry names and sizes listed by the newest worker, until the UI collects them
+    pub compression_preview_result: Option<Result<Vec<(String, u64)>, String>>,
+    /// Message for the status bar once a compression has finished
+    pub compression_result: Option<Result<String, String>>,
+    /// Entries opened with the system app since the UI last collected them
+    pub opened_entries: Vec<WatchedEntry>,
+    /// Why the last entry could not be opened, until the UI shows it
+    pub open_entry_error: Option<String>,
+}
+
+/// Outcome of a background rewrite of the open archive.
+#[derive(Clone)]
+pub struct ArchiveUpdate {
+    pub path: PathBuf,
+    pub password: Option<String>,
+    pub result: Result<String, String>,
+}
+
+/// Outcome of making a ZIP the app can open from a split archive or a 7z
+/// archive, in the background.
+#[derive(Clone)]
+pub struct ReadableCopy {
+    /// The file the user opened
+    pub source: PathBuf,
+    /// The archive the copy was made from, to retry with a password
+    pub archive: PathBuf,
+    pub password: Option<String>,
+    /// The copy failed because the archive is encrypted and no password was given
+    pub needs_password: bool,
+    /// The copy in the temp folder
+    pub result: Result<PathBuf, String>,
 }
 
 #[derive(Clone)]
@@ -39,14 +112,17 @@ pub struct ExtractionStats {
 }
 
 pub struct ArchiveManager {
-    pub selected_files: Vec<PathBuf>,
-    pub files_to_remove: Vec<usize>,
+    /// The compression list, laid out as it will appear in the archive
+    pub staging: StagingTree,
+    /// Folders collapsed in the compression list
+    pub staging_collapsed: HashSet<String>,
     pub dark_mode: bool,
     pub status_message: String,
     pub show_settings: bool,
-    pub current_archive: Option<(PathBuf, Vec<ArchiveFile>)>,
+    pub current_archive: Option<(PathBuf, Arc<Vec<ArchiveFile>>)>,
     pub compress_zone: ArchiveZone,
-    pub extract_zone: ArchiveZone,
+    /// The "Archive Contents" view; files dropped here are added to the open archive
+    pub contents_zone: ArchiveZone,
     pub progress_state: Arc<Mutex<ProgressState>>,
     pub compression_sender: Option<Sender<()>>,
     pub hover_file: Option<String>,
@@ -56,6 +132,79 @@ pub struct ArchiveManager {
     pub show_action_dialog: bool,
     pub pending_archive_path: Option<PathBuf>,
     pub remember_archive_choice: bool,pub last_archive_choice: Option<bool>,
+    pub signature_status: SignatureStatus,
+    pub sign_archives: bool,
+    pub signing_key_path: Option<PathBuf>,
+    pub signature_mode: SignatureMode,
+    pub trusted_keys: Vec<PublicKey>,
+    pub test_report: Option<ArchiveTestReport>,
+    pub recovered_entries: Option<Vec<RecoveredEntry>>,
+    /// The copy joined from a split archive or unpacked from a 7z archive,
+    /// and the file it was opened from. Changes to the copy would never reach
+    /// that file, so it is read-only
+    pub read_only_copy: Option<(PathBuf, PathBuf)>,
+    pub safety_limits: SafetyLimits,
+    pub restore_policy: RestorePolicy,
+    /// Restore extended attributes, ACLs and SELinux labels on extraction
+    pub restore_xattrs: bool,
+    pub link_policy: LinkPolicy,
+    pub input_filters: InputFilters,
+    /// Write new archives in name order with fixed timestamps and permissions
+    pub reproducible_output: bool,
+    /// Split new archives into volumes of `volume_size_mib` MiB
+    pub split_output: bool,
+    pub volume_size_mib: u64,
+    pub volume_style: VolumeStyle,
+    /// Also write a self-extracting executable next to new archives
+    pub self_extracting: bool,
+    pub sfx_options: SfxOptions,
+    /// Entry names and sizes the compression list expands to, when previewed
+    pub compression_preview: Option<Vec<(String, u64)>>,
+    /// Folders are being walked for the compression preview
+    pub compression_preview_loading: bool,
+    pub pending_safety: Option<(SafetyAction, Vec<SafetyViolation>)>,
+    pub safety_override: bool,
+    pub extraction_dir: Option<PathBuf>,
+    pub contents_index: Option<ContentsIndex>,
+    pub contents_view: ContentsView,
+    pub sort_column: SortColumn,
+    pub sort_ascending: bool,
+    pub filter_text: String,
+    pub filter_mode: FilterMode,
+    pub filter_error: Option<String>,
+    /// Indices into the current archive's file list
+    pub selected_entries: HashSet<usize>,
+    pub show_content_search: bool,
+    pub search_options: SearchOptions,
+    pub search_hits: Vec<SearchHit>,
+    pub search_error: Option<String>,
+    pub search_cancel: Option<Arc<AtomicBool>>,
+    pub show_preview: bool,
+    pub preview: Option<Preview>,
+    pub preview_error: Option<String>,
+    preview_password: Option<String>,
+    /// Worker holding the previewed entry's decompressor open
+    preview_loader: Option<PreviewLoader>,
+    /// Set while the preview worker is decompressing
+    preview_loading: bool,
+    /// Image bytes registered with egui's loaders for the current preview
+    preview_image_uri: Option<String>,
+    pub comic_reader: Option<ComicReader>,
+    /// A 7z comic waiting for the UI context to start its reader
+    pub pending_reader: Option<(PathBuf, Option<String>, Vec<String>)>,
+    /// The current archive is a comic book or mostly images
+    pub is_comic: bool,
+    /// Replace same-named entries when adding files to an open archive
+    pub replace_existing: bool,
+    pub pending_changes: PendingChanges,
+    /// Staged name of each entry, `None` if it is staged for deletion
+    pub pending_view: HashMap<String, Option<String>>,
+    pub entry_edit: Option<EntryEdit>,
+    pub update_request: Option<UpdateRequest>,
+    pub watched_entries: Vec<WatchedEntry>,
+    /// Entry whose edited temp copy is waiting for the user to confirm the update
+    pub entry_update_prompt: Option<String>,
+    last_watch_check: Instant,
 }
 
 #[derive(Clone)]
@@ -63,20 +212,78 @@ pub enum PasswordOperation {
     Compress,
     OpenArchive(PathBuf),
     ExtractFile(String),
+    ExtractEntries(Vec<String>, PathBuf),
+    TestArchive,
+    SearchContents,
+    Preview(String),
+    OpenReader,
+    AddToArchive(Vec<PathBuf>),
+    UpdateFromFolder(UpdateRequest),
+}
+
+/// Options of the update/freshen/sync dialog.
+#[derive(Clone)]
+pub struct UpdateRequest {
+    pub source: PathBuf,
+    pub mode: UpdateMode,
+    pub detection: ChangeDetection,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Rename,
+    Move,
+    /// Creates a folder; `path` is unused
+    NewFolder,
+}
+
+/// State of the rename/move dialog for one entry or folder.
+pub struct EntryEdit {
+    pub kind: EditKind,
+    pub path: String,
+    pub text: String,
+    pub error: Option<String>,
+    /// Edits the compression list instead of the open archive
+    pub staging: bool,
+}
+
+impl EntryEdit {
+    pub fn new(kind: EditKind, path: String, staging: bool) -> Self {
+        let text = match kind {
+            EditKind::Rename => path.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string(),
+            EditKind::Move | EditKind::NewFolder => String::new(),
+        };
+        Self { kind, path, text, error: None, staging }
+    }
+}
+
+enum SafetyChoice {
+    Cancel,
+    ChangeDestination,
+    Continue,
+}
+
+/// An operation held back by a safety warning, resumed if the user overrides it.
+#[derive(Clone)]
+pub enum SafetyAction {
+    Compress(PathBuf, Option<String>),
+    OpenArchive(PathBuf, Option<String>),
+    ExtractFile(String, Option<String>),
+    ExtractEntries(Vec<String>, PathBuf, Option<String>),
 }
 
 impl Default for ArchiveManager {
     fn default() -> Self {
         Self {
-            selected_files: Vec::new(),
-            files_to_remove: Vec::new(),
+            staging: StagingTree::default(),
+            staging_collapsed: HashSet::new(),
             dark_mode: true,
             status_message: String::new(),
             show_settings: false,
             remember_archive_choice: false,
             current_archive: None,
             compress_zone: ArchiveZone::default(),
-            extract_zone: ArchiveZone::default(),
+            contents_zone: ArchiveZone::default(),
             compression_sender: None,
             hover_file: None,
             progress_state: Arc::new(Mutex::new(ProgressState::default())),
@@ -86,83 +293,264 @@ impl Default for ArchiveManager {
             show_action_dialog: false,
             pending_archive_path: None,
             last_archive_choice: None,
+            signature_status: SignatureStatus::Unsigned,
+            sign_archives: false,
+            signing_key_path: None,
+            signature_mode: SignatureMode::Embedded,
+            trusted_keys: signing::load_trusted_keys(),
+            test_report: None,
+            recovered_entries: None,
+            read_only_copy: None,
+            safety_limits: SafetyLimits::default(),
+            restore_policy: RestorePolicy::default(),
+            restore_xattrs: false,
+            link_policy: LinkPolicy::default(),
+            input_filters: InputFilters::default(),
+            reproducible_output: false,
+            split_output: false,
+            volume_size_mib: 100,
+            volume_style: VolumeStyle::default(),
+            self_extracting: false,
+            sfx_options: SfxOptions::default(),
+            compression_preview: None,
+            compression_preview_loading: false,
+            pending_safety: None,
+            safety_override: false,
+            extraction_dir: None,
+            contents_index: None,
+            contents_view: ContentsView::Tree,
+            sort_column: SortColumn::Name,
+            sort_ascending: true,
+            filter_text: String::new(),
+            filter_mode: FilterMode::Substring,
+            filter_error: None,
+            selected_entries: HashSet::new(),
+            show_content_search: false,
+            search_options: SearchOptions::default(),
+            search_hits: Vec::new(),
+            search_error: None,
+            search_cancel: None,
+            show_preview: true,
+            preview: None,
+            preview_error: None,
+            preview_password: None,
+            preview_loader: None,
+            preview_loading: false,
+            preview_image_uri: None,
+            comic_reader: None,
+            pending_reader: None,
+            is_comic: false,
+            replace_existing: false,
+            pending_changes: PendingChanges::default(),
+            pending_view: HashMap::new(),
+            entry_edit: None,
+            update_request: None,
+            watched_entries: Vec::new(),
+            entry_update_prompt: None,
+            last_watch_check: Instant::now(),
         }
     }
 }
 
 impl ArchiveManager {
     pub fn compress_files_with_password(&mut self, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-        if self.selected_files.is_empty() {
+        if self.staging.is_empty() {
             self.status_message = "No files selected".to_string();
             return Ok(());
         }
 
         if let Some(output_path) = rfd::FileDialog::new()
             .add_filter("ZIP", &["zip"])
+            .add_filter("tar", &["tar", "tgz", "gz"])
+            .add_filter("7z", &["7z"])
             .set_file_name("archive.zip")
             .save_file()
         {
-            let files = self.selected_files.clone();
-            let (progress_tx, progress_rx) = channel();
-            let (cancel_tx, _cancel_rx) = channel();
-            let cancel_tx = Arc::new(Mutex::new(cancel_tx));
-            let progress_state = Arc::clone(&self.progress_state);
+            self.start_compression(output_path, password)?;
+        }
 
-            let total_size: u64 = files
-                .iter()
-                .filter_map(|path| std::fs::metadata(path).ok())
-                .map(|meta| meta.len())
-                .sum();
-
-            let stats = CompressionStats {
-                original_size: total_size,
-                compressed_size: 0,
-                start_time: Instant::now(),
-                estimated_time: Duration::from_secs(0),
-                output_path: output_path.clone(),
-                files_processed: 0,
-                total_files: files.len(),
+        Ok(())
+    }
+
+    pub fn start_compression(&mut self, output_path: PathBuf, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let signing_key = if self.sign_archives {
+            let Some(key_path) = &self.signing_key_path else {
+                self.status_message = "Error: Signing is enabled but no secret key is selected".to_string();
+                return Ok(());
             };
-            let stats = Arc::new(Mutex::new(stats));
+            match signing::load_secret_key(key_path) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    self.status_message = format!("Error loading signing key: {}", e);
+                    return Ok(());
+                }
+            }
+        } else {
+            None
+        };
+        let signature_mode = self.signature_mode;
+
+        let tar = tar_format(&output_path);
+        let seven_zip = is_seven_zip(&output_path);
+        if tar.is_some() || seven_zip {
+            let unsupported = [
+                (tar.is_some() && password.is_some(), "encryption"),
+                (signing_key.is_some(), "signatures"),
+                (self.self_extracting, "self-extracting output"),
+                (self.split_output && self.volume_style == VolumeStyle::Spanned, "spanned volumes"),
+            ];
+            if let Some((_, feature)) = unsupported.iter().find(|(enabled, _)| *enabled) {
+                let format = if seven_zip { "7z" } else { "tar" };
+                self.status_message = format!("Error: {} archives do not support {}", format, feature);
+                return Ok(());
+            }
+        }
 
-            self.compression_sender = Some(cancel_tx.lock().unwrap().clone());
+        let files = match self.collect_compression_entries(password.is_some()) {
+            Ok(files) => files,
+            Err(e) => {
+                self.status_message = format!("Error collecting files to compress: {}", e);
+                return Ok(());
+            }
+        };
+        if files.is_empty() {
+            self.status_message = "Error: The filters leave no files to compress".to_string();
+            return Ok(());
+        }
+        let total_size: u64 = files
+            .iter()
+            .filter(|entry| entry.is_file())
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|meta| meta.len())
+            .sum();
+
+        let destination = output_path.parent().unwrap_or(Path::new("."));
+        let violations = check_output_space(
+            estimate_compressed_size(&files, total_size),
+            destination,
+            &self.safety_limits,
+        );
+        if !self.passes_safety_checks(
+            SafetyAction::Compress(output_path.clone(), password.clone()),
+            violations.into_iter().collect(),
+        ) {
+            return Ok(());
+        }
 
-            let password = password.unwrap_or_default();
+        let (progress_tx, progress_rx) = channel();
+        let (cancel_tx, cancel_rx) = channel();
+        let cancel_rx = Arc::new(Mutex::new(cancel_rx));
+        let progress_state = Arc::clone(&self.progress_state);
+
+        let stats = CompressionStats {
+            original_size: total_size,
+            compressed_size: 0,
+            start_time: Instant::now(),
+            estimated_time: Duration::from_secs(0),
+            output_path: output_path.clone(),
+            files_processed: 0,
+            total_files: files.len(),
+        };
+        let stats = Arc::new(Mutex::new(stats));
+
+        self.compression_sender = Some(cancel_tx);
+
+        let reproducible = self.reproducible_output.then(Reproducible::from_env);
+        let volumes = self.split_output.then_some((self.volume_size_mib * 1024 * 1024, self.volume_style));
+        let sfx_options = self.self_extracting.then(|| self.sfx_options.clone());
+        let result_state = Arc::clone(&self.progress_state);
+        let report = move |result: Result<String, String>| {
+            if let Ok(mut state) = result_state.lock() {
+                state.compression_result = Some(result);
+            }
+        };
 
-            thread::spawn(move || {
-                if let Err(e) = compress_files_parallel(
-                    files,
-                    output_path,
-                    progress_tx,
-                    cancel_tx,
-                    stats,
-                    password,
-                ) {
-                    error!("Compression error: {}", e);
+        thread::spawn(move || {
+            let result = match tar {
+                Some(compression) => {
+                    compress_to_tar(files, output_path.clone(), compression, progress_tx, stats, reproducible)
                 }
-            });
+                None if seven_zip => {
+                    compress_to_seven_zip(files, output_path.clone(), password, progress_tx, stats, reproducible)
+                }
+                None => {
+                    let password = password.unwrap_or_default();
+                    compress_files_parallel(files, output_path.clone(), progress_tx, cancel_rx, stats, password, reproducible)
+                }
+            };
+            if let Err(e) = result {
+                error!("Compression error: {}", e);
+                report(Err(format!("Error compressing {}: {}", output_path.display(), e)));
+                return;
+            }
 
-            thread::spawn(move || {
-                while let Ok((progress, stats)) = progress_rx.recv() {
-                    if let Ok(mut state) = progress_state.lock() {
-                        state.compression_progress = Some((progress, stats));
+            if let Some(key) = signing_key {
+                match signing::sign_archive(&output_path, &key, signature_mode) {
+                    Ok(_) => info!("Signed archive with key {}", signing::format_key_id(&key.key_id)),
+                    Err(e) => {
+                        error!("Signing error: {}", e);
+                        report(Err(format!("Error signing {}: {}", output_path.display(), e)));
+                        return;
                     }
                 }
-            });
-        }
+            }
+
+            if let Some(options) = sfx_options {
+                let sfx_path = output_path.with_extension("run");
+                match create_self_extractor(&output_path, &sfx_path, &options) {
+                    Ok(()) => info!("Created self-extracting archive {:?}", sfx_path),
+                    Err(e) => {
+                        error!("Self-extracting archive error: {}", e);
+                        report(Err(format!("Error creating {}: {}", sfx_path.display(), e)));
+                        return;
+                    }
+                }
+            }
+
+            if let Some((volume_size, style)) = volumes {
+                match split_archive(&output_path, volume_size, style) {
+                    Ok(volumes) => {
+                        info!("Split archive into {} volumes", volumes.len());
+                        report(Ok(format!("Created {} in {} volumes", output_path.display(), volumes.len())));
+                    }
+                    Err(e) => {
+                        error!("Splitting error: {}", e);
+                        report(Err(format!("Error splitting {}: {}", output_path.display(), e)));
+                    }
+                }
+                return;
+            }
+            report(Ok(format!("Created {}", output_path.display())));
+        });
+
+        thread::spawn(move || {
+            while let Ok((progress, stats)) = progress_rx.recv() {
+                if let Ok(mut state) = progress_state.lock() {
+                    state.compression_progress = Some((progress, stats));
+                }
+            }
+        });
 
         Ok(())
     }
 
     pub fn open_archive_with_password(&mut self, path: &Path, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.start_volume_join(path, password.clone())? {
+            return Ok(());
+        }
         let file = File::open(path)?;
-        let mut archive = ZipArchive::new(file)?;
+        let mut archive = match ZipArchive::new(file) {
+            Ok(archive) => archive,
+            Err(_) if path.extension().is_some_and(|ext| ext == "cb7") => return self.open_seven_zip_comic(path, password),
+            Err(_) if is_seven_zip(path) => return self.start_seven_zip_unpack(path, password),
+            Err(e) if path.extension().is_some_and(|ext| ext == "cbr") => {
+                // Many .cbr files are really ZIPs; RAR itself cannot be read
+                return Err(format!("Only ZIP-packed .cbr comics can be read ({})", e).into());
+            }
+            Err(e) => return self.open_damaged_archive(path, e),
+        };
 
-        let needs_password = archive
-            .get_aes_verification_key_and_salt(0)
-            .unwrap()
-            .is_none()
-            == false;
+        let needs_password = archive_needs_password(&mut archive)?;
 
         if needs_password && password.is_none() {
             self.show_password_dialog = true;
@@ -171,30 +559,33 @@ impl ArchiveManager {
             return Ok(());
         }
 
+        let violations = check_archive(&mut archive, &self.safety_limits);
+        if !self.passes_safety_checks(SafetyAction::OpenArchive(path.to_path_buf(), password.clone()), violations) {
+            return Ok(());
+        }
+
         let mut files = Vec::new();
         if needs_password {
             let password = password.as_ref().unwrap().clone();
             for i in 0..archive.len() {
                 let file = archive.by_index_decrypt(i, password.as_bytes())?;
-                files.push(ArchiveFile {
-                    name: file.name().to_string(),
-                    is_directory: file.is_dir(),
-                    size: file.size(),
-                });
+                files.push(ArchiveFile::from_zip_file(&file));
             }
         } else {
             for i in 0..archive.len() {
                 let file = archive.by_index(i)?;
-                files.push(ArchiveFile {
-                    name: file.name().to_string(),
-                    is_directory: file.is_dir(),
-                    size: file.size(),
-                });
+                files.push(ArchiveFile::from_zip_file(&file));
             }
         }
 
-        self.current_archive = Some((path.to_path_buf(), files));
-        self.status_message = "Archive opened successfully".to_string();
+        self.signature_status = signing::verify_archive(path, &mut archive, &self.trusted_keys);
+        self.recovered_entries = None;
+        self.set_current_archive(path, files);
+        self.status_message = if self.opened_read_only() {
+            "Archive copied and opened read-only".to_string()
+        } else {
+            "Archive opened successfully".to_string()
+        };
         Ok(())
     }
 
@@ -231,226 +622,1977 @@ impl ArchiveManager {
                             Some(PasswordOperation::ExtractFile(file_name)) => {
                                 let _ = self.open_file_with_password(file_name, Some(password));
                             }
+                            Some(PasswordOperation::ExtractEntries(names, destination)) => {
+                                let _ = self.extract_entries_with_password(names, destination, Some(password));
+                            }
+                            Some(PasswordOperation::TestArchive) => {
+                                let _ = self.test_archive_with_password(Some(password));
+                            }
+                            Some(PasswordOperation::SearchContents) => {
+                                let _ = self.search_contents_with_password(Some(password));
+                            }
+                            Some(PasswordOperation::Preview(entry)) => {
+                                let _ = self.preview_entry(entry, PREVIEW_CHUNK, Some(password));
+                            }
+                            Some(PasswordOperation::OpenReader) => {
+                                let _ = self.open_comic_reader(ctx, Some(password));
+                            }
+                            Some(PasswordOperation::AddToArchive(paths)) => {
+                                if let Err(e) = self.add_files_to_archive(paths, Some(password)) {
+                                    self.status_message = format!("Error adding files: {}", e);
+                                }
+                            }
+                            Some(PasswordOperation::UpdateFromFolder(request)) => {
+                                if let Err(e) = self.update_from_folder(request, Some(password)) {
+                                    self.status_message = format!("Error updating archive: {}", e);
+                                }
+                            }
                             None => {}
                         }
                     }
                 });
             });
     }
-    pub fn open_file_with_password(&mut self, file_name: String, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some((archive_path, _)) = &self.current_archive {
-            let file = File::open(archive_path)?;
-            let mut archive = ZipArchive::new(file)?;
+    pub fn test_archive_with_password(&mut self, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(());
+        };
 
-            let needs_password = archive
-                .get_aes_verification_key_and_salt(0)
-                .unwrap()
-                .is_none()
-                == false;
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let needs_password = archive_needs_password(&mut archive)?;
 
-            if needs_password && password.is_none() {
-                self.show_password_dialog = true;
-                self.current_operation = Some(PasswordOperation::ExtractFile(file_name));
-                self.status_message = "File is encrypted. Please enter password.".to_string();
-                return Ok(());
-            }
+        if needs_password && password.is_none() {
+            self.show_password_dialog = true;
+            self.current_operation = Some(PasswordOperation::TestArchive);
+            self.status_message = "Archive is encrypted. Please enter password.".to_string();
+            return Ok(());
+        }
 
-            let temp_dir = get_temp_dir();
-            std::fs::create_dir_all(&temp_dir)?;
+        let archive_path = archive_path.clone();
+        let progress_state = Arc::clone(&self.progress_state);
+        self.test_report = None;
+        self.status_message = "Testing archive...".to_string();
 
-            let archive_path = archive_path.clone();
-            let progress_state = Arc::clone(&self.progress_state);
+        thread::spawn(move || {
+            let report = test_archive(&archive_path, password.as_deref(), |done, total| {
+                if let Ok(mut state) = progress_state.lock() {
+                    state.test_progress = Some(done as f32 / total.max(1) as f32);
+                }
+            });
 
-            thread::spawn(move || {
-                let file = File::open(&archive_path).unwrap();
-                let mut archive = ZipArchive::new(file).unwrap();
+            let report = report.unwrap_or_else(|e| {
+                error!("Archive test error: {}", e);
+                ArchiveTestReport {
+                    entries: Vec::new(),
+                    problems: vec![e.to_string()],
+                }
+            });
 
-                let mut zip_file = if needs_password {
-                    archive.by_name_decrypt(&file_name, password.unwrap().as_bytes()).unwrap()
-                } else {
-                    archive.by_name(&file_name).unwrap()
-                };
+            if let Ok(mut state) = progress_state.lock() {
+                state.test_progress = None;
+                state.test_report = Some(report);
+            }
+        });
+        Ok(())
+    }
 
-                let total_size = zip_file.size();
-                let temp_path = temp_dir.join(&file_name);
+    pub fn draw_test_report(&mut self, ctx: &egui::Context) {
+        let Some(report) = &self.test_report else {
+            return;
+        };
 
-                if let Some(parent) = temp_path.parent() {
-                    std::fs::create_dir_all(parent).unwrap();
+        let mut open = true;
+        Window::new("Archive Test Results")
+            .open(&mut open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} entries tested, {} failed",
+                    report.entries.len(),
+                    report.failed_count()
+                ));
+                for problem in &report.problems {
+                    ui.colored_label(egui::Color32::YELLOW, format!("⚠ {}", problem));
                 }
+                ui.separator();
+
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    egui::Grid::new("test_report_grid").striped(true).show(ui, |ui| {
+                        ui.strong("Result");
+                        ui.strong("Size");
+                        ui.strong("Packed");
+                        ui.strong("Name");
+                        ui.end_row();
+
+                        for entry in &report.entries {
+                            match &entry.error {
+                                None => ui.colored_label(egui::Color32::GREEN, "✔ OK"),
+                                Some(error) => ui
+                                    .colored_label(egui::Color32::RED, "✖ Failed")
+                                    .on_hover_text(error),
+                            };
+                            ui.label(crate::utils::get_formatted_size(entry.size));
+                            ui.label(crate::utils::get_formatted_size(entry.compressed_size));
+                            ui.label(&entry.name);
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
 
-                let mut temp_file = File::create(&temp_path).unwrap();
-                let mut buffer = [0; 8192];
-                let mut processed_size = 0;
-                let start_time = Instant::now();
+        if !open {
+            self.test_report = None;
+        }
+    }
 
-                while let Ok(n) = zip_file.read(&mut buffer) {
-                    if n == 0 {
-                        break;
-                    }
-                    temp_file.write_all(&buffer[..n]).unwrap();
-                    processed_size += n as u64;
+    pub fn search_contents_with_password(&mut self, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(());
+        };
+        if self.recovered_entries.is_some() {
+            self.status_message = "Save the repaired archive before searching its contents".to_string();
+            return Ok(());
+        }
 
-                    let elapsed = start_time.elapsed();
-                    let progress = processed_size as f32 / total_size as f32;
-                    let estimated_time = if progress > 0.0 {
-                        Duration::from_secs_f32(elapsed.as_secs_f32() / progress)
-                    } else {
-                        Duration::from_secs(0)
-                    };
+        let matcher = match self.search_options.build_matcher() {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                self.search_error = Some(e.to_string());
+                return Ok(());
+            }
+        };
+        self.search_error = None;
 
-                    if let Ok(mut state) = progress_state.lock() {
-                        state.extraction_progress = Some((
-                            progress,
-                            ExtractionStats {
-                                original_size: total_size,
-                                extracted_size: processed_size,
-                                start_time,
-                                estimated_time,
-                                current_file: file_name.clone(),
-                            },
-                        ));
-                    }
-                }
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let needs_password = archive_needs_password(&mut archive)?;
+
+        if needs_password && password.is_none() {
+            self.show_password_dialog = true;
+            self.current_operation = Some(PasswordOperation::SearchContents);
+            self.status_message = "Archive is encrypted. Please enter password.".to_string();
+            return Ok(());
+        }
 
-                open_system_file(&temp_path).unwrap();
+        let archive_path = archive_path.clone();
+        self.cancel_content_search();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.search_cancel = Some(Arc::clone(&cancel));
+        self.search_hits.clear();
+
+        let skip_binary = self.search_options.skip_binary;
+        let progress_state = Arc::clone(&self.progress_state);
+        let generation = match progress_state.lock() {
+            Ok(mut state) => {
+                state.search_generation += 1;
+                state.search_hits.clear();
+                state.search_progress = Some(0.0);
+                state.search_generation
+            }
+            Err(_) => return Ok(()),
+        };
 
+        thread::spawn(move || {
+            let result = search_contents(&archive_path, password.as_deref(), &matcher, skip_binary, &cancel, |done, total, hits| {
+                // A cancelled search must not leak results into a newer one
                 if let Ok(mut state) = progress_state.lock() {
-                    state.extraction_progress = None;
+                    if state.search_generation == generation && !cancel.load(Ordering::Relaxed) {
+                        state.search_progress = Some(done as f32 / total.max(1) as f32);
+                        state.search_hits.extend(hits);
+                    }
                 }
             });
-        }
+            if let Err(e) = result {
+                error!("Content search error: {}", e);
+            }
+            if let Ok(mut state) = progress_state.lock() {
+                if state.search_generation == generation && !cancel.load(Ordering::Relaxed) {
+                    state.search_progress = None;
+                }
+            }
+        });
         Ok(())
     }
 
-    pub fn compress_files(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.show_password_dialog = true;
-        self.current_operation = Some(PasswordOperation::Compress);
-        Ok(())
+    pub fn cancel_content_search(&mut self) {
+        if let Some(cancel) = self.search_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+            if let Ok(mut state) = self.progress_state.lock() {
+                state.search_generation += 1;
+                state.search_progress = None;
+                state.search_hits.clear();
+            }
+        }
     }
-    pub fn open_file(&mut self, file_name: String) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some((archive_path, _)) = &self.current_archive {
-            let file = File::open(archive_path)?;
-            let mut archive = ZipArchive::new(file)?;
 
-            let needs_password = archive
-                .get_aes_verification_key_and_salt(0)
-                .unwrap()
-                .is_none()
-                == false;
+    pub fn draw_content_search(&mut self, ctx: &egui::Context) {
+        if !self.show_content_search {
+            return;
+        }
 
-            if needs_password {
-                self.show_password_dialog = true;
-                self.current_operation = Some(PasswordOperation::ExtractFile(file_name));
-                self.status_message = "File is encrypted. Please enter password.".to_string();
-                return Ok(());
+        let progress = match self.progress_state.lock() {
+            Ok(mut state) => {
+                self.search_hits.append(&mut state.search_hits);
+                state.search_progress
             }
+            Err(_) => None,
+        };
+        if progress.is_none() {
+            self.search_cancel = None;
+        }
 
-            let temp_dir = get_temp_dir();
-            std::fs::create_dir_all(&temp_dir)?;
-
-            let archive_path = archive_path.clone();
-            let progress_state = Arc::clone(&self.progress_state);
-
-            thread::spawn(move || {
-                let file = File::open(&archive_path).unwrap();
-                let mut archive = ZipArchive::new(file).unwrap();
-                let mut zip_file = archive.by_name(&file_name).unwrap();
-                let total_size = zip_file.size();
-                let temp_path = temp_dir.join(&file_name);
-
-                if let Some(parent) = temp_path.parent() {
-                    std::fs::create_dir_all(parent).unwrap();
-                }
-
-                let mut temp_file = File::create(&temp_path).unwrap();
-                let mut buffer = [0; 8192];
-                let mut processed_size = 0;
-                let start_time = Instant::now();
-
-                while let Ok(n) = zip_file.read(&mut buffer) {
-                    if n == 0 {
-                        break;
+        let mut open = true;
+        let mut start = false;
+        let mut entry_to_open = None;
+        Window::new("Search in Contents")
+            .open(&mut open)
+            .default_width(600.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.search_options.pattern)
+                            .hint_text("Text or pattern to find")
+                            .desired_width(300.0),
+                    );
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        start = true;
                     }
-                    temp_file.write_all(&buffer[..n]).unwrap();
-                    processed_size += n as u64;
-
-                    let elapsed = start_time.elapsed();
-                    let progress = processed_size as f32 / total_size as f32;
-                    let estimated_time = if progress > 0.0 {
-                        Duration::from_secs_f32(elapsed.as_secs_f32() / progress)
-                    } else {
-                        Duration::from_secs(0)
-                    };
-
-                    if let Ok(mut state) = progress_state.lock() {
-                        state.extraction_progress = Some((
-                            progress,
-                            ExtractionStats {
-                                original_size: total_size,
-                                extracted_size: processed_size,
-                                start_time,
-                                estimated_time,
-                                current_file: file_name.clone(),
-                            },
-                        ));
+                    if progress.is_some() {
+                        if ui.button("Cancel").clicked() {
+                            self.cancel_content_search();
+                        }
+                    } else if ui.button("Search").clicked() {
+                        start = true;
                     }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.search_options.use_regex, "Regular expression");
+                    ui.checkbox(&mut self.search_options.case_insensitive, "Ignore case");
+                    ui.checkbox(&mut self.search_options.skip_binary, "Skip binary files");
+                });
+                if let Some(error) = &self.search_error {
+                    ui.colored_label(egui::Color32::RED, format!("Invalid pattern: {}", error));
                 }
 
-                open_system_file(&temp_path).unwrap();
-
-                if let Ok(mut state) = progress_state.lock() {
-                    state.extraction_progress = None;
+                if let Some(progress) = progress {
+                    ui.add(
+                        egui::ProgressBar::new(progress)
+                            .text(format!("Searching... {:.1}%", progress * 100.0))
+                            .animate(true),
+                    );
                 }
+                ui.label(format!("{} matching lines", self.search_hits.len()));
+                ui.separator();
+
+                let row_height = ui.text_style_height(&egui::TextStyle::Body) + 4.0;
+                egui::ScrollArea::vertical()
+                    .max_height(350.0)
+                    .auto_shrink([false, true])
+                    .show_rows(ui, row_height, self.search_hits.len(), |ui, range| {
+                        for hit in &self.search_hits[range] {
+                            ui.horizontal(|ui| {
+                                let response = ui
+                                    .add(
+                                        egui::Label::new(
+                                            egui::RichText::new(format!("{}:{}", hit.entry, hit.line_number)).strong(),
+                                        )
+                                        .sense(egui::Sense::click()),
+                                    )
+                                    .on_hover_text("Double-click to open");
+                                ui.monospace(&hit.snippet);
+                                if response.double_clicked() {
+                                    entry_to_open = Some(hit.entry.clone());
+                                }
+                            });
+                        }
+                    });
             });
+
+        if start && !self.search_options.pattern.is_empty() {
+            if let Err(e) = self.search_contents_with_password(None) {
+                self.status_message = format!("Error searching archive: {}", e);
+            }
+        }
+        if let Some(entry) = entry_to_open {
+            let _ = self.open_file(entry);
+        }
+        if !open {
+            self.cancel_content_search();
+            self.show_content_search = false;
         }
-        Ok(())
     }
 
-    pub fn open_archive(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-        let file = File::open(path)?;
-        let mut archive = ZipArchive::new(file)?;
+    /// Decompresses the first `limit` bytes of an entry into the preview pane
+    /// on a worker thread. A larger limit for the entry already shown resumes
+    /// its decompressor.
+    pub fn preview_entry(&mut self, entry: String, limit: u64, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(loader) = self.preview_loader.as_ref().filter(|loader| loader.entry == entry) {
+            if self.preview.as_ref().is_some_and(|preview| preview.entry == entry && preview.loaded < limit) {
+                loader.load_more(limit);
+                self.preview_loading = true;
+            }
+            return Ok(());
+        }
+        if self.recovered_entries.is_some() {
+            self.preview_error = Some("Save the repaired archive to preview entries".to_string());
+            return Ok(());
+        }
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(());
+        };
 
-        let needs_password = archive
-            .get_aes_verification_key_and_salt(0)
-            .unwrap()
-            .is_none()
-            == false;
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let needs_password = archive_needs_password(&mut archive)?;
 
-        if needs_password {
+        let password = password.or_else(|| self.preview_password.clone());
+        if needs_password && password.is_none() {
             self.show_password_dialog = true;
-            self.current_operation = Some(PasswordOperation::OpenArchive(path.to_path_buf()));
+            self.current_operation = Some(PasswordOperation::Preview(entry));
             self.status_message = "Archive is encrypted. Please enter password.".to_string();
             return Ok(());
         }
 
-        let mut files = Vec::new();
-        for i in 0..archive.len() {
-            let file = archive.by_index(i)?;
-            files.push(ArchiveFile {
-                name: file.name().to_string(),
-                is_directory: file.is_dir(),
-                size: file.size(),
-            });
+        let progress_state = Arc::clone(&self.progress_state);
+        let generation = match progress_state.lock() {
+            Ok(mut state) => {
+                state.preview_generation += 1;
+                state.preview_result = None;
+                state.preview_generation
+            }
+            Err(_) => return Ok(()),
+        };
+        let archive_path = archive_path.clone();
+        self.preview_password = password.clone();
+        self.preview_loading = true;
+        self.preview_loader = Some(PreviewLoader::start(archive_path, entry.clone(), password, limit, move |result| {
+            if let Ok(mut state) = progress_state.lock() {
+                if state.preview_generation == generation {
+                    state.preview_result = Some(result.map_err(|e| format!("Cannot preview {}: {}", entry, e)));
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// Stops the preview worker and clears the pane.
+    pub fn close_preview(&mut self) {
+        self.preview_loader = None;
+        self.preview_loading = false;
+        self.preview = None;
+        self.preview_error = None;
+        if let Ok(mut state) = self.progress_state.lock() {
+            state.preview_generation += 1;
+            state.preview_result = None;
         }
+    }
 
-        self.current_archive = Some((path.to_path_buf(), files));
-        self.status_message = "Archive opened successfully".to_string();
+    /// Opens a .cb7 comic that is really 7z-packed. Only the reader can show
+    /// it; the archive itself stays closed.
+    fn open_seven_zip_comic(&mut self, path: &Path, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let pages = match seven_zip_pages(path, password.as_deref()) {
+            Ok(pages) => pages,
+            Err(sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::MaybeBadPassword(_)) if password.is_none() => {
+                self.show_password_dialog = true;
+                self.current_operation = Some(PasswordOperation::OpenArchive(path.to_path_buf()));
+                self.status_message = "Archive is encrypted. Please enter password.".to_string();
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if pages.is_empty() {
+            self.status_message = "Archive contains no readable images".to_string();
+            return Ok(());
+        }
+        self.status_message = format!("Reading {} pages", pages.len());
+        self.pending_reader = Some((path.to_path_buf(), password, pages));
         Ok(())
     }
 
-    pub fn cleanup_removed_files(&mut self) {
-        self.files_to_remove.sort_unstable_by(|a, b| b.cmp(a));
-        for &index in &self.files_to_remove {
-            if index < self.selected_files.len() {
-                self.selected_files.remove(index);
-            }
+    /// Starts a reader queued by `open_seven_zip_comic`, which has no context.
+    pub fn start_pending_reader(&mut self, ctx: &egui::Context) {
+        if let Some((path, password, pages)) = self.pending_reader.take() {
+            self.comic_reader = Some(ComicReader::new(ctx, path, password, pages));
         }
-        self.files_to_remove.clear();
     }
 
-    pub fn handle_file_drop(
-        &mut self,
-        path: &Path,
+    /// Switches to the comic-book reader for the image entries of the current archive.
+    pub fn open_comic_reader(&mut self, ctx: &egui::Context, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.recovered_entries.is_some() {
+            self.status_message = "Save the repaired archive before reading it".to_string();
+            return Ok(());
+        }
+        let Some((archive_path, files)) = &self.current_archive else {
+            return Ok(());
+        };
+
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let needs_password = archive_needs_password(&mut archive)?;
+
+        if needs_password && password.is_none() {
+            self.show_password_dialog = true;
+            self.current_operation = Some(PasswordOperation::OpenReader);
+            self.status_message = "Archive is encrypted. Please enter password.".to_string();
+            return Ok(());
+        }
+
+        let pages = comic_pages(files);
+        if pages.is_empty() {
+            self.status_message = "Archive contains no readable images".to_string();
+            return Ok(());
+        }
+        self.status_message = format!("Reading {} pages", pages.len());
+        self.comic_reader = Some(ComicReader::new(ctx, archive_path.clone(), password, pages));
+        Ok(())
+    }
+
+    pub fn draw_preview_panel(&mut self, ctx: &egui::Context) {
+        let result = self.progress_state.lock().ok().and_then(|mut state| state.preview_result.take());
+        match result {
+            Some(Ok(preview)) => {
+                self.preview = Some(preview);
+                self.preview_error = None;
+                self.preview_loading = false;
+            }
+            Some(Err(e)) => {
+                warn!("{}", e);
+                self.preview = None;
+                self.preview_error = Some(e);
+                self.preview_loader = None;
+                self.preview_password = None;
+                self.preview_loading = false;
+            }
+            None => {}
+        }
+
+        let image_uri = match &self.preview {
+            Some(Preview { content: PreviewContent::Image { uri, .. }, .. }) => Some(uri.clone()),
+            _ => None,
+        };
+        if self.preview_image_uri != image_uri {
+            if let Some(uri) = self.preview_image_uri.take() {
+                ctx.forget_image(&uri);
+            }
+            self.preview_image_uri = image_uri;
+        }
+
+        if !self.show_preview || (self.preview.is_none() && self.preview_error.is_none() && !self.preview_loading) {
+            return;
+        }
+
+        let mut close = false;
+        let mut load_more = None;
+        egui::SidePanel::right("preview_panel")
+            .resizable(true)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Preview");
+                    if ui.small_button("✖").clicked() {
+                        close = true;
+                    }
+                });
+                if let Some(error) = &self.preview_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if self.preview_loading {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.weak("Decompressing…");
+                    });
+                }
+                let Some(preview) = &self.preview else {
+                    return;
+                };
+
+                ui.label(egui::RichText::new(&preview.entry).strong());
+                let kind = match &preview.content {
+                    PreviewContent::Text { encoding, .. } => format!("Text, {}", encoding),
+                    PreviewContent::Image { .. } => "Image".to_string(),
+                    PreviewContent::Hex(_) => "Binary".to_string(),
+                };
+                ui.weak(format!("{} · {}", kind, crate::utils::get_formatted_size(preview.size)));
+                if preview.is_truncated() {
+                    ui.horizontal(|ui| {
+                        ui.weak(format!("Showing first {}", crate::utils::get_formatted_size(preview.loaded)));
+                        if ui.add_enabled(!self.preview_loading, egui::Button::new("Load more").small()).clicked() {
+                            load_more = Some((preview.entry.clone(), preview.loaded + PREVIEW_CHUNK));
+                        }
+                    });
+                }
+                ui.separator();
+
+                match &preview.content {
+                    PreviewContent::Text { text, .. } => {
+                        egui::ScrollArea::both().auto_shrink([false, false]).show(ui, |ui| {
+                            let theme = egui_extras::syntax_highlighting::CodeTheme::from_style(ui.style());
+                            egui_extras::syntax_highlighting::code_view_ui(ui, &theme, text, &preview.language);
+                        });
+                    }
+                    PreviewContent::Image { uri, bytes } => {
+                        egui::ScrollArea::both().auto_shrink([false, false]).show(ui, |ui| {
+                            ui.add(
+                                egui::Image::from_bytes(uri.clone(), egui::load::Bytes::Shared(Arc::clone(bytes)))
+                                    .max_width(ui.available_width())
+                                    .shrink_to_fit(),
+                            );
+                        });
+                    }
+                    PreviewContent::Hex(data) => {
+                        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                        egui::ScrollArea::both()
+                            .auto_shrink([false, false])
+                            .show_rows(ui, row_height, data.len().div_ceil(16), |ui, range| {
+                                for row in range {
+                                    ui.monospace(crate::preview::hex_line(data, row * 16));
+                                }
+                            });
+                    }
+                }
+            });
+
+        if close {
+            self.close_preview();
+        }
+        if let Some((entry, limit)) = load_more {
+            if let Err(e) = self.preview_entry(entry, limit, None) {
+                self.preview_error = Some(format!("Error: {}", e));
+            }
+        }
+    }
+
+    fn save_trusted_keys(&mut self) {
+        if let Err(e) = signing::save_trusted_keys(&self.trusted_keys) {
+            self.status_message = format!("Error saving trusted keys: {}", e);
+        }
+    }
+
+    pub fn draw_signing_settings(&mut self, ui: &mut egui::Ui) {
+        ui.label("Archive signing");
+        ui.checkbox(&mut self.sign_archives, "Sign new archives");
+
+        ui.horizontal(|ui| {
+            ui.label("Secret key:");
+            match &self.signing_key_path {
+                Some(path) => ui.label(path.display().to_string()),
+                None => ui.label("none"),
+            };
+            if ui.button("Choose…").clicked() {
+                if let Some(path) = rfd::FileDialog::new().add_filter("Minisign key", &["key"]).pick_file() {
+                    self.signing_key_path = Some(path);
+                }
+            }
+            if ui.button("Generate key pair…").clicked() {
+                if let Some(path) = rfd::FileDialog::new().set_file_name("minisign.key").save_file() {
+                    match signing::generate_key_pair(&path) {
+                        Ok(public_key) => {
+                            self.status_message = format!(
+                                "Generated key pair {}",
+                                signing::format_key_id(&public_key.key_id)
+                            );
+                            self.trusted_keys.push(public_key);
+                            self.save_trusted_keys();
+                            self.signing_key_path = Some(path);
+                        }
+                        Err(e) => self.status_message = format!("Error generating key pair: {}", e),
+                    }
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.signature_mode, SignatureMode::Embedded, "Embedded (archive comment)");
+            ui.radio_value(&mut self.signature_mode, SignatureMode::Detached, "Detached (.minisig)");
+        });
+
+        ui.label("Trusted public keys:");
+        let mut remove_key = None;
+        for (idx, key) in self.trusted_keys.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.button("❌").clicked() {
+                    remove_key = Some(idx);
+                }
+                ui.monospace(signing::format_key_id(&key.key_id));
+            });
+        }
+        if let Some(idx) = remove_key {
+            self.trusted_keys.remove(idx);
+            self.save_trusted_keys();
+        }
+        if ui.button("Add trusted key…").clicked() {
+            if let Some(path) = rfd::FileDialog::new().add_filter("Minisign public key", &["pub"]).pick_file() {
+                match std::fs::read_to_string(&path).map_err(|e| e.into()).and_then(|text| signing::parse_public_key(&text)) {
+                    Ok(key) => {
+                        self.trusted_keys.push(key);
+                        self.save_trusted_keys();
+                    }
+                    Err(e) => self.status_message = format!("Error loading public key: {}", e),
+                }
+            }
+        }
+    }
+
+    pub fn open_file_with_password(&mut self, file_name: String, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.recovered_entries.is_some() {
+            self.status_message = "Save the repaired archive before opening entries".to_string();
+            return Ok(());
+        }
+        if let Some((archive_path, _)) = &self.current_archive {
+            let file = File::open(archive_path)?;
+            let mut archive = ZipArchive::new(file)?;
+
+            let needs_password = archive_needs_password(&mut archive)?;
+
+            if needs_password && password.is_none() {
+                self.show_password_dialog = true;
+                self.current_operation = Some(PasswordOperation::ExtractFile(file_name));
+                self.status_message = "File is encrypted. Please enter password.".to_string();
+                return Ok(());
+            }
+
+            let temp_dir = self.extraction_dir();
+            std::fs::create_dir_all(&temp_dir)?;
+
+            let violations = match archive.index_for_name(&file_name) {
+                Some(index) => {
+                    let entry = archive.by_index_raw(index)?;
+                    check_extraction(&file_name, entry.size(), entry.compressed_size(), &temp_dir, &self.safety_limits)
+                }
+                None => Vec::new(),
+            };
+            let archive_path = archive_path.clone();
+            if !self.passes_safety_checks(SafetyAction::ExtractFile(file_name.clone(), password.clone()), violations) {
+                return Ok(());
+            }
+
+            let progress_state = Arc::clone(&self.progress_state);
+
+            thread::spawn(move || {
+                let start_time = Instant::now();
+                let result = extract_for_editing(&archive_path, &file_name, password.as_deref(), &temp_dir, |processed_size, total_size| {
+                    let progress = processed_size as f32 / total_size as f32;
+                    let estimated_time = if progress > 0.0 {
+                        Duration::from_secs_f32(start_time.elapsed().as_secs_f32() / progress)
+                    } else {
+                        Duration::from_secs(0)
+                    };
+                    if let Ok(mut state) = progress_state.lock() {
+                        state.extraction_progress = Some((
+                            progress,
+                            ExtractionStats {
+                                original_size: total_size,
+                                extracted_size: processed_size,
+                                start_time,
+                                estimated_time,
+                                current_file: file_name.clone(),
+                            },
+                        ));
+                    }
+                });
+
+                let error = match result {
+                    Ok(temp_path) => {
+                        let watched = WatchedEntry::new(file_name.clone(), temp_path.clone(), password);
+                        if let Ok(mut state) = progress_state.lock() {
+                            state.opened_entries.push(watched);
+                        }
+                        open_system_file(&temp_path).err().map(|e| format!("Error opening {}: {}", temp_path.display(), e))
+                    }
+                    Err(e) => {
+                        error!("Extracting {} failed: {}", file_name, e);
+                        Some(format!("Error extracting {}: {}", file_name, e))
+                    }
+                };
+                if let Ok(mut state) = progress_state.lock() {
+                    state.extraction_progress = None;
+                    if error.is_some() {
+                        state.open_entry_error = error;
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub fn compress_files(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.show_password_dialog = true;
+        self.current_operation = Some(PasswordOperation::Compress);
+        Ok(())
+    }
+    pub fn open_file(&mut self, file_name: String) -> Result<(), Box<dyn std::error::Error>> {
+        self.open_file_with_password(file_name, None)
+    }
+
+    pub fn open_archive(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if self.start_volume_join(path, None)? {
+            return Ok(());
+        }
+        let file = File::open(path)?;
+        let mut archive = match ZipArchive::new(file) {
+            Ok(archive) => archive,
+            Err(_) if path.extension().is_some_and(|ext| ext == "cb7") => return self.open_seven_zip_comic(path, None),
+            Err(_) if is_seven_zip(path) => return self.start_seven_zip_unpack(path, None),
+            Err(e) if path.extension().is_some_and(|ext| ext == "cbr") => {
+                // Many .cbr files are really ZIPs; RAR itself cannot be read
+                return Err(format!("Only ZIP-packed .cbr comics can be read ({})", e).into());
+            }
+            Err(e) => return self.open_damaged_archive(path, e),
+        };
+
+        let needs_password = archive_needs_password(&mut archive)?;
+
+        if needs_password {
+            self.show_password_dialog = true;
+            self.current_operation = Some(PasswordOperation::OpenArchive(path.to_path_buf()));
+            self.status_message = "Archive is encrypted. Please enter password.".to_string();
+            return Ok(());
+        }
+
+        let violations = check_archive(&mut archive, &self.safety_limits);
+        if !self.passes_safety_checks(SafetyAction::OpenArchive(path.to_path_buf(), None), violations) {
+            return Ok(());
+        }
+
+        let mut files = Vec::new();
+        for i in 0..archive.len() {
+            let file = archive.by_index(i)?;
+            files.push(ArchiveFile::from_zip_file(&file));
+        }
+
+        self.signature_status = signing::verify_archive(path, &mut archive, &self.trusted_keys);
+        self.recovered_entries = None;
+        self.set_current_archive(path, files);
+        self.status_message = if self.opened_read_only() {
+            "Archive copied and opened read-only".to_string()
+        } else {
+            "Archive opened successfully".to_string()
+        };
+        Ok(())
+    }
+
+    /// Claims the worker that makes readable copies, reporting `label` as its
+    /// progress. Returns false, with a message, if it is busy.
+    fn claim_copy_worker(&mut self, label: &'static str) -> bool {
+        match self.progress_state.lock() {
+            Ok(mut state) if state.copy_progress.is_none() && state.readable_copy.is_none() => {
+                state.copy_progress = Some((label, 0.0));
+                true
+            }
+            _ => {
+                self.status_message = "Error: another archive is still being copied".to_string();
+                false
+            }
+        }
+    }
+
+    /// Starts joining the volumes of a split archive into the temp folder on a
+    /// worker thread; `finish_readable_copy` opens the result. Returns false if
+    /// `path` is not part of a volume set.
+    fn start_volume_join(&mut self, path: &Path, password: Option<String>) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(set) = find_volume_set(path)? else {
+            return Ok(false);
+        };
+        if !self.claim_copy_worker("Joining volumes") {
+            return Ok(true);
+        }
+        let progress_state = Arc::clone(&self.progress_state);
+        let temp_dir = get_temp_dir();
+        std::fs::create_dir_all(&temp_dir)?;
+        let joined = temp_dir.join(&set.name);
+        let source = path.to_path_buf();
+        info!("Joining {} volumes into {:?}", set.volumes.len(), joined);
+        self.status_message = format!("Joining {} volumes...", set.volumes.len());
+
+        thread::spawn(move || {
+            let result = join_volumes(&set, &joined, |progress| {
+                if let Ok(mut state) = progress_state.lock() {
+                    state.copy_progress = Some(("Joining volumes", progress));
+                }
+            });
+            let result = match result {
+                Ok(()) => Ok(joined),
+                Err(e) => {
+                    error!("Joining volumes failed: {}", e);
+                    Err(e.to_string())
+                }
+            };
+            if let Ok(mut state) = progress_state.lock() {
+                state.copy_progress = None;
+                let archive = source.clone();
+                state.readable_copy = Some(ReadableCopy { source, archive, password, needs_password: false, result });
+            }
+        });
+        Ok(true)
+    }
+
+    /// Starts unpacking the 7z archive at `path` into a ZIP in the temp folder
+    /// on a worker thread; `finish_readable_copy` opens the result.
+    fn start_seven_zip_unpack(&mut self, path: &Path, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.claim_copy_worker("Unpacking 7z archive") {
+            return Ok(());
+        }
+        let progress_state = Arc::clone(&self.progress_state);
+        let temp_dir = get_temp_dir();
+        std::fs::create_dir_all(&temp_dir)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let copy = temp_dir.join(format!("{}.zip", name));
+        // A 7z joined from volumes is already a copy; report the volume the user opened
+        let source = match &self.read_only_copy {
+            Some((joined, source)) if joined == path => source.clone(),
+            _ => path.to_path_buf(),
+        };
+        let archive = path.to_path_buf();
+        let limits = self.safety_limits.clone();
+        info!("Unpacking {:?} into {:?}", archive, copy);
+        self.status_message = format!("Unpacking {}...", source.display());
+
+        thread::spawn(move || {
+            let result = unpack_to_zip(&archive, password.as_deref(), &copy, &limits, |progress| {
+                if let Ok(mut state) = progress_state.lock() {
+                    state.copy_progress = Some(("Unpacking 7z archive", progress));
+                }
+            });
+            let needs_password = password.is_none()
+                && matches!(result, Err(sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::MaybeBadPassword(_)));
+            let result = match result {
+                Ok(()) => Ok(copy),
+                Err(e) => {
+                    error!("Unpacking {:?} failed: {}", archive, e);
+                    let _ = std::fs::remove_file(&copy);
+                    Err(e.to_string())
+                }
+            };
+            if let Ok(mut state) = progress_state.lock() {
+                state.copy_progress = None;
+                state.readable_copy = Some(ReadableCopy { source, archive, password, needs_password, result });
+            }
+        });
+        Ok(())
+    }
+
+    /// Opens a copy made by `start_volume_join` or `start_seven_zip_unpack`,
+    /// or asks for the password it needs.
+    pub fn finish_readable_copy(&mut self, copy: ReadableCopy) {
+        let path = match copy.result {
+            Ok(path) => path,
+            Err(_) if copy.needs_password => {
+                self.show_password_dialog = true;
+                self.current_operation = Some(PasswordOperation::OpenArchive(copy.archive));
+                self.status_message = "Archive is encrypted. Please enter password.".to_string();
+                return;
+            }
+            Err(e) => {
+                self.status_message = format!("Error opening {}: {}", copy.source.display(), e);
+                return;
+            }
+        };
+        self.read_only_copy = Some((path.clone(), copy.source));
+        let opened = match copy.password {
+            Some(password) => self.open_archive_with_password(&path, Some(password)),
+            None => self.open_archive(&path),
+        };
+        if let Err(e) = opened {
+            self.status_message = format!("Error opening archive: {}", e);
+        }
+    }
+
+    /// True if the open archive is a copy made from a split or 7z archive.
+    pub fn opened_read_only(&self) -> bool {
+        match (&self.read_only_copy, &self.current_archive) {
+            (Some((copy, _)), Some((path, _))) => copy == path,
+            _ => false,
+        }
+    }
+
+    /// Falls back to scanning local file headers when the central directory
+    /// cannot be read, listing whatever entries are still intact.
+    fn open_damaged_archive(&mut self, path: &Path, error: zip::result::ZipError) -> Result<(), Box<dyn std::error::Error>> {
+        warn!("Central directory unreadable ({}), scanning for local headers", error);
+        let entries = scan_local_headers(path)?;
+        if entries.is_empty() {
+            return Err(format!("{} (no recoverable entries found)", error).into());
+        }
+
+        let files: Vec<ArchiveFile> = entries
+            .iter()
+            .filter(|entry| entry.complete)
+            .map(RecoveredEntry::to_archive_file)
+            .collect();
+        let incomplete = entries.len() - files.len();
+
+        self.status_message = format!(
+            "Archive is damaged: recovered {} entries ({} incomplete). Save a repaired copy to open them.",
+            files.len(),
+            incomplete
+        );
+        self.signature_status = SignatureStatus::Unsigned;
+        self.set_current_archive(path, files);
+        self.recovered_entries = Some(entries);
+        Ok(())
+    }
+
+    pub fn save_repaired_archive(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (Some((source, _)), Some(entries)) = (&self.current_archive, &self.recovered_entries) else {
+            return Ok(());
+        };
+
+        let default_name = format!(
+            "{}_repaired.zip",
+            source.file_stem().unwrap_or_default().to_string_lossy()
+        );
+        let Some(output) = rfd::FileDialog::new()
+            .add_filter("ZIP", &["zip"])
+            .set_file_name(default_name)
+            .save_file()
+        else {
+            return Ok(());
+        };
+
+        let written = write_repaired_archive(source, entries, &output)?;
+        info!("Wrote repaired archive with {} entries to {:?}", written, output);
+        self.open_archive(&output)?;
+        self.status_message = format!("Repaired archive saved with {} entries", written);
+        Ok(())
+    }
+
+    fn set_current_archive(&mut self, path: &Path, files: Vec<ArchiveFile>) {
+        // Reopening after a rewrite keeps watching; another archive does not
+        if self.current_archive.as_ref().is_none_or(|(current, _)| current != path) {
+            self.watched_entries.clear();
+            self.entry_update_prompt = None;
+        }
+        self.is_comic = looks_like_comic(path, &files);
+        let files = Arc::new(files);
+        self.contents_index = Some(ContentsIndex::new(Arc::clone(&files), self.sort_column, self.sort_ascending));
+        self.current_archive = Some((path.to_path_buf(), files));
+        self.selected_entries.clear();
+        self.cancel_content_search();
+        self.search_hits.clear();
+        self.close_preview();
+        self.preview_password = None;
+        self.comic_reader = None;
+        self.pending_changes = PendingChanges::default();
+        self.pending_view.clear();
+        self.entry_edit = None;
+        self.apply_filter();
+    }
+
+    /// Compiles the search pattern, recording why it is invalid if it is.
+    pub fn compile_filter(&mut self) -> Option<EntryFilter> {
+        if self.filter_text.is_empty() {
+            self.filter_error = None;
+            return None;
+        }
+        match EntryFilter::new(self.filter_mode, &self.filter_text) {
+            Ok(filter) => {
+                self.filter_error = None;
+                Some(filter)
+            }
+            Err(e) => {
+                self.filter_error = Some(e);
+                None
+            }
+        }
+    }
+
+    fn apply_filter(&mut self) {
+        let filter = self.compile_filter();
+        if let Some(index) = &mut self.contents_index {
+            index.set_filter(filter);
+        }
+    }
+
+    pub fn toggle_selected(&mut self, file: usize) {
+        if !self.selected_entries.remove(&file) {
+            self.selected_entries.insert(file);
+        }
+    }
+
+    /// Asks for a destination folder and extracts every selected entry into it.
+    pub fn extract_selected(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((_, files)) = &self.current_archive else {
+            return Ok(());
+        };
+        let mut selected: Vec<usize> = self.selected_entries.iter().copied().collect();
+        selected.sort_unstable();
+        let names: Vec<String> = selected.into_iter().map(|index| files[index].name.clone()).collect();
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        match rfd::FileDialog::new().pick_folder() {
+            Some(destination) => self.extract_entries_with_password(names, destination, None),
+            None => Ok(()),
+        }
+    }
+
+    pub fn extract_entries_with_password(
+        &mut self,
+        names: Vec<String>,
+        destination: PathBuf,
+        password: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.recovered_entries.is_some() {
+            self.status_message = "Save the repaired archive before extracting entries".to_string();
+            return Ok(());
+        }
+        let Some((archive_path, files)) = &self.current_archive else {
+            return Ok(());
+        };
+
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let needs_password = archive_needs_password(&mut archive)?;
+
+        if needs_password && password.is_none() {
+            self.show_password_dialog = true;
+            self.current_operation = Some(PasswordOperation::ExtractEntries(names, destination));
+            self.status_message = "Archive is encrypted. Please enter password.".to_string();
+            return Ok(());
+        }
+
+        let wanted: HashSet<&str> = names.iter().map(String::as_str).collect();
+        let total_size: u64 = files.iter().filter(|file| wanted.contains(file.name.as_str())).map(|file| file.size).sum();
+        let mut violations = Vec::new();
+        if total_size > self.safety_limits.max_total_size {
+            violations.push(SafetyViolation::TotalSizeExceeded {
+                size: total_size,
+                limit: self.safety_limits.max_total_size,
+            });
+        }
+        violations.extend(check_output_space(total_size, &destination, &self.safety_limits));
+
+        let archive_path = archive_path.clone();
+        let action = SafetyAction::ExtractEntries(names.clone(), destination.clone(), password.clone());
+        if !self.passes_safety_checks(action, violations) {
+            return Ok(());
+        }
+
+        let progress_state = Arc::clone(&self.progress_state);
+        let policy = self.restore_policy;
+        let xattrs = self.restore_xattrs;
+        self.status_message = format!("Extracting {} entries to {}", names.len(), destination.display());
+
+        thread::spawn(move || {
+            let start_time = Instant::now();
+            let result = extract_entries(&archive_path, &names, &destination, password.as_deref(), policy, xattrs, |written, total, current| {
+                let progress = written as f32 / total.max(1) as f32;
+                let estimated_time = if progress > 0.0 {
+                    Duration::from_secs_f32(start_time.elapsed().as_secs_f32() / progress)
+                } else {
+                    Duration::from_secs(0)
+                };
+                if let Ok(mut state) = progress_state.lock() {
+                    state.extraction_progress = Some((
+                        progress,
+                        ExtractionStats {
+                            original_size: total,
+                            extracted_size: written,
+                            start_time,
+                            estimated_time,
+                            current_file: current.to_string(),
+                        },
+                    ));
+                }
+            });
+
+            match result {
+                Ok(count) => info!("Extracted {} entries to {:?}", count, destination),
+                Err(e) => error!("Extraction error: {}", e),
+            }
+            if let Ok(mut state) = progress_state.lock() {
+                state.extraction_progress = None;
+            }
+        });
+        Ok(())
+    }
+
+    /// Sorts the table by `column`, toggling the direction if it is already active.
+    pub fn sort_by(&mut self, column: SortColumn) {
+        if self.sort_column == column {
+            self.sort_ascending = !self.sort_ascending;
+        } else {
+            self.sort_column = column;
+            self.sort_ascending = true;
+        }
+        if let Some(index) = &mut self.contents_index {
+            index.sort(self.sort_column, self.sort_ascending);
+        }
+    }
+
+    /// Returns the password to rewrite the open archive with (`Some(None)` if it
+    /// is not encrypted), or `None` if the password dialog was opened for `operation`.
+    fn rewrite_password(&mut self, password: Option<String>, operation: PasswordOperation) -> Result<Option<Option<String>>, Box<dyn std::error::Error>> {
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(None);
+        };
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let needs_password = archive_needs_password(&mut archive)?;
+        if !needs_password {
+            return Ok(Some(None));
+        }
+
+        let Some(password) = password else {
+            self.show_password_dialog = true;
+            self.current_operation = Some(operation);
+            self.status_message = "Archive is encrypted. Please enter password.".to_string();
+            return Ok(None);
+        };
+        // New entries are encrypted with this password, so it must be the right one
+        if let Err(e) = archive.by_index_decrypt(0, password.as_bytes()) {
+            return Err(format!("Wrong password: {}", e).into());
+        }
+        Ok(Some(Some(password)))
+    }
+
+    /// Rewrites the open archive in the background and reopens it when done.
+    fn start_rewrite(&mut self, keep: Vec<RawEntry>, add: Vec<NewEntry>, comment: Vec<u8>, password: Option<String>, summary: String) {
+        // Any embedded signature no longer matches the rewritten contents
+        let comment = if signing::is_signature_comment(&comment) { Vec::new() } else { comment };
+        let job_password = password.clone();
+        self.spawn_rewrite(password, move |archive_path, progress| {
+            rewrite_archive(archive_path, &keep, &add, &comment, job_password.as_deref(), progress)?;
+            Ok(summary)
+        });
+    }
+
+    /// Runs `job` on the open archive in a background thread. The job reports
+    /// progress and returns the status message shown once the archive is reopened.
+    fn spawn_rewrite(
+        &mut self,
+        password: Option<String>,
+        job: impl FnOnce(&Path, &dyn Fn(f32)) -> Result<String, Box<dyn std::error::Error>> + Send + 'static,
+    ) {
+        if self.opened_read_only() {
+            self.status_message = READ_ONLY_COPY.to_string();
+            return;
+        }
+        let Some((archive_path, _)) = &self.current_archive else {
+            return;
+        };
+        let archive_path = archive_path.clone();
+        let progress_state = Arc::clone(&self.progress_state);
+        // Claim the archive before the thread starts so a second job cannot
+        // begin while this one is still writing
+        match progress_state.lock() {
+            Ok(mut state) if state.rewrite_progress.is_none() && state.archive_update.is_none() => {
+                state.rewrite_progress = Some(0.0);
+            }
+            _ => {
+                self.status_message = "Error: the archive is still being updated".to_string();
+                return;
+            }
+        }
+        self.status_message = "Updating archive...".to_string();
+
+        thread::spawn(move || {
+            let report_progress = |progress: f32| {
+                if let Ok(mut state) = progress_state.lock() {
+                    state.rewrite_progress = Some(progress);
+                }
+            };
+            let result = job(&archive_path, &report_progress).map_err(|e| e.to_string());
+            if let Err(e) = &result {
+                error!("Archive update error: {}", e);
+            }
+
+            if let Ok(mut state) = progress_state.lock() {
+                state.rewrite_progress = None;
+                state.archive_update = Some(ArchiveUpdate {
+                    path: archive_path,
+                    password,
+                    result,
+                });
+            }
+        });
+    }
+
+    /// Reloads the listing after a rewrite finished.
+    pub fn finish_archive_update(&mut self, update: ArchiveUpdate) {
+        match update.result {
+            Ok(summary) => {
+                let reopened = match update.password {
+                    Some(password) => self.open_archive_with_password(&update.path, Some(password)),
+                    None => self.open_archive(&update.path),
+                };
+                self.status_message = match reopened {
+                    Ok(()) => summary,
+                    Err(e) => format!("Error reopening archive: {}", e),
+                };
+            }
+            Err(e) => self.status_message = format!("Error updating archive: {}", e),
+        }
+    }
+
+    /// Picks up newly opened entries and, at most once per `WATCH_INTERVAL`,
+    /// asks whether an edited temp copy should be written back.
+    pub fn check_watched_entries(&mut self, ctx: &egui::Context) {
+        let (opened, rewriting) = match self.progress_state.lock() {
+            Ok(mut state) => (
+                std::mem::take(&mut state.opened_entries),
+                state.rewrite_progress.is_some() || state.archive_update.is_some(),
+            ),
+            Err(_) => return,
+        };
+        for watched in opened {
+            self.watched_entries.retain(|existing| existing.entry != watched.entry);
+            self.watched_entries.push(watched);
+        }
+        if self.watched_entries.is_empty() {
+            return;
+        }
+
+        ctx.request_repaint_after(WATCH_INTERVAL);
+        if self.entry_update_prompt.is_some() || rewriting || self.last_watch_check.elapsed() < WATCH_INTERVAL {
+            return;
+        }
+        self.last_watch_check = Instant::now();
+        if let Some(watched) = self.watched_entries.iter().find(|watched| watched.is_modified()) {
+            self.entry_update_prompt = Some(watched.entry.clone());
+        }
+    }
+
+    pub fn draw_entry_update_dialog(&mut self, ctx: &egui::Context) {
+        let Some(entry) = &self.entry_update_prompt else {
+            return;
+        };
+
+        let mut update = None;
+        Window::new("Update entry in archive?")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} was modified outside the archive.", entry));
+                ui.label("Replace the entry in the archive with the edited copy?");
+                ui.horizontal(|ui| {
+                    if ui.button("Update").clicked() {
+                        update = Some(true);
+                    }
+                    if ui.button("Ignore").clicked() {
+                        update = Some(false);
+                    }
+                });
+            });
+
+        let Some(update) = update else {
+            return;
+        };
+        let entry = self.entry_update_prompt.take().unwrap_or_default();
+        if let Some(watched) = self.watched_entries.iter_mut().find(|watched| watched.entry == entry) {
+            watched.mark_synced();
+        }
+        if update {
+            if let Err(e) = self.update_watched_entry(&entry) {
+                self.status_message = format!("Error updating {}: {}", entry, e);
+            }
+        }
+    }
+
+    /// Replaces `entry` with its edited temp copy, keeping the entry's
+    /// compression method and encryption. All other entries are raw-copied.
+    fn update_watched_entry(&mut self, entry: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(());
+        };
+        let Some(watched) = self.watched_entries.iter().find(|watched| watched.entry == entry) else {
+            return Ok(());
+        };
+
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        let index = archive.index_for_name(entry).ok_or("the entry is no longer in the archive")?;
+        let (compression, encrypted) = {
+            let file = archive.by_index_raw(index)?;
+            (file.compression(), file.encrypted())
+        };
+        let aes_mode = archive.get_aes_verification_key_and_salt(index)?.map(|info| info.aes_mode);
+        if encrypted && aes_mode.is_none() {
+            return Err("ZipCrypto-encrypted entries cannot be rewritten".into());
+        }
+        if encrypted && watched.password.is_none() {
+            return Err("the password for this entry is not known".into());
+        }
+
+        let mut replacement = NewEntry::new(watched.temp_path.clone(), entry.to_string(), encrypted);
+        replacement.compression = compression;
+        if let Some(aes_mode) = aes_mode {
+            replacement.aes_mode = aes_mode;
+        }
+
+        let (entries, comment) = read_raw_entries(archive_path)?;
+        let keep: Vec<RawEntry> = entries.into_iter().filter(|raw| raw.name != entry).collect();
+        let password = watched.password.clone();
+        self.start_rewrite(keep, vec![replacement], comment, password, format!("Updated {} in archive", entry));
+        Ok(())
+    }
+
+    /// Updates, freshens or synchronizes the open archive against a folder.
+    pub fn update_from_folder(&mut self, request: UpdateRequest, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.current_archive.is_none() {
+            return Ok(());
+        }
+        let Some(password) = self.rewrite_password(password, PasswordOperation::UpdateFromFolder(request.clone()))? else {
+            return Ok(());
+        };
+
+        let job_password = password.clone();
+        let links = self.link_policy;
+        self.spawn_rewrite(password, move |archive_path, progress| {
+            let summary = update_archive(
+                archive_path,
+                &request.source,
+                request.mode,
+                request.detection,
+                job_password.as_deref(),
+                links,
+                progress,
+            )?;
+            Ok(format!("{} from {}: {}", request.mode.title(), request.source.display(), summary.describe()))
+        });
+        Ok(())
+    }
+
+    pub fn draw_update_dialog(&mut self, ctx: &egui::Context) {
+        let Some(request) = &mut self.update_request else {
+            return;
+        };
+
+        let mut run = None;
+        Window::new("Update from Folder")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Source folder:");
+                    ui.monospace(request.source.display().to_string());
+                    if ui.small_button("Change…").clicked() {
+                        if let Some(folder) = rfd::FileDialog::new().set_directory(&request.source).pick_folder() {
+                            request.source = folder;
+                        }
+                    }
+                });
+                ui.separator();
+                for mode in UpdateMode::ALL {
+                    ui.radio_value(&mut request.mode, mode, mode.title()).on_hover_text(mode.description());
+                }
+                ui.separator();
+                ui.label("Detect changes by:");
+                for detection in [ChangeDetection::SizeAndTime, ChangeDetection::ContentHash] {
+                    ui.radio_value(&mut request.detection, detection, detection.title());
+                }
+                ui.weak("Unchanged entries are copied without recompressing.");
+
+                ui.horizontal(|ui| {
+                    if ui.button("Cancel").clicked() {
+                        run = Some(false);
+                    }
+                    if ui.button(request.mode.title()).clicked() {
+                        run = Some(true);
+                    }
+                });
+            });
+
+        match run {
+            Some(true) => {
+                let request = self.update_request.take().unwrap();
+                if let Err(e) = self.update_from_folder(request, None) {
+                    self.status_message = format!("Error updating archive: {}", e);
+                }
+            }
+            Some(false) => self.update_request = None,
+            None => {}
+        }
+    }
+
+    /// Appends files and folders to the open archive. Existing entries are
+    /// raw-copied; same-named entries are replaced only if `replace_existing` is set.
+    pub fn add_files_to_archive(&mut self, paths: Vec<PathBuf>, password: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.recovered_entries.is_some() {
+            self.status_message = "Save the repaired archive before adding files".to_string();
+            return Ok(());
+        }
+        if self.opened_read_only() {
+            self.status_message = READ_ONLY_COPY.to_string();
+            return Ok(());
+        }
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(());
+        };
+        if paths.iter().any(|path| path == archive_path) {
+            return Err("An archive cannot be added to itself".into());
+        }
+        let archive_path = archive_path.clone();
+
+        let Some(password) = self.rewrite_password(password, PasswordOperation::AddToArchive(paths.clone()))? else {
+            return Ok(());
+        };
+
+        let (mut keep, comment) = read_raw_entries(&archive_path)?;
+        let mut add = collect_new_entries(&paths, "", password.is_some(), self.link_policy, &FilterSet::default())?;
+
+        let existing: HashSet<String> = keep.iter().map(|entry| entry.name.clone()).collect();
+        // Folders that already exist need no new entry
+        add.retain(|entry| !(entry.name.ends_with('/') && existing.contains(&entry.name)));
+        let conflicts = add.iter().filter(|entry| existing.contains(&entry.name)).count();
+        if self.replace_existing {
+            let replaced: HashSet<&str> = add.iter().map(|entry| entry.name.as_str()).collect();
+            keep.retain(|entry| !replaced.contains(entry.name.as_str()));
+        } else {
+            add.retain(|entry| !existing.contains(&entry.name));
+        }
+
+        if add.is_empty() {
+            self.status_message = format!("Nothing to add: {} entries already exist", conflicts);
+            return Ok(());
+        }
+
+        let summary = if conflicts == 0 {
+            format!("Added {} entries", add.len())
+        } else if self.replace_existing {
+            format!("Added {} entries, replacing {}", add.len(), conflicts)
+        } else {
+            format!("Added {} entries, skipped {} that already exist", add.len(), conflicts)
+        };
+        info!("Adding {} entries to {:?}", add.len(), archive_path);
+        self.start_rewrite(keep, add, comment, password, summary);
+        Ok(())
+    }
+
+    /// Stages a delete, rename or move; nothing is written until the changes are applied.
+    pub fn stage_change(&mut self, change: EntryChange) -> Result<(), String> {
+        let Some((_, files)) = &self.current_archive else {
+            return Ok(());
+        };
+        if self.recovered_entries.is_some() {
+            return Err("Save the repaired archive before editing it".to_string());
+        }
+        if self.opened_read_only() {
+            return Err(READ_ONLY_COPY.to_string());
+        }
+        let names: Vec<String> = files.iter().map(|file| file.name.clone()).collect();
+        let description = change.describe();
+        self.pending_changes.push(change, &names)?;
+        self.pending_view = self.pending_changes.apply_to_names(&names)?;
+        self.status_message = format!("Staged: {}", description);
+        Ok(())
+    }
+
+    pub fn undo_change(&mut self) {
+        let Some(change) = self.pending_changes.undo() else {
+            return;
+        };
+        self.pending_view = match &self.current_archive {
+            Some((_, files)) => {
+                let names: Vec<String> = files.iter().map(|file| file.name.clone()).collect();
+                self.pending_changes.apply_to_names(&names).unwrap_or_default()
+            }
+            None => HashMap::new(),
+        };
+        if self.pending_changes.is_empty() {
+            self.pending_view.clear();
+        }
+        self.status_message = format!("Undone: {}", change.describe());
+    }
+
+    pub fn discard_changes(&mut self) {
+        self.pending_changes = PendingChanges::default();
+        self.pending_view.clear();
+        self.status_message = "Pending changes discarded".to_string();
+    }
+
+    /// Writes all staged changes in one rewrite. Entries are raw-copied, so
+    /// nothing is recompressed and encrypted entries need no password.
+    pub fn apply_pending_changes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((archive_path, _)) = &self.current_archive else {
+            return Ok(());
+        };
+        if self.pending_changes.is_empty() {
+            return Ok(());
+        }
+
+        let (entries, comment) = read_raw_entries(archive_path)?;
+        let before = entries.len();
+        let keep = self.pending_changes.apply(entries)?;
+        let summary = format!(
+            "Applied {} changes ({} entries removed)",
+            self.pending_changes.changes.len(),
+            before - keep.len()
+        );
+        self.start_rewrite(keep, Vec::new(), comment, None, summary);
+        Ok(())
+    }
+
+    pub fn draw_entry_edit_dialog(&mut self, ctx: &egui::Context) {
+        let Some(edit) = &mut self.entry_edit else {
+            return;
+        };
+
+        let is_folder = edit.path.ends_with('/');
+        let trimmed = edit.path.trim_end_matches('/');
+        let (parent, base) = match trimmed.rfind('/') {
+            Some(pos) => (&trimmed[..=pos], &trimmed[pos + 1..]),
+            None => ("", trimmed),
+        };
+        let folders: Vec<String> = match &self.contents_index {
+            _ if edit.staging => self.staging.folder_paths(),
+            Some(index) => index.tree.directories().map(|node| index.tree.nodes[node].path.clone()).collect(),
+            None => Vec::new(),
+        };
+
+        let mut result = None;
+        let title = match edit.kind {
+            EditKind::Rename => "Rename",
+            EditKind::Move => "Move to Folder",
+            EditKind::NewFolder => "New Folder",
+        };
+        Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if edit.kind != EditKind::NewFolder {
+                    ui.label(&edit.path);
+                }
+                ui.horizontal(|ui| {
+                    match edit.kind {
+                        EditKind::Rename => ui.label("New name:"),
+                        EditKind::Move => ui.label("Destination folder:"),
+                        EditKind::NewFolder => ui.label("Folder path:"),
+                    };
+                    ui.text_edit_singleline(&mut edit.text);
+                    if edit.kind == EditKind::Move {
+                        egui::ComboBox::from_id_salt("move_destination")
+                            .selected_text("Existing…")
+                            .show_ui(ui, |ui| {
+                                if ui.selectable_label(false, "/ (archive root)").clicked() {
+                                    edit.text.clear();
+                                }
+                                for folder in &folders {
+                                    if ui.selectable_label(false, folder).clicked() {
+                                        edit.text = folder.clone();
+                                    }
+                                }
+                            });
+                    }
+                });
+                if let Some(error) = &edit.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Cancel").clicked() {
+                        result = Some(None);
+                    }
+                    if ui.button(if edit.staging { "OK" } else { "Stage" }).clicked() {
+                        let suffix = if is_folder { "/" } else { "" };
+                        let to = match edit.kind {
+                            EditKind::NewFolder => format!("{}/", edit.text.trim_matches('/')),
+                            EditKind::Rename => format!("{}{}{}", parent, edit.text.trim_matches('/'), suffix),
+                            EditKind::Move => {
+                                let folder = edit.text.trim_matches('/');
+                                if folder.is_empty() {
+                                    format!("{}{}", base, suffix)
+                                } else {
+                                    format!("{}/{}{}", folder, base, suffix)
+                                }
+                            }
+                        };
+                        result = Some(Some((edit.path.clone(), to)));
+                    }
+                });
+            });
+
+        let (kind, staging) = (edit.kind, edit.staging);
+        let Some(edit) = result else {
+            return;
+        };
+        let outcome = match edit {
+            None => Ok(()),
+            Some((_, to)) if staging && kind == EditKind::NewFolder => self.staging.add_folder(&to),
+            Some((from, to)) if staging => self.staging.rename(&from, &to),
+            Some((from, to)) => self.stage_change(EntryChange::Rename { from, to }),
+        };
+        match outcome {
+            Ok(()) => self.entry_edit = None,
+            Err(e) => {
+                if let Some(edit) = &mut self.entry_edit {
+                    edit.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Directory entries are extracted to before opening them.
+    pub fn extraction_dir(&self) -> PathBuf {
+        self.extraction_dir.clone().unwrap_or_else(get_temp_dir)
+    }
+
+    /// Returns true if the operation may proceed. Otherwise the violations are
+    /// queued for the safety dialog, which can re-run the action as an override.
+    fn passes_safety_checks(&mut self, action: SafetyAction, violations: Vec<SafetyViolation>) -> bool {
+        let overridden = std::mem::take(&mut self.safety_override);
+        if violations.is_empty() || overridden {
+            return true;
+        }
+
+        warn!("Safety check failed: {}", violations[0]);
+        self.status_message = format!("Safety warning: {}", violations[0]);
+        self.pending_safety = Some((action, violations));
+        false
+    }
+
+    pub fn draw_safety_dialog(&mut self, ctx: &egui::Context) {
+        let Some((action, violations)) = &self.pending_safety else {
+            return;
+        };
+
+        let mut choice = None;
+        Window::new("Safety Warning")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("The operation was stopped by a safety check:");
+                ui.add_space(5.0);
+                for violation in violations {
+                    ui.colored_label(egui::Color32::YELLOW, format!("⚠ {}", violation));
+                }
+                ui.add_space(10.0);
+
+                let out_of_space = violations
+                    .iter()
+                    .any(|violation| matches!(violation, SafetyViolation::InsufficientSpace { .. }));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Cancel").clicked() {
+                        choice = Some(SafetyChoice::Cancel);
+                    }
+                    if out_of_space && ui.button("Change destination…").clicked() {
+                        choice = Some(SafetyChoice::ChangeDestination);
+                    }
+                    if ui.button("Continue anyway").clicked() {
+                        choice = Some(SafetyChoice::Continue);
+                    }
+                });
+            });
+
+        match choice {
+            Some(SafetyChoice::ChangeDestination) => {
+                let action = action.clone();
+                self.pending_safety = None;
+                let result = match action {
+                    SafetyAction::Compress(output_path, password) => {
+                        let file_name = output_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                        match rfd::FileDialog::new()
+                            .add_filter("ZIP", &["zip"])
+                            .add_filter("tar", &["tar", "tgz", "gz"])
+                            .add_filter("7z", &["7z"])
+                            .set_file_name(file_name)
+                            .save_file()
+                        {
+                            Some(output_path) => self.start_compression(output_path, password),
+                            None => Ok(()),
+                        }
+                    }
+                    SafetyAction::ExtractFile(file_name, password) => match rfd::FileDialog::new().pick_folder() {
+                        Some(dir) => {
+                            // The new folder is for this entry only
+                            let previous = self.extraction_dir.replace(dir);
+                            let result = match password {
+                                Some(password) => self.open_file_with_password(file_name, Some(password)),
+                                None => self.open_file(file_name),
+                            };
+                            self.extraction_dir = previous;
+                            result
+                        }
+                        None => Ok(()),
+                    },
+                    SafetyAction::ExtractEntries(names, _, password) => match rfd::FileDialog::new().pick_folder() {
+                        Some(destination) => self.extract_entries_with_password(names, destination, password),
+                        None => Ok(()),
+                    },
+                    SafetyAction::OpenArchive(..) => Ok(()),
+                };
+                if let Err(e) = result {
+                    self.status_message = format!("Error: {}", e);
+                }
+            }
+            Some(SafetyChoice::Continue) => {
+                let action = action.clone();
+                self.pending_safety = None;
+                self.safety_override = true;
+                let result = match action {
+                    SafetyAction::Compress(output_path, password) => self.start_compression(output_path, password),
+                    SafetyAction::OpenArchive(path, password) => match password {
+                        Some(password) => self.open_archive_with_password(&path, Some(password)),
+                        None => self.open_archive(&path),
+                    },
+                    SafetyAction::ExtractFile(file_name, password) => match password {
+                        Some(password) => self.open_file_with_password(file_name, Some(password)),
+                        None => self.open_file(file_name),
+                    },
+                    SafetyAction::ExtractEntries(names, destination, password) => {
+                        self.extract_entries_with_password(names, destination, password)
+                    }
+                };
+                self.safety_override = false;
+                if let Err(e) = result {
+                    self.status_message = format!("Error: {}", e);
+                }
+            }
+            Some(SafetyChoice::Cancel) => {
+                self.pending_safety = None;
+                self.status_message = "Operation cancelled".to_string();
+            }
+            None => {}
+        }
+    }
+
+    pub fn draw_safety_settings(&mut self, ui: &mut egui::Ui) {
+        const GB: u64 = 1024 * 1024 * 1024;
+
+        ui.label("Extraction safeguards");
+        let mut max_size_gb = self.safety_limits.max_total_size / GB;
+        ui.horizontal(|ui| {
+            ui.label("Max uncompressed size (GB):");
+            ui.add(egui::DragValue::new(&mut max_size_gb).range(1..=u32::MAX as u64));
+        });
+        self.safety_limits.max_total_size = max_size_gb * GB;
+
+        ui.horizontal(|ui| {
+            ui.label("Max compression ratio:");
+            ui.add(egui::DragValue::new(&mut self.safety_limits.max_ratio).range(1.0..=100_000.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Max entry count:");
+            ui.add(egui::DragValue::new(&mut self.safety_limits.max_entries).range(1..=usize::MAX));
+        });
+        ui.checkbox(&mut self.safety_limits.detect_overlaps, "Detect overlapping entries");
+        ui.checkbox(&mut self.safety_limits.check_free_space, "Check free disk space before compressing or extracting");
+    }
+
+    /// Expands the compression list, walking folders and applying the filters.
+    pub fn collect_compression_entries(&self, encrypt: bool) -> Result<Vec<NewEntry>, Box<dyn std::error::Error>> {
+        let filters = self.input_filters.compile()?;
+        Ok(self.staging.entries(encrypt, self.link_policy, &filters)?)
+    }
+
+    /// Lists what the compression list expands to. Folders are walked on a
+    /// worker thread; `draw_compression_preview` shows the result.
+    pub fn preview_compression(&mut self) {
+        let filters = match self.input_filters.compile() {
+            Ok(filters) => filters,
+            Err(e) => {
+                self.status_message = format!("Error previewing files to compress: {}", e);
+                return;
+            }
+        };
+        let sources = self.staging.sources();
+        let links = self.link_policy;
+        let progress_state = Arc::clone(&self.progress_state);
+        let generation = match progress_state.lock() {
+            Ok(mut state) => {
+                state.compression_preview_generation += 1;
+                state.compression_preview_result = None;
+                state.compression_preview_generation
+            }
+            Err(_) => return,
+        };
+        self.compression_preview_loading = true;
+
+        thread::spawn(move || {
+            let result = collect_named_entries(&sources, false, links, &filters)
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .map(|entry| {
+                            let size = if entry.is_file() { entry.metadata().map_or(0, |meta| meta.len()) } else { 0 };
+                            (entry.name.clone(), size)
+                        })
+                        .collect()
+                })
+                .map_err(|e| e.to_string());
+            if let Ok(mut state) = progress_state.lock() {
+                if state.compression_preview_generation == generation {
+                    state.compression_preview_result = Some(result);
+                }
+            }
+        });
+    }
+
+    fn close_compression_preview(&mut self) {
+        self.compression_preview = None;
+        self.compression_preview_loading = false;
+        if let Ok(mut state) = self.progress_state.lock() {
+            state.compression_preview_generation += 1;
+            state.compression_preview_result = None;
+        }
+    }
+
+    pub fn draw_input_filters(&mut self, ui: &mut egui::Ui) {
+        const MIB: u64 = 1024 * 1024;
+        let filters = &mut self.input_filters;
+        egui::CollapsingHeader::new(if filters.is_active() { "Filters (active)" } else { "Filters" }).show(ui, |ui| {
+            ui.weak("One pattern per line, in .gitignore syntax, e.g. target/ or *.log");
+            ui.horizontal_top(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Include only");
+                    ui.add(egui::TextEdit::multiline(&mut filters.include).desired_rows(3).desired_width(180.0));
+                });
+                ui.vertical(|ui| {
+                    ui.label("Exclude");
+                    ui.add(egui::TextEdit::multiline(&mut filters.exclude).desired_rows(3).desired_width(180.0));
+                });
+            });
+            ui.checkbox(&mut filters.use_ignore_files, "Honour .gitignore and .ignore files")
+                .on_hover_text("Also .git/info/exclude; .git folders are left out");
+            ui.checkbox(&mut filters.skip_hidden, "Skip hidden files and folders");
+            ui.horizontal(|ui| {
+                let mut limited = filters.max_size.is_some();
+                ui.checkbox(&mut limited, "Skip files larger than");
+                let mut size = filters.max_size.map_or(100, |size| size / MIB);
+                ui.add_enabled(limited, egui::DragValue::new(&mut size).range(1..=u64::MAX).suffix(" MiB"));
+                filters.max_size = limited.then_some(size * MIB);
+            });
+            ui.horizontal(|ui| {
+                let mut limited = filters.max_age_days.is_some();
+                ui.checkbox(&mut limited, "Skip files not modified in");
+                let mut days = filters.max_age_days.unwrap_or(30);
+                ui.add_enabled(limited, egui::DragValue::new(&mut days).range(1..=36500).suffix(" days"));
+                filters.max_age_days = limited.then_some(days);
+            });
+        });
+    }
+
+    pub fn draw_compression_preview(&mut self, ctx: &egui::Context) {
+        let result = self.progress_state.lock().ok().and_then(|mut state| state.compression_preview_result.take());
+        match result {
+            Some(Ok(preview)) => {
+                self.compression_preview = Some(preview);
+                self.compression_preview_loading = false;
+            }
+            Some(Err(e)) => {
+                self.status_message = format!("Error previewing files to compress: {}", e);
+                self.close_compression_preview();
+            }
+            None => {}
+        }
+        if self.compression_preview_loading {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+        let loading = self.compression_preview_loading;
+        if self.compression_preview.is_none() && !loading {
+            return;
+        }
+        let empty = Vec::new();
+        let preview = self.compression_preview.as_ref().unwrap_or(&empty);
+
+        let mut open = true;
+        let mut refresh = false;
+        Window::new("Files to Compress")
+            .open(&mut open)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                let files = preview.iter().filter(|(name, _)| !name.ends_with('/')).count();
+                let total: u64 = preview.iter().map(|(_, size)| size).sum();
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} files and {} folders, {}",
+                        files,
+                        preview.len() - files,
+                        get_formatted_size(total)
+                    ));
+                    if loading {
+                        ui.spinner();
+                        ui.weak("Listing files…");
+                    } else if ui.small_button("Refresh").clicked() {
+                        refresh = true;
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().show_rows(ui, ui.text_style_height(&egui::TextStyle::Monospace), preview.len(), |ui, rows| {
+                    for (name, size) in &preview[rows] {
+                        ui.horizontal(|ui| {
+                            ui.monospace(name);
+                            if !name.ends_with('/') {
+                                ui.weak(get_formatted_size(*size));
+                            }
+                        });
+                    }
+                });
+            });
+
+        if !open {
+            self.close_compression_preview();
+        } else if refresh {
+            self.preview_compression();
+        }
+    }
+
+    pub fn draw_link_settings(&mut self, ui: &mut egui::Ui) {
+        ui.label("Symbolic links when archiving");
+        ui.horizontal(|ui| {
+            for policy in LinkPolicy::ALL {
+                ui.radio_value(&mut self.link_policy, policy, policy.title());
+            }
+        });
+    }
+
+    pub fn draw_output_settings(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.reproducible_output, "Reproducible output")
+            .on_hover_text("Sorted entries, permissions of 0644/0755 and no extra fields");
+        if self.reproducible_output {
+            let timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+                Ok(value) => format!("SOURCE_DATE_EPOCH ({})", value),
+                Err(_) => "1980-01-01 00:00 (set SOURCE_DATE_EPOCH to change)".to_string(),
+            };
+            ui.weak(format!("Every entry is dated {}. Encrypted archives still differ between runs.", timestamp));
+        }
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.split_output, "Split into volumes of");
+            ui.add_enabled(
+                self.split_output,
+                egui::DragValue::new(&mut self.volume_size_mib)
+                    .range(1..=u32::MAX as u64)
+                    .suffix(" MiB"),
+            );
+        });
+        if self.split_output {
+            ui.horizontal(|ui| {
+                for style in VolumeStyle::ALL {
+                    ui.radio_value(&mut self.volume_style, style, style.title());
+                }
+            });
+        }
+
+        ui.checkbox(&mut self.self_extracting, "Create self-extracting executable (.run)")
+            .on_hover_text("Written next to the archive; it extracts into the folder given as its argument, or the current one");
+        if self.self_extracting {
+            ui.horizontal(|ui| {
+                ui.label("Run after extraction:");
+                ui.text_edit_singleline(&mut self.sfx_options.run_after)
+                    .on_hover_text("Shell command run in the destination folder; leave empty for none");
+            });
+            ui.weak(
+                "The extractor is the seven_zipper-sfx program next to this one, built with \
+                 `cargo build --release --bin seven_zipper-sfx`, or the file named by SEVEN_ZIPPER_SFX_STUB. \
+                 That build links against glibc; for a static extractor, build it with \
+                 `--no-default-features --target x86_64-unknown-linux-musl` and set SEVEN_ZIPPER_SFX_STUB to it.",
+            );
+        }
+    }
+
+    pub fn draw_restore_settings(&mut self, ui: &mut egui::Ui) {
+        ui.label("Restore metadata on extraction");
+        ui.horizontal(|ui| {
+            for policy in RestorePolicy::ALL {
+                ui.radio_value(&mut self.restore_policy, policy, policy.title());
+            }
+        });
+        if self.restore_policy == RestorePolicy::Everything && !is_root() {
+            ui.weak("Not running as root: ownership is not restored.");
+        }
+        ui.checkbox(&mut self.restore_xattrs, "Restore extended attributes, ACLs and SELinux labels");
+    }
+
+    pub fn handle_file_drop(
+        &mut self,
+        path: &Path,
     ) -> Result<(), Box<dyn std::error::Error>> {
         info!("Handling file drop: {:?}", path);
 
@@ -458,13 +2600,13 @@ impl ArchiveManager {
         info!("File extension: {}", extension);
 
         match extension {
-            "zip" | "7z" | "rar" => {
+            _ if matches!(extension, "zip" | "7z" | "rar" | "cbz" | "cbr" | "cb7") || is_volume_name(path) => {
                 if self.remember_archive_choice {
                     // If we're remembering the choice, follow the last decision
                     if let Some(compress) = self.last_archive_choice {
                         if compress {
                             info!("Adding archive to compression list (remembered choice)");
-                            self.selected_files.push(path.to_path_buf());
+                            self.staging.add(path.to_path_buf());
                             self.status_message = "Archive added to compression list".to_string();
                         } else {
                             info!("Opening archive for viewing (remembered choice)");
@@ -485,7 +2627,7 @@ impl ArchiveManager {
             }
             _ => {
                 info!("Adding file to compression list");
-                self.selected_files.push(path.to_path_buf());
+                self.staging.add(path.to_path_buf());
                 self.status_message = "File added to compression list".to_string();
             }
         }
@@ -496,6 +2638,7 @@ impl ArchiveManager {
     pub fn handle_drops(&mut self, ctx: &egui::Context) {
         // Store the zone at the start of the frame
         let drop_zone = self.compress_zone.rect;
+        let contents_zone = self.contents_zone.rect.filter(|_| self.current_archive.is_some());
 
         ctx.input(|i| {
             let pointer_pos = i.pointer.hover_pos();
@@ -503,6 +2646,17 @@ impl ArchiveManager {
             if !i.raw.dropped_files.is_empty() {
                 info!("Files dropped: {} files", i.raw.dropped_files.len());
 
+                if let (Some(pos), Some(rect)) = (pointer_pos, contents_zone) {
+                    if rect.contains(pos) {
+                        let paths: Vec<PathBuf> = i.raw.dropped_files.iter().filter_map(|file| file.path.clone()).collect();
+                        info!("Adding {} dropped files to the open archive", paths.len());
+                        if let Err(e) = self.add_files_to_archive(paths, None) {
+                            self.status_message = format!("Error adding files: {}", e);
+                        }
+                        return;
+                    }
+                }
+
                 // Check if pointer is in drop zone
                 let in_drop_zone = match (pointer_pos, drop_zone) {
                     (Some(pos), Some(rect)) => {
@@ -538,3 +2692,110 @@ impl ArchiveManager {
     }
 }
 
+
+/// Writes the entry `name` below `temp_dir` to be opened with the system app,
+/// calling `progress` with the bytes written and the entry's size. The path
+/// comes from the entry's enclosed name, and the copy is only returned once it
+/// has been read to the end with its checksum verified; a partial copy is
+/// removed, so it can never be watched and written back.
+fn extract_for_editing(
+    archive_path: &Path,
+    name: &str,
+    password: Option<&str>,
+    temp_dir: &Path,
+    progress: impl Fn(u64, u64),
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+    let mut entry = match password {
+        Some(password) => archive.by_name_decrypt(name, password.as_bytes())?,
+        None => archive.by_name(name)?,
+    };
+    let relative = entry.enclosed_name().ok_or("its path leaves the temp folder")?;
+    let temp_path = temp_dir.join(relative);
+    if let Some(parent) = temp_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let total_size = entry.size();
+    let mut copy = || -> std::io::Result<()> {
+        let mut temp_file = File::create(&temp_path)?;
+        let mut buffer = [0; 8192];
+        let mut processed_size = 0;
+        loop {
+            let n = entry.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(());
+            }
+            processed_size += n as u64;
+            if processed_size > total_size {
+                // The entry inflates past the size it declared; never trust it
+                return Err(std::io::Error::other("the entry expands beyond its declared size"));
+            }
+            temp_file.write_all(&buffer[..n])?;
+            progress(processed_size, total_size);
+        }
+    };
+    if let Err(e) = copy() {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    Ok(temp_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn write_archive(path: &Path, name: &str, data: &[u8], password: Option<&str>) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        let mut options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        if let Some(password) = password {
+            options = options.with_aes_encryption(zip::AesMode::Aes256, password);
+        }
+        writer.start_file(name, options).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn entries_are_copied_out_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("a.zip");
+        write_archive(&archive, "docs/note.txt", b"hello", Some("hunter2"));
+        let temp_dir = dir.path().join("temp");
+
+        assert!(extract_for_editing(&archive, "docs/note.txt", Some("wrong"), &temp_dir, |_, _| {}).is_err());
+        assert!(!temp_dir.join("docs/note.txt").exists());
+        let path = extract_for_editing(&archive, "docs/note.txt", Some("hunter2"), &temp_dir, |_, _| {}).unwrap();
+        assert_eq!(path, temp_dir.join("docs/note.txt"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn corrupt_entries_leave_no_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("a.zip");
+        write_archive(&archive, "note.txt", b"hello, world", None);
+        // Stored data follows the 30-byte local header and the name
+        let mut bytes = std::fs::read(&archive).unwrap();
+        bytes[30 + "note.txt".len()] ^= 0xff;
+        std::fs::write(&archive, bytes).unwrap();
+
+        let temp_dir = dir.path().join("temp");
+        assert!(extract_for_editing(&archive, "note.txt", None, &temp_dir, |_, _| {}).is_err());
+        assert!(!temp_dir.join("note.txt").exists());
+    }
+
+    #[test]
+    fn entries_cannot_leave_the_temp_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("a.zip");
+        write_archive(&archive, "../escaped.txt", b"hello", None);
+
+        let temp_dir = dir.path().join("temp");
+        assert!(extract_for_editing(&archive, "../escaped.txt", None, &temp_dir, |_, _| {}).is_err());
+        assert!(!dir.path().join("escaped.txt").exists());
+    }
+}
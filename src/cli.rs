@@ -0,0 +1,387 @@
+use crate::app::CompressionStats;
+use crate::filters::{parse_size, InputFilters};
+use crate::integrity::test_archive;
+use crate::metadata::Reproducible;
+use crate::parallel::compress_files_parallel;
+use crate::rewrite::{collect_new_entries, LinkPolicy};
+use crate::sfx::{create_self_extractor, SfxOptions};
+use crate::sevenzip::{compress_to_seven_zip, is_seven_zip};
+use crate::tarball::{compress_to_tar, tar_format};
+use crate::update::{update_archive, ChangeDetection, UpdateMode};
+use crate::utils::get_formatted_size;
+use crate::volumes::{split_archive, VolumeStyle, MIN_VOLUME_SIZE};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage:
+  seven_zipper                              Start the graphical interface
+  seven_zipper test <archive> [--password-stdin]
+                                            Verify every entry of an archive
+  seven_zipper compress <archive> <path>... [--include <pattern>]... [--exclude <pattern>]...
+                     [--gitignore] [--skip-hidden] [--max-size <size>] [--max-age <days>]
+                     [--links store|follow|skip] [--password-stdin] [--reproducible] [--dry-run]
+                     [--volume-size <size>] [--volume-style numbered|spanned] [--sfx <file>] [--sfx-run <command>]
+                                            Create an archive from files and folders,
+                                            a tar archive when <archive> ends in .tar,
+                                            .tar.gz or .tgz, a 7z archive for .7z;
+                                            patterns use .gitignore syntax, sizes accept
+                                            K/M/G suffixes, --dry-run only lists the files;
+                                            --gitignore honours .gitignore, .ignore and
+                                            .git/info/exclude and leaves .git folders out;
+                                            --reproducible sorts entries and fixes their
+                                            metadata, dating them SOURCE_DATE_EPOCH if set;
+                                            --volume-size splits the archive into
+                                            archive.zip.001 (or .7z.001), .002, … or, when spanned,
+                                            archive.z01, .z02, …, archive.zip;
+                                            --sfx also writes a self-extracting executable
+                                            that runs --sfx-run in its folder afterwards
+  seven_zipper update <archive> <folder> [--mode update|freshen|sync] [--hash]
+                     [--links store|follow|skip] [--password-stdin]
+                                            Bring an archive up to date with a folder;
+                                            --hash compares contents instead of size and time
+
+Passwords are read from the first line of standard input with --password-stdin,
+or else from the SEVEN_ZIPPER_PASSWORD environment variable. --password <password>
+is still accepted, but other users can see it in the process list.";
+
+/// Environment variable holding the archive password
+const PASSWORD_ENV: &str = "SEVEN_ZIPPER_PASSWORD";
+
+/// Runs a command-line subcommand if one was given. Returns the process exit
+/// code, or `None` when the GUI should be started instead.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.first()?;
+    let code = match command.as_str() {
+        "test" => run_test(&args[1..]),
+        "compress" => run_compress(&args[1..]),
+        "update" => run_update(&args[1..]),
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            0
+        }
+        other => {
+            eprintln!("Unknown command: {}\n\n{}", other, USAGE);
+            2
+        }
+    };
+    Some(code)
+}
+
+/// Splits `args` into positional arguments and the password, taken from
+/// `--password-stdin`, `--password` or the environment, in that order.
+fn parse_password(args: &[String]) -> Result<(Vec<&String>, Option<String>), String> {
+    let mut positional = Vec::new();
+    let mut password = None;
+    let mut from_stdin = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--password" || arg == "-p" {
+            password = Some(iter.next().ok_or("--password needs a value")?.clone());
+        } else if arg == "--password-stdin" {
+            from_stdin = true;
+        } else {
+            positional.push(arg);
+        }
+    }
+    if from_stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map_err(|e| format!("Cannot read the password: {}", e))?;
+        password = Some(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+    let password = password.or_else(|| std::env::var(PASSWORD_ENV).ok().filter(|value| !value.is_empty()));
+    Ok((positional, password))
+}
+
+fn run_test(args: &[String]) -> i32 {
+    let (positional, password) = match parse_password(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let [archive] = positional.as_slice() else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let path = PathBuf::from(archive);
+    let report = match test_archive(&path, password.as_deref(), |_, _| {}) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error testing {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+
+    for entry in &report.entries {
+        match &entry.error {
+            None => println!("PASS  {:>12}  {}", get_formatted_size(entry.size), entry.name),
+            Some(error) => println!("FAIL  {:>12}  {}: {}", get_formatted_size(entry.size), entry.name, error),
+        }
+    }
+    for problem in &report.problems {
+        println!("WARN  {}", problem);
+    }
+
+    println!(
+        "\n{} entries tested, {} failed, {} archive problems",
+        report.entries.len(),
+        report.failed_count(),
+        report.problems.len()
+    );
+
+    if report.is_ok() {
+        0
+    } else {
+        1
+    }
+}
+
+fn run_compress(args: &[String]) -> i32 {
+    let (positional, password) = match parse_password(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    let mut filters = InputFilters::default();
+    let mut links = LinkPolicy::default();
+    let mut dry_run = false;
+    let mut reproducible = None;
+    let mut volume_size = None;
+    let mut volume_style = VolumeStyle::default();
+    let mut sfx_path = None;
+    let mut sfx_options = SfxOptions::default();
+    let mut paths = Vec::new();
+    let mut iter = positional.into_iter();
+    while let Some(arg) = iter.next() {
+        let pattern_list = match arg.as_str() {
+            "--include" => Some(&mut filters.include),
+            "--exclude" => Some(&mut filters.exclude),
+            _ => None,
+        };
+        if let Some(list) = pattern_list {
+            let Some(pattern) = iter.next() else {
+                eprintln!("{} needs a pattern\n\n{}", arg, USAGE);
+                return 2;
+            };
+            list.push_str(pattern);
+            list.push('\n');
+            continue;
+        }
+        match arg.as_str() {
+            "--gitignore" => filters.use_ignore_files = true,
+            "--skip-hidden" => filters.skip_hidden = true,
+            "--dry-run" => dry_run = true,
+            "--reproducible" => reproducible = Some(Reproducible::from_env()),
+            "--max-size" => match iter.next().and_then(|size| parse_size(size)) {
+                Some(size) => filters.max_size = Some(size),
+                None => {
+                    eprintln!("--max-size needs a size such as 500K or 10M\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--max-age" => match iter.next().and_then(|days| days.parse().ok()) {
+                Some(days) => filters.max_age_days = Some(days),
+                None => {
+                    eprintln!("--max-age needs a number of days\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--links" => match iter.next().and_then(|name| LinkPolicy::parse(name)) {
+                Some(parsed) => links = parsed,
+                None => {
+                    eprintln!("--links must be store, follow or skip\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--volume-size" => match iter.next().and_then(|size| parse_size(size)) {
+                Some(size) if size >= MIN_VOLUME_SIZE => volume_size = Some(size),
+                _ => {
+                    eprintln!("--volume-size needs a size of at least 64K\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--sfx" | "--sfx-run" => {
+                let Some(value) = iter.next() else {
+                    eprintln!("{} needs a value\n\n{}", arg, USAGE);
+                    return 2;
+                };
+                match arg.as_str() {
+                    "--sfx" => sfx_path = Some(PathBuf::from(value)),
+                    _ => sfx_options.run_after = value.clone(),
+                }
+            }
+            "--volume-style" => match iter.next().and_then(|name| VolumeStyle::parse(name)) {
+                Some(parsed) => volume_style = parsed,
+                None => {
+                    eprintln!("--volume-style must be numbered or spanned\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let Some((archive, inputs)) = paths.split_first().filter(|(_, inputs)| !inputs.is_empty()) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let tar = tar_format(archive);
+    let seven_zip = is_seven_zip(archive);
+    if tar.is_some() || seven_zip {
+        let unsupported = [
+            (tar.is_some() && password.is_some(), "encryption"),
+            (sfx_path.is_some(), "--sfx"),
+            (volume_size.is_some() && volume_style == VolumeStyle::Spanned, "spanned volumes"),
+        ];
+        if let Some((_, feature)) = unsupported.iter().find(|(enabled, _)| *enabled) {
+            eprintln!("Error: {} archives do not support {}", if seven_zip { "7z" } else { "tar" }, feature);
+            return 2;
+        }
+    }
+
+    let entries = match filters.compile() {
+        Ok(compiled) => collect_new_entries(inputs, "", password.is_some(), links, &compiled).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error collecting files: {}", e);
+            return 1;
+        }
+    };
+
+    let sizes: Vec<u64> = entries
+        .iter()
+        .map(|entry| if entry.is_file() { entry.metadata().map_or(0, |meta| meta.len()) } else { 0 })
+        .collect();
+    let total_size: u64 = sizes.iter().sum();
+    if dry_run {
+        for (entry, size) in entries.iter().zip(&sizes) {
+            println!("{:>12}  {}", if entry.is_file() { get_formatted_size(*size) } else { String::new() }, entry.name);
+        }
+        println!("\n{} entries, {}", entries.len(), get_formatted_size(total_size));
+        return 0;
+    }
+    if entries.is_empty() {
+        eprintln!("Error: the filters leave no files to compress");
+        return 1;
+    }
+
+    let stats = CompressionStats {
+        original_size: total_size,
+        compressed_size: 0,
+        start_time: Instant::now(),
+        estimated_time: Duration::from_secs(0),
+        output_path: archive.clone(),
+        files_processed: 0,
+        total_files: entries.len(),
+    };
+    let count = entries.len();
+    // Progress is not shown, but the channel must stay open until the end
+    let (progress_tx, _progress_rx) = channel();
+    let (_cancel_tx, cancel_rx) = channel();
+    let result = match tar {
+        Some(compression) => {
+            compress_to_tar(entries, archive.clone(), compression, progress_tx, Arc::new(Mutex::new(stats)), reproducible)
+        }
+        None if seven_zip => {
+            compress_to_seven_zip(entries, archive.clone(), password, progress_tx, Arc::new(Mutex::new(stats)), reproducible)
+        }
+        None => compress_files_parallel(
+            entries,
+            archive.clone(),
+            progress_tx,
+            Arc::new(Mutex::new(cancel_rx)),
+            Arc::new(Mutex::new(stats)),
+            password.unwrap_or_default(),
+            reproducible,
+        ),
+    };
+    if let Err(e) = result {
+        eprintln!("Error compressing {}: {}", archive.display(), e);
+        return 1;
+    }
+    println!("Compressed {} entries ({}) into {}", count, get_formatted_size(total_size), archive.display());
+
+    if let Some(sfx_path) = sfx_path {
+        if let Err(e) = create_self_extractor(archive, &sfx_path, &sfx_options) {
+            eprintln!("Error creating {}: {}", sfx_path.display(), e);
+            return 1;
+        }
+        println!("Created self-extracting archive {}", sfx_path.display());
+    }
+
+    if let Some(volume_size) = volume_size {
+        match split_archive(archive, volume_size, volume_style) {
+            Ok(volumes) => {
+                for volume in &volumes {
+                    println!("  {}", volume.display());
+                }
+                println!("{} volumes", volumes.len());
+            }
+            Err(e) => {
+                eprintln!("Error splitting {}: {}", archive.display(), e);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn run_update(args: &[String]) -> i32 {
+    let (positional, password) = match parse_password(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    let mut mode = UpdateMode::Update;
+    let mut detection = ChangeDetection::SizeAndTime;
+    let mut links = LinkPolicy::default();
+    let mut paths = Vec::new();
+    let mut iter = positional.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--hash" => detection = ChangeDetection::ContentHash,
+            "--mode" => match iter.next().and_then(|name| UpdateMode::parse(name)) {
+                Some(parsed) => mode = parsed,
+                None => {
+                    eprintln!("--mode must be update, freshen or sync\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--links" => match iter.next().and_then(|name| LinkPolicy::parse(name)) {
+                Some(parsed) => links = parsed,
+                None => {
+                    eprintln!("--links must be store, follow or skip\n\n{}", USAGE);
+                    return 2;
+                }
+            },
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let [archive, source] = paths.as_slice() else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    match update_archive(archive, source, mode, detection, password.as_deref(), links, |_| {}) {
+        Ok(summary) => {
+            println!("{} {}: {}", mode.title(), archive.display(), summary.describe());
+            0
+        }
+        Err(e) => {
+            eprintln!("Error updating {}: {}", archive.display(), e);
+            1
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use crate::rewrite::RawEntry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often temp copies of opened entries are checked for edits
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A staged change to the open archive. Paths ending in '/' act on a folder
+/// and everything below it; moving an entry is a rename into another folder.
+#[derive(Clone)]
+pub enum EntryChange {
+    Delete(String),
+    Rename { from: String, to: String },
+}
+
+impl EntryChange {
+    pub fn describe(&self) -> String {
+        match self {
+            EntryChange::Delete(path) => format!("Delete {}", path),
+            EntryChange::Rename { from, to } => format!("{} → {}", from, to),
+        }
+    }
+}
+
+/// Changes waiting to be written in one rewrite. The newest change can be undone.
+#[derive(Default)]
+pub struct PendingChanges {
+    pub changes: Vec<EntryChange>,
+}
+
+/// Checks that `name` is a usable relative entry path.
+pub fn validate_entry_path(name: &str) -> Result<(), String> {
+    let trimmed = name.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Err("Name cannot be empty".to_string());
+    }
+    if name.starts_with('/') || name.contains('\\') {
+        return Err(format!("{} must be a relative path using '/'", name));
+    }
+    if trimmed.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+        return Err(format!("{} contains an empty, '.' or '..' component", name));
+    }
+    Ok(())
+}
+
+fn affects(path: &str, name: &str) -> bool {
+    if path.ends_with('/') {
+        name.starts_with(path)
+    } else {
+        name == path
+    }
+}
+
+impl PendingChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Stages `change` if it still applies cleanly to `names` after the changes
+    /// already staged.
+    pub fn push(&mut self, change: EntryChange, names: &[String]) -> Result<(), String> {
+        self.changes.push(change);
+        if let Err(e) = self.apply_to_names(names) {
+            self.changes.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> Option<EntryChange> {
+        self.changes.pop()
+    }
+
+    /// Maps each original name to its staged name, or `None` if it will be deleted.
+    pub fn apply_to_names(&self, names: &[String]) -> Result<HashMap<String, Option<String>>, String> {
+        let mut current: Vec<(String, Option<String>)> =
+            names.iter().map(|name| (name.clone(), Some(name.clone()))).collect();
+
+        for change in &self.changes {
+            match change {
+                EntryChange::Delete(path) => {
+                    let mut matched = false;
+                    for (_, staged) in &mut current {
+                        if staged.as_deref().is_some_and(|name| affects(path, name)) {
+                            *staged = None;
+                            matched = true;
+                        }
+                    }
+                    if !matched {
+                        return Err(format!("{} is not in the archive", path));
+                    }
+                }
+                EntryChange::Rename { from, to } => {
+                    validate_entry_path(to)?;
+                    if from.ends_with('/') != to.ends_with('/') {
+                        return Err("A folder can only be renamed to a folder".to_string());
+                    }
+                    if from.ends_with('/') && to.starts_with(from.as_str()) {
+                        return Err(format!("Cannot move {} into itself", from));
+                    }
+
+                    let mut renamed = HashSet::new();
+                    for (_, staged) in &mut current {
+                        if let Some(name) = staged.as_mut().filter(|name| affects(from, name)) {
+                            *name = format!("{}{}", to, &name[from.len()..]);
+                            renamed.insert(name.clone());
+                        }
+                    }
+                    if renamed.is_empty() {
+                        return Err(format!("{} is not in the archive", from));
+                    }
+
+                    let mut counts: HashMap<&str, usize> = HashMap::new();
+                    for name in current.iter().filter_map(|(_, staged)| staged.as_deref()) {
+                        *counts.entry(name).or_default() += 1;
+                    }
+                    if let Some(duplicate) = renamed.iter().find(|name| counts[name.as_str()] > 1) {
+                        return Err(format!("{} already exists", duplicate));
+                    }
+                }
+            }
+        }
+
+        Ok(current.into_iter().collect())
+    }
+
+    /// Applies the staged changes to raw entries read from the archive.
+    pub fn apply(&self, entries: Vec<RawEntry>) -> Result<Vec<RawEntry>, String> {
+        let names: Vec<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+        let staged = self.apply_to_names(&names)?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                let new_name = staged.get(&entry.name)?.as_ref()?;
+                if *new_name != entry.name {
+                    entry.rename(new_name);
+                }
+                Some(entry)
+            })
+            .collect())
+    }
+}
+
+/// A temp copy of an entry opened with the system app, watched so that edits
+/// can be written back into the archive.
+#[derive(Clone)]
+pub struct WatchedEntry {
+    pub entry: String,
+    pub temp_path: PathBuf,
+    /// Password the entry was extracted with, reused to encrypt the update
+    pub password: Option<String>,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+impl WatchedEntry {
+    pub fn new(entry: String, temp_path: PathBuf, password: Option<String>) -> Self {
+        let stamp = file_stamp(&temp_path);
+        Self {
+            entry,
+            temp_path,
+            password,
+            stamp,
+        }
+    }
+
+    /// True if the copy was saved since it was extracted or last synced.
+    pub fn is_modified(&self) -> bool {
+        let stamp = file_stamp(&self.temp_path);
+        stamp.is_some() && stamp != self.stamp
+    }
+
+    pub fn mark_synced(&mut self) {
+        self.stamp = file_stamp(&self.temp_path);
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        ["a.txt", "b.txt", "docs/", "docs/x.txt", "docs/sub/y.txt"].map(String::from).to_vec()
+    }
+
+    fn rename(from: &str, to: &str) -> EntryChange {
+        EntryChange::Rename { from: from.to_string(), to: to.to_string() }
+    }
+
+    fn staged(changes: &PendingChanges, name: &str) -> Option<String> {
+        changes.apply_to_names(&names()).unwrap()[name].clone()
+    }
+
+    #[test]
+    fn folder_renames_move_everything_below() {
+        let mut changes = PendingChanges::default();
+        changes.push(rename("docs/", "notes/"), &names()).unwrap();
+        assert_eq!(staged(&changes, "docs/").as_deref(), Some("notes/"));
+        assert_eq!(staged(&changes, "docs/sub/y.txt").as_deref(), Some("notes/sub/y.txt"));
+        assert_eq!(staged(&changes, "a.txt").as_deref(), Some("a.txt"));
+    }
+
+    #[test]
+    fn renames_cannot_collide() {
+        let mut changes = PendingChanges::default();
+        assert_eq!(changes.push(rename("a.txt", "b.txt"), &names()).unwrap_err(), "b.txt already exists");
+        assert_eq!(
+            changes.push(rename("a.txt", "docs/x.txt"), &names()).unwrap_err(),
+            "docs/x.txt already exists"
+        );
+        assert!(changes.is_empty());
+
+        // Freed once the other entry has moved away
+        changes.push(rename("b.txt", "c.txt"), &names()).unwrap();
+        changes.push(rename("a.txt", "b.txt"), &names()).unwrap();
+        assert_eq!(staged(&changes, "a.txt").as_deref(), Some("b.txt"));
+    }
+
+    #[test]
+    fn folders_cannot_move_into_themselves() {
+        let mut changes = PendingChanges::default();
+        assert_eq!(
+            changes.push(rename("docs/", "docs/sub/docs/"), &names()).unwrap_err(),
+            "Cannot move docs/ into itself"
+        );
+        assert_eq!(
+            changes.push(rename("docs/", "notes.txt"), &names()).unwrap_err(),
+            "A folder can only be renamed to a folder"
+        );
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn deleted_entries_cannot_be_renamed() {
+        let mut changes = PendingChanges::default();
+        changes.push(EntryChange::Delete("docs/".to_string()), &names()).unwrap();
+        assert_eq!(staged(&changes, "docs/sub/y.txt"), None);
+        assert_eq!(
+            changes.push(rename("docs/x.txt", "x.txt"), &names()).unwrap_err(),
+            "docs/x.txt is not in the archive"
+        );
+        assert_eq!(
+            changes.push(EntryChange::Delete("docs/".to_string()), &names()).unwrap_err(),
+            "docs/ is not in the archive"
+        );
+
+        // A deleted name can be reused
+        changes.push(rename("a.txt", "docs/x.txt"), &names()).unwrap();
+        assert_eq!(staged(&changes, "a.txt").as_deref(), Some("docs/x.txt"));
+    }
+
+    #[test]
+    fn undo_drops_the_newest_change() {
+        let mut changes = PendingChanges::default();
+        changes.push(rename("a.txt", "c.txt"), &names()).unwrap();
+        changes.push(EntryChange::Delete("b.txt".to_string()), &names()).unwrap();
+
+        assert!(matches!(changes.undo(), Some(EntryChange::Delete(path)) if path == "b.txt"));
+        assert_eq!(staged(&changes, "b.txt").as_deref(), Some("b.txt"));
+        assert_eq!(staged(&changes, "a.txt").as_deref(), Some("c.txt"));
+
+        changes.undo();
+        assert_eq!(staged(&changes, "a.txt").as_deref(), Some("a.txt"));
+        assert!(changes.undo().is_none());
+    }
+
+    #[test]
+    fn entry_paths_are_validated() {
+        assert!(validate_entry_path("a/b.txt").is_ok());
+        assert!(validate_entry_path("a/").is_ok());
+        for name in ["", "/", "/a", "a\\b", "a//b", "./a", "a/../b"] {
+            assert!(validate_entry_path(name).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::metadata::{read_entry_metadata, restore_metadata, RestorePolicy};
+use crate::safety::{link_escapes, link_leaves, passes_through_link};
+use crate::sparse::SparseWriter;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Longest link target accepted from an archive
+const MAX_LINK_TARGET: u64 = 4096;
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Extracts the named entries below `destination`, keeping their folder layout.
+/// Entries whose paths would escape `destination` are skipped, as are links
+/// leading outside it, directly or through other links; links are created
+/// after all files. Stored timestamps, permissions and ownership are restored
+/// as `policy` allows; extended attributes and ACLs only when `xattrs` is set.
+/// `progress` is called with (bytes written, total bytes, current entry).
+pub fn extract_entries(
+    archive_path: &Path,
+    names: &[String],
+    destination: &Path,
+    password: Option<&str>,
+    policy: RestorePolicy,
+    xattrs: bool,
+    mut progress: impl FnMut(u64, u64, &str),
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+    let mut headers = File::open(archive_path)?;
+
+    let mut indices = Vec::with_capacity(names.len());
+    let mut total_size = 0u64;
+    for name in names {
+        let Some(index) = archive.index_for_name(name) else {
+            continue;
+        };
+        total_size = total_size.saturating_add(archive.by_index_raw(index)?.size());
+        indices.push(index);
+    }
+
+    let mut written = 0u64;
+    let mut extracted = 0;
+    let mut buffer = [0; 8192];
+    // Directory times change as their contents are written, so they are set last
+    let mut directories = Vec::new();
+    // Created last so no file is ever written through a link from the archive
+    let mut links: Vec<(PathBuf, PathBuf, String)> = Vec::new();
+    for index in indices {
+        let mut entry = match password {
+            Some(password) => archive.by_index_decrypt(index, password.as_bytes())?,
+            None => archive.by_index(index)?,
+        };
+        let name = entry.name().to_string();
+        let Some(relative) = entry.enclosed_name() else {
+            tracing::warn!("Skipping {}: path escapes the destination folder", name);
+            continue;
+        };
+        if passes_through_link(destination, &relative) {
+            tracing::warn!("Skipping {}: a folder on its path is a symbolic link", name);
+            continue;
+        }
+        let output_path = destination.join(&relative);
+
+        if entry.unix_mode().is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+            let mut target = String::new();
+            (&mut entry).take(MAX_LINK_TARGET).read_to_string(&mut target)?;
+            if link_escapes(&relative, Path::new(&target)) {
+                tracing::warn!("Skipping link {} -> {}: it points outside the destination folder", name, target);
+                continue;
+            }
+            links.push((relative, PathBuf::from(target), name));
+            continue;
+        }
+
+        let metadata = if policy == RestorePolicy::Never && !xattrs {
+            None
+        } else {
+            let central_extra = entry.extra_data().unwrap_or_default();
+            read_entry_metadata(&mut headers, entry.header_start(), central_extra, entry.unix_mode(), entry.last_modified())
+                .inspect_err(|e| tracing::warn!("Cannot read metadata of {}: {}", name, e))
+                .ok()
+        };
+
+        if entry.is_dir() {
+            std::fs::create_dir_all(&output_path)?;
+            directories.extend(metadata.map(|metadata| (output_path, metadata)));
+            extracted += 1;
+            continue;
+        }
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let declared_size = entry.size();
+        let mut entry_written = 0u64;
+        if std::fs::symlink_metadata(&output_path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            // Replace the link rather than writing to wherever it points
+            std::fs::remove_file(&output_path)?;
+        }
+        // Runs of zeros become holes, so sparse files stay sparse
+        let mut output = SparseWriter::new(File::create(&output_path)?);
+        loop {
+            let n = entry.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            entry_written += n as u64;
+            if entry_written > declared_size {
+                // The entry inflates past the size it declared; never trust it
+                drop(output);
+                let _ = std::fs::remove_file(&output_path);
+                return Err(format!("{} expands beyond its declared size; extraction aborted", name).into());
+            }
+            output.write_all(&buffer[..n])?;
+            written += n as u64;
+            progress(written, total_size, &name);
+        }
+        output.finish()?;
+        if let Some(metadata) = metadata {
+            if let Err(e) = restore_metadata(&output_path, &metadata, policy, xattrs) {
+                tracing::warn!("Cannot restore metadata of {}: {}", name, e);
+            }
+        }
+        extracted += 1;
+    }
+
+    // Links can point through each other, so each one is checked against the
+    // links created before it rather than by its own target alone
+    let root = if links.is_empty() {
+        PathBuf::new()
+    } else {
+        std::fs::create_dir_all(destination)?;
+        destination.canonicalize()?
+    };
+    let mut created = Vec::new();
+    for (relative, target, name) in links {
+        if passes_through_link(destination, &relative) {
+            tracing::warn!("Skipping link {}: a folder on its path is a symbolic link", name);
+            continue;
+        }
+        let path = destination.join(&relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => std::fs::remove_file(&path)?,
+            Ok(_) => {
+                tracing::warn!("Skipping link {}: a file or folder with that name was extracted", name);
+                continue;
+            }
+            Err(_) => {}
+        }
+        if link_leaves(&root, &path, &target) {
+            tracing::warn!("Skipping link {} -> {}: it leads outside the destination folder", name, target.display());
+            continue;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&target, &path)?;
+        #[cfg(not(unix))]
+        std::fs::write(&path, target.to_string_lossy().as_bytes())?;
+        created.push((path, target, name));
+    }
+    // A link checked before the links it passes through existed may lead out
+    // once they are in place; removing one can change others, so repeat
+    #[cfg(unix)]
+    while let Some(index) = created.iter().position(|(path, target, _)| link_leaves(&root, path, target)) {
+        let (path, target, name) = created.remove(index);
+        tracing::warn!("Removing link {} -> {}: it leads outside the destination folder", name, target.display());
+        std::fs::remove_file(&path)?;
+    }
+    extracted += created.len();
+
+    for (path, metadata) in directories.iter().rev() {
+        if let Err(e) = restore_metadata(path, metadata, policy, xattrs) {
+            tracing::warn!("Cannot restore metadata of {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(extracted)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// `x` points at the extraction folder itself, so `d/a` -> `../x/..`
+    /// stays inside on paper but leads to its parent once `x` exists
+    fn extract_links(links: &[(&str, &str)]) -> (tempfile::TempDir, usize) {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("links.zip");
+        let mut writer = ZipWriter::new(File::create(&archive).unwrap());
+        writer.add_directory("d/", SimpleFileOptions::default()).unwrap();
+        for (name, target) in links {
+            writer.add_symlink(*name, *target, SimpleFileOptions::default()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let destination = dir.path().join("out");
+        let mut names = vec!["d/".to_string()];
+        names.extend(links.iter().map(|(name, _)| name.to_string()));
+        let extracted = extract_entries(&archive, &names, &destination, None, RestorePolicy::Never, false, |_, _, _| {}).unwrap();
+        (dir, extracted)
+    }
+
+    #[test]
+    fn chained_links_cannot_leave_the_destination() {
+        for links in [[("x", "."), ("d/a", "../x/..")], [("d/a", "../x/.."), ("x", ".")]] {
+            let (dir, extracted) = extract_links(&links);
+            let out = dir.path().join("out");
+            assert!(std::fs::symlink_metadata(out.join("x")).unwrap().file_type().is_symlink());
+            assert!(std::fs::symlink_metadata(out.join("d/a")).is_err(), "{:?}", links);
+            assert_eq!(extracted, 2);
+        }
+    }
+
+    #[test]
+    fn links_inside_the_destination_are_kept() {
+        let (dir, extracted) = extract_links(&[("x", "d"), ("d/a", "../x/b"), ("c", "x/a")]);
+        let out = dir.path().join("out");
+        assert_eq!(std::fs::read_link(out.join("c")).unwrap(), Path::new("x/a"));
+        assert_eq!(extracted, 4);
+    }
+}
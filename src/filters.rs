@@ -0,0 +1,253 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Ignore files read from each folder when `use_ignore_files` is set
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+/// Git's own folder, always skipped when ignore files are honoured
+const GIT_DIR: &str = ".git";
+/// Repository-wide patterns, read in folders holding a `.git` folder
+const GIT_EXCLUDE: &str = ".git/info/exclude";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Which files of the compression list end up in the archive. Patterns are
+/// one per line in .gitignore syntax and match entry names in the archive,
+/// e.g. `target/`, `*.log` or `/repo/build`.
+#[derive(Clone, Default)]
+pub struct InputFilters {
+    /// When not empty, only files matching one of these are added
+    pub include: String,
+    pub exclude: String,
+    /// Honour `.gitignore`, `.ignore` and `.git/info/exclude` files inside
+    /// added folders, and leave `.git` folders out
+    pub use_ignore_files: bool,
+    pub skip_hidden: bool,
+    /// Skip files larger than this many bytes
+    pub max_size: Option<u64>,
+    /// Skip files not modified within this many days
+    pub max_age_days: Option<u64>,
+}
+
+impl InputFilters {
+    pub fn compile(&self) -> Result<FilterSet, ignore::Error> {
+        Ok(FilterSet {
+            include: parse_rules(&self.include)?,
+            exclude: parse_rules(&self.exclude)?,
+            use_ignore_files: self.use_ignore_files,
+            skip_hidden: self.skip_hidden,
+            max_size: self.max_size,
+            max_age: self.max_age_days.map(|days| Duration::from_secs(days * SECONDS_PER_DAY)),
+            now: SystemTime::now(),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.include.trim().is_empty()
+            || !self.exclude.trim().is_empty()
+            || self.use_ignore_files
+            || self.skip_hidden
+            || self.max_size.is_some()
+            || self.max_age_days.is_some()
+    }
+}
+
+/// Parses .gitignore-style lines. Patterns without a slash match at any depth;
+/// the others are anchored to the start of the entry name.
+fn parse_rules(text: &str) -> Result<Gitignore, ignore::Error> {
+    let mut builder = GitignoreBuilder::new("");
+    for line in text.lines() {
+        builder.add_line(None, line)?;
+    }
+    builder.build()
+}
+
+/// Rules from the ignore files of one folder.
+pub struct IgnoreFile {
+    /// Entry name of the folder, ending in '/'
+    base: String,
+    rules: Gitignore,
+}
+
+/// Compiled [`InputFilters`], applied while folders are walked.
+pub struct FilterSet {
+    include: Gitignore,
+    exclude: Gitignore,
+    use_ignore_files: bool,
+    skip_hidden: bool,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    now: SystemTime,
+}
+
+impl Default for FilterSet {
+    /// Admits everything
+    fn default() -> Self {
+        Self {
+            include: Gitignore::empty(),
+            exclude: Gitignore::empty(),
+            use_ignore_files: false,
+            skip_hidden: false,
+            max_size: None,
+            max_age: None,
+            now: SystemTime::now(),
+        }
+    }
+}
+
+impl FilterSet {
+    /// True if an include list is set, in which case folders left without any
+    /// included file are dropped.
+    pub fn has_includes(&self) -> bool {
+        !self.include.is_empty()
+    }
+
+    /// Decides whether the entry `name` (without a trailing '/') is left out.
+    /// `ignores` are the ignore files of the folders above it. Paths added
+    /// explicitly (`top_level`) are never treated as hidden or ignored.
+    pub fn excludes(&self, ignores: &[IgnoreFile], name: &str, metadata: &Metadata, top_level: bool) -> bool {
+        let is_dir = metadata.is_dir();
+        if !top_level {
+            let file_name = name.rsplit('/').next().unwrap_or(name);
+            if self.skip_hidden && file_name.starts_with('.') {
+                return true;
+            }
+            if self.use_ignore_files && file_name == GIT_DIR {
+                return true;
+            }
+            // Deeper ignore files override the ones above them
+            let ignored = ignores.iter().fold(false, |ignored, file| {
+                let Some(relative) = name.strip_prefix(file.base.as_str()) else {
+                    return ignored;
+                };
+                match file.rules.matched(relative, is_dir) {
+                    Match::None => ignored,
+                    Match::Ignore(_) => true,
+                    Match::Whitelist(_) => false,
+                }
+            });
+            if ignored {
+                return true;
+            }
+        }
+        if self.exclude.matched(name, is_dir).is_ignore() {
+            return true;
+        }
+        if is_dir {
+            return false;
+        }
+
+        // A folder in the include list includes everything below it
+        if self.has_includes() && !self.include.matched_path_or_any_parents(name, false).is_ignore() {
+            return true;
+        }
+        if self.max_size.is_some_and(|max| metadata.len() > max) {
+            return true;
+        }
+        self.max_age.is_some_and(|max_age| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| self.now.duration_since(modified).ok())
+                .is_some_and(|age| age > max_age)
+        })
+    }
+
+    /// Reads the ignore files of the folder `dir`, whose entry name is `base`.
+    pub fn ignore_file(&self, dir: &Path, base: &str) -> Option<IgnoreFile> {
+        if !self.use_ignore_files {
+            return None;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        let git_exclude = dir.join(GIT_DIR).is_dir().then(|| dir.join(GIT_EXCLUDE));
+        let files = git_exclude.into_iter().chain(IGNORE_FILES.iter().map(|file_name| dir.join(file_name)));
+        for path in files.filter(|path| path.is_file()) {
+            if let Some(e) = builder.add(&path) {
+                tracing::warn!("Skipping some patterns in {}: {}", path.display(), e);
+            }
+        }
+        match builder.build() {
+            Ok(rules) if rules.is_empty() => None,
+            Ok(rules) => Some(IgnoreFile { base: base.to_string(), rules }),
+            Err(e) => {
+                tracing::warn!("Ignoring the ignore files in {}: {}", dir.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Parses a size such as `500`, `64K`, `10M` or `2G` into bytes.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, multiplier) = match text.char_indices().last()? {
+        (i, 'k' | 'K') => (&text[..i], 1 << 10),
+        (i, 'm' | 'M') => (&text[..i], 1 << 20),
+        (i, 'g' | 'G') => (&text[..i], 1 << 30),
+        _ => (text, 1),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewrite::{collect_new_entries, LinkPolicy};
+
+    fn collect(source: &Path, filters: InputFilters) -> Vec<String> {
+        let filters = filters.compile().unwrap();
+        collect_new_entries(&[source.to_path_buf()], "", false, LinkPolicy::Store, &filters)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    #[test]
+    fn ignore_files_follow_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        for folder in [".git/info", "src", "build"] {
+            std::fs::create_dir_all(repo.join(folder)).unwrap();
+        }
+        for file in [".git/HEAD", "src/main.rs", "src/keep.log", "src/notes.tmp", "build/out.o", "#hash", "secret.env"] {
+            std::fs::write(repo.join(file), b"x").unwrap();
+        }
+        std::fs::write(repo.join(".git/info/exclude"), "secret.env\n").unwrap();
+        std::fs::write(repo.join(".gitignore"), "build/\n*.log\n\\#hash\n").unwrap();
+        std::fs::write(repo.join("src/.ignore"), "!keep.log\n*.tmp\n").unwrap();
+
+        let names = collect(&repo, InputFilters { use_ignore_files: true, ..Default::default() });
+        assert_eq!(names, ["repo/", "repo/.gitignore", "repo/src/", "repo/src/.ignore", "repo/src/keep.log", "repo/src/main.rs"]);
+
+        // Without ignore files everything is kept, .git included
+        let names = collect(&repo, InputFilters::default());
+        assert!(names.contains(&"repo/.git/HEAD".to_string()));
+        assert!(names.contains(&"repo/#hash".to_string()));
+    }
+
+    #[test]
+    fn include_and_exclude_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("p");
+        std::fs::create_dir_all(root.join("docs/img")).unwrap();
+        for file in ["a.rs", "b.txt", "docs/guide.md", "docs/img/logo.png"] {
+            std::fs::write(root.join(file), b"x").unwrap();
+        }
+
+        let names = collect(&root, InputFilters { include: "*.rs\np/docs/\n".to_string(), ..Default::default() });
+        assert_eq!(names, ["p/", "p/a.rs", "p/docs/", "p/docs/guide.md", "p/docs/img/", "p/docs/img/logo.png"]);
+
+        let names = collect(&root, InputFilters { exclude: "img/\n/p/b.txt\n".to_string(), ..Default::default() });
+        assert_eq!(names, ["p/", "p/a.rs", "p/docs/", "p/docs/guide.md"]);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("500"), Some(500));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size(" 2g "), Some(2 << 30));
+        assert_eq!(parse_size("x"), None);
+    }
+}
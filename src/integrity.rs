@@ -0,0 +1,261 @@
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zip::ZipArchive;
+
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+
+#[derive(Clone)]
+pub struct EntryTestResult {
+    pub name: String,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct ArchiveTestReport {
+    pub entries: Vec<EntryTestResult>,
+    pub problems: Vec<String>,
+}
+
+impl ArchiveTestReport {
+    pub fn failed_count(&self) -> usize {
+        self.entries.iter().filter(|entry| entry.error.is_some()).count()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty() && self.failed_count() == 0
+    }
+}
+
+/// Reads entry names straight from the central directory. `ZipArchive` keys its
+/// entries by name, so duplicates are only visible at this level.
+pub fn central_directory_names(path: &Path, directory_start: u64) -> std::io::Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    reader.seek(SeekFrom::Start(directory_start))?;
+
+    let mut names = Vec::new();
+    let mut header = [0u8; 46];
+    loop {
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        if u32::from_le_bytes(header[0..4].try_into().unwrap()) != CENTRAL_HEADER_SIGNATURE {
+            break;
+        }
+        let name_len = u16::from_le_bytes([header[28], header[29]]) as usize;
+        let extra_len = u16::from_le_bytes([header[30], header[31]]) as i64;
+        let comment_len = u16::from_le_bytes([header[32], header[33]]) as i64;
+
+        let mut name = vec![0u8; name_len];
+        reader.read_exact(&mut name)?;
+        names.push(String::from_utf8_lossy(&name).into_owned());
+        reader.seek_relative(extra_len + comment_len)?;
+    }
+    Ok(names)
+}
+
+/// Returns pairs of entry names whose stored byte ranges overlap.
+pub fn find_overlapping_entries<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Vec<(String, String)> {
+    let mut ranges = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        if let Ok(file) = archive.by_index_raw(i) {
+            ranges.push((file.header_start(), file.data_start() + file.compressed_size(), file.name().to_string()));
+        }
+    }
+    ranges.sort_by_key(|(start, _, _)| *start);
+
+    let mut overlaps = Vec::new();
+    let mut furthest: Option<(u64, &str)> = None;
+    for (start, end, name) in &ranges {
+        if let Some((prev_end, prev_name)) = furthest {
+            if *start < prev_end {
+                overlaps.push((prev_name.to_string(), name.clone()));
+            }
+        }
+        if furthest.is_none_or(|(prev_end, _)| *end > prev_end) {
+            furthest = Some((*end, name));
+        }
+    }
+    overlaps
+}
+
+/// Decompresses every entry to a null sink in parallel. CRC32 and AES
+/// authentication codes are checked by the zip reader once an entry is fully read.
+pub fn test_archive(
+    path: &Path,
+    password: Option<&str>,
+    progress: impl Fn(usize, usize) + Sync,
+) -> Result<ArchiveTestReport, Box<dyn std::error::Error>> {
+    let archive_len = std::fs::metadata(path)?.len();
+    let mut archive = ZipArchive::new(File::open(path)?)
+        .map_err(|e| format!("Cannot read central directory (archive may be truncated): {}", e))?;
+    let total = archive.len();
+
+    let mut report = ArchiveTestReport::default();
+
+    let names = central_directory_names(path, archive.central_directory_start())?;
+    let mut seen = HashSet::new();
+    for name in &names {
+        if !seen.insert(name.as_str()) {
+            report.problems.push(format!("Duplicate entry name: {}", name));
+        }
+    }
+
+    for (first, second) in find_overlapping_entries(&mut archive) {
+        report.problems.push(format!("Entries overlap: {} and {}", first, second));
+    }
+
+    let done = AtomicUsize::new(0);
+    report.entries = (0..total)
+        .into_par_iter()
+        .map_init(
+            || ZipArchive::new(File::open(path).ok()?).ok(),
+            |archive, index| {
+                let result = test_entry(archive.as_mut(), index, password, archive_len);
+                progress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+                result
+            },
+        )
+        .collect();
+
+    Ok(report)
+}
+
+fn test_entry(
+    archive: Option<&mut ZipArchive<File>>,
+    index: usize,
+    password: Option<&str>,
+    archive_len: u64,
+) -> EntryTestResult {
+    let Some(archive) = archive else {
+        return EntryTestResult {
+            name: format!("#{}", index),
+            size: 0,
+            compressed_size: 0,
+            error: Some("Could not open archive".to_string()),
+        };
+    };
+
+    let name = archive.name_for_index(index).unwrap_or_default().to_string();
+    let file = match password {
+        Some(password) => archive.by_index_decrypt(index, password.as_bytes()),
+        None => archive.by_index(index),
+    };
+    let mut file = match file {
+        Ok(file) => file,
+        Err(e) => {
+            return EntryTestResult { name, size: 0, compressed_size: 0, error: Some(e.to_string()) };
+        }
+    };
+
+    let size = file.size();
+    let compressed_size = file.compressed_size();
+    let error = if file.data_start() + compressed_size > archive_len {
+        Some("Entry data extends past the end of the archive (truncated)".to_string())
+    } else {
+        match std::io::copy(&mut file, &mut std::io::sink()) {
+            Ok(written) if written != size => Some(format!("Expected {} bytes, got {}", size, written)),
+            Ok(_) => None,
+            Err(e) => Some(e.to_string()),
+        }
+    };
+
+    EntryTestResult { name, size, compressed_size, error }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// A stored archive of `entries` and the offsets of its central headers.
+    fn archive_with(entries: &[(&str, &[u8])]) -> (Vec<u8>, Vec<usize>) {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let mut offset = ZipArchive::new(Cursor::new(&bytes)).unwrap().central_directory_start() as usize;
+        let mut headers = Vec::new();
+        for _ in entries {
+            headers.push(offset);
+            let field = |at: usize| u16::from_le_bytes([bytes[offset + at], bytes[offset + at + 1]]) as usize;
+            offset += 46 + field(28) + field(30) + field(32);
+        }
+        (bytes, headers)
+    }
+
+    fn write_temp(bytes: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn intact_archives_pass() {
+        let (bytes, _) = archive_with(&[("a.txt", b"hello"), ("b.txt", b"world")]);
+        let file = write_temp(&bytes);
+        let report = test_archive(file.path(), None, |_, _| {}).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.entries.len(), 2);
+    }
+
+    #[test]
+    fn duplicate_names_are_listed() {
+        let (mut bytes, headers) = archive_with(&[("a.txt", b"hello"), ("b.txt", b"world")]);
+        // Rename b.txt to a.txt in the central directory only
+        bytes[headers[1] + 46] = b'a';
+        let file = write_temp(&bytes);
+        let names = central_directory_names(file.path(), headers[0] as u64).unwrap();
+        assert_eq!(names, ["a.txt", "a.txt"]);
+
+        let report = test_archive(file.path(), None, |_, _| {}).unwrap();
+        assert_eq!(report.problems, ["Duplicate entry name: a.txt"]);
+    }
+
+    #[test]
+    fn overlapping_entries_are_found() {
+        let (mut bytes, headers) = archive_with(&[("a.txt", b"hello"), ("b.txt", b"world")]);
+        assert!(find_overlapping_entries(&mut ZipArchive::new(Cursor::new(&bytes)).unwrap()).is_empty());
+
+        // Point b.txt at the local header of a.txt, as overlapping-entry bombs do
+        bytes[headers[1] + 42..headers[1] + 46].copy_from_slice(&0u32.to_le_bytes());
+        let overlaps = find_overlapping_entries(&mut ZipArchive::new(Cursor::new(&bytes)).unwrap());
+        assert_eq!(overlaps, [("a.txt".to_string(), "b.txt".to_string())]);
+
+        let file = write_temp(&bytes);
+        let report = test_archive(file.path(), None, |_, _| {}).unwrap();
+        assert_eq!(report.problems, ["Entries overlap: a.txt and b.txt"]);
+    }
+
+    #[test]
+    fn truncated_entries_fail() {
+        let (mut bytes, headers) = archive_with(&[("a.txt", b"hello")]);
+        // Declare far more data than the file holds
+        bytes[headers[0] + 20..headers[0] + 28].copy_from_slice(&[0, 0, 1, 0, 0, 0, 1, 0]);
+        let file = write_temp(&bytes);
+        let report = test_archive(file.path(), None, |_, _| {}).unwrap();
+        assert_eq!(report.failed_count(), 1);
+        assert!(report.entries[0].error.as_deref().unwrap().contains("truncated"));
+    }
+
+    #[test]
+    fn damaged_data_fails_its_checksum() {
+        let (mut bytes, _) = archive_with(&[("a.txt", b"hello")]);
+        bytes[30 + "a.txt".len()] ^= 0xff;
+        let file = write_temp(&bytes);
+        let report = test_archive(file.path(), None, |_, _| {}).unwrap();
+        assert_eq!(report.failed_count(), 1);
+        assert!(!report.is_ok());
+    }
+}
@@ -0,0 +1,432 @@
+use crate::models::ArchiveFile;
+use crate::tree::{ArchiveTree, ROOT};
+use globset::{GlobBuilder, GlobMatcher};
+use rayon::prelude::*;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ContentsView {
+    Tree,
+    Table,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Substring,
+    Glob,
+    Regex,
+}
+
+impl FilterMode {
+    pub fn title(self) -> &'static str {
+        match self {
+            FilterMode::Substring => "Text",
+            FilterMode::Glob => "Glob",
+            FilterMode::Regex => "Regex",
+        }
+    }
+}
+
+/// A compiled entry-name filter. Substring matching ignores ASCII case; globs
+/// without a '/' match the file name in any folder, globs with one match the full path.
+pub enum EntryFilter {
+    Substring(String),
+    Glob { matcher: GlobMatcher, full_path: bool },
+    Regex(Regex),
+}
+
+impl EntryFilter {
+    pub fn new(mode: FilterMode, pattern: &str) -> Result<Self, String> {
+        match mode {
+            FilterMode::Substring => Ok(EntryFilter::Substring(pattern.to_ascii_lowercase())),
+            FilterMode::Glob => {
+                let full_path = pattern.contains('/');
+                let glob = GlobBuilder::new(pattern)
+                    .literal_separator(full_path)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                Ok(EntryFilter::Glob {
+                    matcher: glob.compile_matcher(),
+                    full_path,
+                })
+            }
+            FilterMode::Regex => Regex::new(pattern).map(EntryFilter::Regex).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            EntryFilter::Substring(needle) => name.to_ascii_lowercase().contains(needle.as_str()),
+            EntryFilter::Glob { matcher, full_path } => {
+                let name = name.trim_end_matches('/');
+                if *full_path {
+                    matcher.is_match(name)
+                } else {
+                    matcher.is_match(name.rsplit('/').next().unwrap_or(name))
+                }
+            }
+            EntryFilter::Regex(regex) => regex.is_match(name),
+        }
+    }
+
+    /// Byte ranges of `text` to highlight. Globs match as a whole, so a
+    /// matching text is highlighted entirely.
+    pub fn highlight_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        match self {
+            EntryFilter::Substring(needle) if !needle.is_empty() => text
+                .to_ascii_lowercase()
+                .match_indices(needle.as_str())
+                .map(|(start, found)| start..start + found.len())
+                .collect(),
+            EntryFilter::Substring(_) => Vec::new(),
+            EntryFilter::Glob { .. } => {
+                if self.is_match(text) {
+                    std::iter::once(0..text.len()).collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            EntryFilter::Regex(regex) => regex
+                .find_iter(text)
+                .filter(|found| !found.is_empty())
+                .map(|found| found.range())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortColumn {
+    Name,
+    Size,
+    CompressedSize,
+    Ratio,
+    Modified,
+    Crc32,
+    Compression,
+    Encrypted,
+    Attributes,
+    Comment,
+}
+
+impl SortColumn {
+    pub const ALL: [SortColumn; 10] = [
+        SortColumn::Name,
+        SortColumn::Size,
+        SortColumn::CompressedSize,
+        SortColumn::Ratio,
+        SortColumn::Modified,
+        SortColumn::Crc32,
+        SortColumn::Compression,
+        SortColumn::Encrypted,
+        SortColumn::Attributes,
+        SortColumn::Comment,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            SortColumn::Name => "Name",
+            SortColumn::Size => "Size",
+            SortColumn::CompressedSize => "Packed",
+            SortColumn::Ratio => "Ratio",
+            SortColumn::Modified => "Modified",
+            SortColumn::Crc32 => "CRC32",
+            SortColumn::Compression => "Method",
+            SortColumn::Encrypted => "Encrypted",
+            SortColumn::Attributes => "Attributes",
+            SortColumn::Comment => "Comment",
+        }
+    }
+
+    fn compare(self, a: &ArchiveFile, b: &ArchiveFile) -> Ordering {
+        match self {
+            SortColumn::Name => a.name.cmp(&b.name),
+            SortColumn::Size => a.size.cmp(&b.size),
+            SortColumn::CompressedSize => a.compressed_size.cmp(&b.compressed_size),
+            SortColumn::Ratio => a.ratio().total_cmp(&b.ratio()),
+            SortColumn::Modified => a.modified.cmp(&b.modified),
+            SortColumn::Crc32 => a.crc32.cmp(&b.crc32),
+            SortColumn::Compression => a.compression.cmp(&b.compression),
+            SortColumn::Encrypted => a.encrypted.cmp(&b.encrypted),
+            SortColumn::Attributes => a.unix_mode.cmp(&b.unix_mode),
+            SortColumn::Comment => a.comment.cmp(&b.comment),
+        }
+    }
+}
+
+/// Returns indices into `files` ordered by `column`, ties broken by name.
+pub fn sorted_order(files: &[ArchiveFile], column: SortColumn, ascending: bool) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by(|&a, &b| {
+        let ordering = column
+            .compare(&files[a], &files[b])
+            .then_with(|| files[a].name.cmp(&files[b].name));
+        if ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    });
+    order
+}
+
+/// Everything the contents view needs to draw an archive, precomputed so each
+/// frame only touches the rows that are on screen. The entry list is shared,
+/// and the tree and table are views over it by index.
+pub struct ContentsIndex {
+    pub files: Arc<Vec<ArchiveFile>>,
+    pub tree: ArchiveTree,
+    expanded: HashSet<usize>,
+    tree_rows: Vec<(usize, usize)>,
+    sorted: Vec<usize>,
+    table_rows: Vec<usize>,
+    filter: Option<EntryFilter>,
+    /// Per tree node: whether it matches the filter or contains a match
+    visible: Vec<bool>,
+    match_count: usize,
+}
+
+impl ContentsIndex {
+    pub fn new(files: Arc<Vec<ArchiveFile>>, column: SortColumn, ascending: bool) -> Self {
+        let tree = ArchiveTree::build(&files);
+        let expanded = HashSet::new();
+        let tree_rows = tree.visible_rows(&expanded);
+        let sorted = sorted_order(&files, column, ascending);
+        let match_count = files.len();
+        Self {
+            files,
+            tree,
+            expanded,
+            tree_rows,
+            table_rows: sorted.clone(),
+            sorted,
+            filter: None,
+            visible: Vec::new(),
+            match_count,
+        }
+    }
+
+    pub fn filter(&self) -> Option<&EntryFilter> {
+        self.filter.as_ref()
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.match_count
+    }
+
+    /// File indices of every entry matching the current filter
+    pub fn matching_files(&self) -> Vec<usize> {
+        match &self.filter {
+            Some(filter) => (0..self.files.len())
+                .filter(|&index| filter.is_match(&self.files[index].name))
+                .collect(),
+            None => (0..self.files.len()).collect(),
+        }
+    }
+
+    /// Applies a filter to both views. Matching runs in parallel so large
+    /// archives stay responsive while typing.
+    pub fn set_filter(&mut self, filter: Option<EntryFilter>) {
+        self.filter = filter;
+        match &self.filter {
+            Some(filter) => {
+                let matches: Vec<bool> = self.files.par_iter().map(|file| filter.is_match(&file.name)).collect();
+                self.match_count = matches.iter().filter(|&&matched| matched).count();
+                self.table_rows = self.sorted.iter().copied().filter(|&index| matches[index]).collect();
+
+                // Mark matching nodes, then propagate to their ancestors; children
+                // always come after parents in the arena
+                self.visible = self
+                    .tree
+                    .nodes
+                    .iter()
+                    .map(|node| node.file_index.is_some_and(|index| matches[index]))
+                    .collect();
+                for node in (1..self.tree.nodes.len()).rev() {
+                    if self.visible[node] {
+                        let parent = self.tree.nodes[node].parent;
+                        self.visible[parent] = true;
+                    }
+                }
+            }
+            None => {
+                self.match_count = self.files.len();
+                self.table_rows = self.sorted.clone();
+                self.visible.clear();
+            }
+        }
+        self.refresh_tree_rows();
+    }
+
+    fn refresh_tree_rows(&mut self) {
+        if self.filter.is_none() {
+            self.tree_rows = self.tree.visible_rows(&self.expanded);
+            return;
+        }
+
+        // While filtering, every folder on the way to a match is shown open
+        let mut rows = Vec::new();
+        let mut stack: Vec<(usize, usize)> = self.tree.nodes[ROOT]
+            .children
+            .iter()
+            .rev()
+            .filter(|&&child| self.visible[child])
+            .map(|&child| (child, 0))
+            .collect();
+        while let Some((node, depth)) = stack.pop() {
+            rows.push((node, depth));
+            stack.extend(
+                self.tree.nodes[node]
+                    .children
+                    .iter()
+                    .rev()
+                    .filter(|&&child| self.visible[child])
+                    .map(|&child| (child, depth + 1)),
+            );
+        }
+        self.tree_rows = rows;
+    }
+
+    /// Visible tree rows as (node, depth)
+    pub fn tree_rows(&self) -> &[(usize, usize)] {
+        &self.tree_rows
+    }
+
+    /// Table rows as indices into `files`, in display order
+    pub fn table_rows(&self) -> &[usize] {
+        &self.table_rows
+    }
+
+    pub fn is_expanded(&self, node: usize) -> bool {
+        self.filter.is_some() || self.expanded.contains(&node)
+    }
+
+    pub fn toggle(&mut self, node: usize) {
+        if !self.expanded.remove(&node) {
+            self.expanded.insert(node);
+        }
+        self.refresh_tree_rows();
+    }
+
+    pub fn expand_all(&mut self) {
+        self.expanded.extend(self.tree.directories());
+        self.refresh_tree_rows();
+    }
+
+    pub fn collapse_all(&mut self) {
+        self.expanded.clear();
+        self.refresh_tree_rows();
+    }
+
+    pub fn sort(&mut self, column: SortColumn, ascending: bool) {
+        self.sorted = sorted_order(&self.files, column, ascending);
+        let filter = self.filter.take();
+        self.set_filter(filter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: u64) -> ArchiveFile {
+        ArchiveFile {
+            name: name.to_string(),
+            is_directory: name.ends_with('/'),
+            size,
+            compressed_size: size,
+            modified: None,
+            crc32: 0,
+            compression: "Stored".to_string(),
+            encrypted: false,
+            unix_mode: None,
+            comment: String::new(),
+            has_xattrs: false,
+        }
+    }
+
+    fn glob(pattern: &str) -> EntryFilter {
+        EntryFilter::new(FilterMode::Glob, pattern).unwrap()
+    }
+
+    #[test]
+    fn globs_without_a_slash_match_the_file_name() {
+        let filter = glob("*.txt");
+        assert!(filter.is_match("c.txt"));
+        assert!(filter.is_match("a/b/c.txt"));
+        assert!(!filter.is_match("a/c.txt.bak"));
+        assert!(!filter.is_match("a.txt/c.log"));
+        assert!(glob("doc*").is_match("src/docs/"));
+    }
+
+    #[test]
+    fn globs_with_a_slash_match_the_full_path() {
+        let filter = glob("a/*.txt");
+        assert!(filter.is_match("a/c.txt"));
+        assert!(!filter.is_match("a/b/c.txt"));
+        assert!(!filter.is_match("b/a/c.txt"));
+        assert!(glob("a/**/*.txt").is_match("a/b/c.txt"));
+    }
+
+    #[test]
+    fn substrings_ignore_case_and_highlight_every_occurrence() {
+        let filter = EntryFilter::new(FilterMode::Substring, "Report").unwrap();
+        assert!(filter.is_match("docs/REPORT.pdf"));
+        assert_eq!(filter.highlight_ranges("report_REPORT.txt"), [0..6, 7..13]);
+        assert!(filter.highlight_ranges("notes.txt").is_empty());
+
+        let empty = EntryFilter::new(FilterMode::Substring, "").unwrap();
+        assert!(empty.is_match("anything"));
+        assert!(empty.highlight_ranges("anything").is_empty());
+    }
+
+    #[test]
+    fn globs_and_regexes_highlight_what_they_match() {
+        assert_eq!(glob("*.txt").highlight_ranges("c.txt"), vec![0..5]);
+        assert!(glob("*.txt").highlight_ranges("c.log").is_empty());
+
+        let regex = EntryFilter::new(FilterMode::Regex, "[0-9]*").unwrap();
+        assert_eq!(regex.highlight_ranges("a1b22"), [1..2, 3..5]);
+        assert!(EntryFilter::new(FilterMode::Regex, "(").is_err());
+    }
+
+    #[test]
+    fn filtering_keeps_the_sort_order() {
+        let files = vec![file("a/", 0), file("a/x.txt", 3), file("b.log", 1), file("c.txt", 2)];
+        let mut index = ContentsIndex::new(Arc::new(files), SortColumn::Size, true);
+        assert_eq!(index.table_rows(), [0, 2, 3, 1]);
+
+        index.set_filter(Some(glob("*.txt")));
+        assert_eq!(index.match_count(), 2);
+        assert_eq!(index.table_rows(), [3, 1]);
+        assert_eq!(index.matching_files(), [1, 3]);
+
+        index.sort(SortColumn::Size, false);
+        assert_eq!(index.table_rows(), [1, 3]);
+        assert!(index.filter().is_some());
+
+        index.set_filter(None);
+        assert_eq!(index.table_rows(), [1, 3, 2, 0]);
+        assert_eq!(index.match_count(), 4);
+    }
+
+    #[test]
+    fn filtering_opens_the_folders_leading_to_matches() {
+        let files = vec![file("a/x.txt", 1), file("a/y.log", 1), file("b/z.log", 1), file("c.txt", 1)];
+        let mut index = ContentsIndex::new(Arc::new(files), SortColumn::Name, true);
+        let path = |index: &ContentsIndex, node: usize| index.tree.nodes[node].path.clone();
+        assert_eq!(index.tree_rows().len(), 3);
+
+        index.set_filter(Some(glob("*.txt")));
+        let rows: Vec<(String, usize)> = index.tree_rows().iter().map(|&(node, depth)| (path(&index, node), depth)).collect();
+        assert_eq!(rows, [("a/".to_string(), 0), ("a/x.txt".to_string(), 1), ("c.txt".to_string(), 0)]);
+        assert!(index.is_expanded(index.tree_rows()[0].0));
+
+        index.set_filter(None);
+        assert_eq!(index.tree_rows().len(), 3);
+    }
+}
@@ -1,8 +1,30 @@
 mod app;
+mod cli;
+mod edits;
+mod extract;
+mod filters;
+mod integrity;
+mod listing;
+mod metadata;
 mod models;
 mod ui;
 mod utils;
 mod parallel;
+mod preview;
+mod reader;
+mod recovery;
+mod rewrite;
+mod safety;
+mod search;
+mod sevenzip;
+mod sfx;
+mod signing;
+mod sparse;
+mod staging;
+mod tarball;
+mod tree;
+mod update;
+mod volumes;
 
 use app::ArchiveManager;
 use std::panic;
@@ -16,6 +38,11 @@ fn main() -> Result<(), eframe::Error> {
     // Initialize logging with reasonable defaults
     tracing_subscriber::fmt::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     // Set up custom panic hook
     let original_hook = panic::take_hook();
     panic::set_hook(Box::new(move |panic_info| {
@@ -39,7 +66,10 @@ fn main() -> Result<(), eframe::Error> {
     match eframe::run_native(
         "Archive Manager",
         options,
-        Box::new(|_| Ok(Box::<ArchiveManager>::default())),
+        Box::new(|cc| {
+            egui_extras::install_image_loaders(&cc.egui_ctx);
+            Ok(Box::<ArchiveManager>::default())
+        }),
     ) {
         Ok(_) => {
             info!("Application terminated normally");
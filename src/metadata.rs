@@ -0,0 +1,522 @@
+use crate::utils::{u16_at, u32_at};
+use std::fs::{File, FileTimes, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zip::write::FullFileOptions;
+
+const EXTENDED_TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+/// Info-ZIP "ux" field holding the owner's uid and gid
+const UNIX_OWNER_EXTRA_ID: u16 = 0x7875;
+/// Extended attributes, including POSIX ACLs (`system.posix_acl_*`) and SELinux
+/// labels (`security.selinux`). There is no standard ZIP field for them; this
+/// one is private to this program and ignored by other readers.
+const XATTR_EXTRA_ID: u16 = 0x7861;
+const XATTR_FORMAT_VERSION: u8 = 1;
+/// Leaves room in the 64 KiB extra area for the other fields
+const MAX_XATTR_FIELD: usize = 60 * 1024;
+const TIMESTAMP_MODIFIED: u8 = 0x01;
+const TIMESTAMP_ACCESSED: u8 = 0x02;
+/// 1980-01-01 00:00 UTC, the default date of ZIP entries
+const DOS_EPOCH: u64 = 315_532_800;
+
+/// What extraction restores from the metadata stored with each entry.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum RestorePolicy {
+    Never,
+    #[default]
+    Timestamps,
+    /// Timestamps and permission bits, plus ownership when running as root
+    Everything,
+}
+
+impl RestorePolicy {
+    pub const ALL: [RestorePolicy; 3] = [RestorePolicy::Never, RestorePolicy::Timestamps, RestorePolicy::Everything];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            RestorePolicy::Never => "Never",
+            RestorePolicy::Timestamps => "Timestamps only",
+            RestorePolicy::Everything => "Everything (ownership as root)",
+        }
+    }
+}
+
+/// Metadata recorded for one entry. Fields are `None` when the archive
+/// does not carry them.
+#[derive(Clone, Default)]
+pub struct EntryMetadata {
+    pub mode: Option<u32>,
+    pub modified: Option<i64>,
+    pub accessed: Option<i64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Extended attribute names and values
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Settings for archives that come out identical byte for byte whenever their
+/// inputs are the same: one timestamp for every entry, permissions reduced to
+/// 0o644/0o755, and no extra fields.
+#[derive(Clone, Copy)]
+pub struct Reproducible {
+    pub modified: zip::DateTime,
+    /// The same time in seconds since 1970, for tar and 7z entries
+    pub unix_time: u64,
+}
+
+impl Reproducible {
+    /// Uses `SOURCE_DATE_EPOCH` when it is set, otherwise 1980-01-01 00:00,
+    /// the earliest time ZIP can store.
+    pub fn from_env() -> Self {
+        let parsed = std::env::var("SOURCE_DATE_EPOCH").ok().map(|value| {
+            let seconds = value.trim().parse::<u64>().ok();
+            let time = seconds.and_then(|seconds| zip_datetime(UNIX_EPOCH + Duration::from_secs(seconds)));
+            if time.is_none() {
+                tracing::warn!("Ignoring SOURCE_DATE_EPOCH={}: not a time ZIP can store", value);
+            }
+            seconds.zip(time)
+        });
+        match parsed.flatten() {
+            Some((unix_time, modified)) => Self { modified, unix_time },
+            None => Self { modified: zip::DateTime::default(), unix_time: DOS_EPOCH },
+        }
+    }
+
+    pub fn system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.unix_time)
+    }
+
+    pub fn file_options(&self, metadata: &Metadata) -> FullFileOptions<'static> {
+        #[cfg(unix)]
+        let executable = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o111 != 0
+        };
+        #[cfg(not(unix))]
+        let executable = false;
+
+        let mode = if metadata.file_type().is_symlink() {
+            0o777
+        } else if metadata.is_dir() || executable {
+            0o755
+        } else {
+            0o644
+        };
+        FullFileOptions::default().last_modified_time(self.modified).unix_permissions(mode)
+    }
+}
+
+/// Options recording the file's permission bits, modification time, and the
+/// extended timestamp, owner and extended attribute fields. The zip writer
+/// keeps only the 0o777 permission bits. `metadata` must describe `path`.
+pub fn file_options(path: &Path, metadata: &Metadata) -> FullFileOptions<'static> {
+    let mut options = FullFileOptions::default();
+    let modified = metadata.modified().ok();
+    if let Some(time) = modified.and_then(zip_datetime) {
+        options = options.last_modified_time(time);
+    }
+
+    let mut timestamp = vec![0u8];
+    for (flag, time) in [(TIMESTAMP_MODIFIED, modified), (TIMESTAMP_ACCESSED, metadata.accessed().ok())] {
+        if let Some(seconds) = time.and_then(unix_seconds) {
+            timestamp[0] |= flag;
+            timestamp.extend_from_slice(&(seconds as i32).to_le_bytes());
+        }
+    }
+    if timestamp.len() > 1 {
+        let _ = options.add_extra_data(EXTENDED_TIMESTAMP_EXTRA_ID, timestamp.into_boxed_slice(), false);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        options = options.unix_permissions(metadata.mode());
+
+        let mut owner = vec![1u8, 4];
+        owner.extend_from_slice(&metadata.uid().to_le_bytes());
+        owner.push(4);
+        owner.extend_from_slice(&metadata.gid().to_le_bytes());
+        let _ = options.add_extra_data(UNIX_OWNER_EXTRA_ID, owner.into_boxed_slice(), false);
+    }
+
+    match read_xattrs(path) {
+        Ok(xattrs) if !xattrs.is_empty() => {
+            let field = encode_xattrs(&xattrs);
+            if field.len() > MAX_XATTR_FIELD {
+                tracing::warn!("Not storing extended attributes of {}: they exceed {} bytes", path.display(), MAX_XATTR_FIELD);
+            } else if let Err(e) = options.add_extra_data(XATTR_EXTRA_ID, field.into_boxed_slice(), false) {
+                tracing::warn!("Not storing extended attributes of {}: {}", path.display(), e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Cannot read extended attributes of {}: {}", path.display(), e),
+    }
+    options
+}
+
+fn encode_xattrs(xattrs: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    let mut field = vec![XATTR_FORMAT_VERSION];
+    for (name, value) in xattrs {
+        field.push(name.len() as u8);
+        field.extend_from_slice(name);
+        field.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        field.extend_from_slice(value);
+    }
+    field
+}
+
+fn decode_xattrs(data: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut xattrs = Vec::new();
+    if data.first() != Some(&XATTR_FORMAT_VERSION) {
+        return xattrs;
+    }
+    let mut pos = 1;
+    while pos < data.len() {
+        let name_end = pos + 1 + data[pos] as usize;
+        if name_end + 2 > data.len() {
+            break;
+        }
+        let value_end = name_end + 2 + u16_at(data, name_end) as usize;
+        if value_end > data.len() {
+            break;
+        }
+        xattrs.push((data[pos + 1..name_end].to_vec(), data[name_end + 2..value_end].to_vec()));
+        pos = value_end;
+    }
+    xattrs
+}
+
+/// True if the extra fields carry extended attributes.
+pub fn has_xattrs(extra: &[u8]) -> bool {
+    extra_fields(extra).any(|(id, _)| id == XATTR_EXTRA_ID)
+}
+
+#[cfg(target_os = "linux")]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)
+}
+
+/// Extended attributes of `path` itself, not of a link's target. Attributes
+/// that vanish or grow while being read are left out.
+#[cfg(target_os = "linux")]
+pub fn read_xattrs(path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let path = c_path(path)?;
+    // SAFETY: a null buffer with size 0 asks for the size of the name list
+    let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    if size < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENOTSUP) => Ok(Vec::new()),
+            _ => Err(error),
+        };
+    }
+    let mut names = vec![0u8; size as usize];
+    // SAFETY: the buffer is valid for `names.len()` bytes
+    let size = unsafe { libc::llistxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(size as usize);
+
+    let mut xattrs = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let c_name = std::ffi::CString::new(name).map_err(io::Error::other)?;
+        // SAFETY: as above, first the size, then a buffer of that size
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 || name.len() > u8::MAX as usize || size as usize > u16::MAX as usize {
+            continue;
+        }
+        let mut value = vec![0u8; size as usize];
+        // SAFETY: the value buffer is valid for `value.len()` bytes; a value
+        // that grew since the size query fails with ERANGE instead of overflowing
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if size < 0 {
+            continue;
+        }
+        value.truncate(size as usize);
+        xattrs.push((name.to_vec(), value));
+    }
+    Ok(xattrs)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_xattrs(_path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+/// Sets each attribute on `path` itself, logging the ones the filesystem or
+/// the current user's privileges do not allow.
+#[cfg(target_os = "linux")]
+fn restore_xattrs(path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+    let c_path = c_path(path)?;
+    for (name, value) in xattrs {
+        let c_name = std::ffi::CString::new(name.as_slice()).map_err(io::Error::other)?;
+        // SAFETY: the value buffer is valid for `value.len()` bytes
+        let result = unsafe { libc::lsetxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if result < 0 {
+            tracing::warn!(
+                "Cannot restore {} on {}: {}",
+                String::from_utf8_lossy(name),
+                path.display(),
+                io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn restore_xattrs(path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+    if !xattrs.is_empty() {
+        tracing::warn!("Extended attributes of {} cannot be restored on this platform", path.display());
+    }
+    Ok(())
+}
+
+/// Converts a file time to the DOS date and time stored in entry headers, in UTC
+/// like the timestamps the zip writer stamps by default.
+pub fn zip_datetime(time: SystemTime) -> Option<zip::DateTime> {
+    zip::DateTime::try_from(time::OffsetDateTime::from(time)).ok()
+}
+
+fn unix_seconds(time: SystemTime) -> Option<i64> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (seconds <= i32::MAX as u64).then_some(seconds as i64)
+}
+
+fn extra_fields(extra: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos + 4 > extra.len() {
+            return None;
+        }
+        let id = u16_at(extra, pos);
+        let end = (pos + 4 + u16_at(extra, pos + 2) as usize).min(extra.len());
+        let data = &extra[pos + 4..end];
+        pos = end;
+        Some((id, data))
+    })
+}
+
+fn read_extra_fields(extra: &[u8], metadata: &mut EntryMetadata) {
+    for (id, data) in extra_fields(extra) {
+        match id {
+            EXTENDED_TIMESTAMP_EXTRA_ID if !data.is_empty() => {
+                let flags = data[0];
+                let mut pos = 1;
+                for (flag, slot) in [(TIMESTAMP_MODIFIED, &mut metadata.modified), (TIMESTAMP_ACCESSED, &mut metadata.accessed)] {
+                    if flags & flag == 0 {
+                        continue;
+                    }
+                    // Central records carry only the modification time
+                    if pos + 4 > data.len() {
+                        break;
+                    }
+                    *slot = Some(u32_at(data, pos) as i32 as i64);
+                    pos += 4;
+                }
+            }
+            XATTR_EXTRA_ID => metadata.xattrs = decode_xattrs(data),
+            UNIX_OWNER_EXTRA_ID if data.len() >= 3 && data[0] == 1 => {
+                let uid_size = data[1] as usize;
+                let gid_at = 2 + uid_size;
+                if uid_size == 4 && data.len() >= gid_at + 5 && data[gid_at] == 4 {
+                    metadata.uid = Some(u32_at(data, 2));
+                    metadata.gid = Some(u32_at(data, gid_at + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the metadata of the entry whose local header starts at
+/// `header_start`. The local extra fields are read as well, since the central
+/// directory usually omits the access time and owner.
+pub fn read_entry_metadata(
+    archive: &mut File,
+    header_start: u64,
+    central_extra: &[u8],
+    mode: Option<u32>,
+    dos_modified: Option<zip::DateTime>,
+) -> io::Result<EntryMetadata> {
+    let mut metadata = EntryMetadata {
+        mode,
+        ..Default::default()
+    };
+    if let Some(time) = dos_modified.and_then(|time| time::OffsetDateTime::try_from(time).ok()) {
+        metadata.modified = Some(time.unix_timestamp());
+    }
+    read_extra_fields(central_extra, &mut metadata);
+
+    let mut header = [0u8; 30];
+    archive.seek(SeekFrom::Start(header_start))?;
+    archive.read_exact(&mut header)?;
+    let name_len = u16_at(&header, 26) as i64;
+    let mut local_extra = vec![0u8; u16_at(&header, 28) as usize];
+    archive.seek(SeekFrom::Current(name_len))?;
+    archive.read_exact(&mut local_extra)?;
+    read_extra_fields(&local_extra, &mut metadata);
+
+    Ok(metadata)
+}
+
+pub fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Name of the user with `uid`, as tar records it next to the number.
+#[cfg(unix)]
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0u8; 4096];
+    // SAFETY: passwd is plain old data, filled in by getpwuid_r
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid and the buffer size is its real length;
+    // the strings in `entry` point into `buffer`, which outlives their use
+    let status = unsafe { libc::getpwuid_r(uid, &mut entry, buffer.as_mut_ptr().cast(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: on success pw_name is a NUL-terminated string inside `buffer`
+    Some(unsafe { std::ffi::CStr::from_ptr(entry.pw_name) }.to_string_lossy().into_owned())
+}
+
+/// Name of the group with `gid`.
+#[cfg(unix)]
+pub fn group_name(gid: u32) -> Option<String> {
+    let mut buffer = vec![0u8; 4096];
+    // SAFETY: group is plain old data, filled in by getgrgid_r
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: as in user_name
+    let status = unsafe { libc::getgrgid_r(gid, &mut entry, buffer.as_mut_ptr().cast(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: on success gr_name is a NUL-terminated string inside `buffer`
+    Some(unsafe { std::ffi::CStr::from_ptr(entry.gr_name) }.to_string_lossy().into_owned())
+}
+
+/// Applies `metadata` to an extracted file or directory as far as `policy`
+/// allows. Extended attributes are only restored when `xattrs` is set.
+pub fn restore_metadata(path: &Path, metadata: &EntryMetadata, policy: RestorePolicy, xattrs: bool) -> io::Result<()> {
+    // Before permissions, which may make the file read-only
+    if xattrs {
+        restore_xattrs(path, &metadata.xattrs)?;
+    }
+    if policy == RestorePolicy::Never {
+        return Ok(());
+    }
+
+    let to_time = |seconds: i64| UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64);
+    let mut times = FileTimes::new();
+    if let Some(modified) = metadata.modified {
+        times = times.set_modified(to_time(modified));
+    }
+    if let Some(accessed) = metadata.accessed.or(metadata.modified) {
+        times = times.set_accessed(to_time(accessed));
+    }
+    // Times are set first, while the file is still writable. Directories
+    // cannot be opened for writing; read-only is enough to set their times
+    let file = if path.is_dir() { File::open(path)? } else { File::options().write(true).open(path)? };
+    file.set_times(times)?;
+
+    if policy == RestorePolicy::Everything {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if is_root() && (metadata.uid.is_some() || metadata.gid.is_some()) {
+                std::os::unix::fs::chown(path, metadata.uid, metadata.gid)?;
+            }
+            // After chown, which clears setuid and setgid bits
+            if let Some(mode) = metadata.mode {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{ZipArchive, ZipWriter};
+
+    #[test]
+    fn xattr_fields_round_trip() {
+        let xattrs = vec![(b"user.color".to_vec(), b"blue".to_vec()), (b"user.empty".to_vec(), Vec::new())];
+        assert_eq!(decode_xattrs(&encode_xattrs(&xattrs)), xattrs);
+        // A field cut short keeps the attributes that fit
+        let field = encode_xattrs(&xattrs);
+        assert_eq!(decode_xattrs(&field[..field.len() - 3]), xattrs[..1]);
+    }
+
+    #[test]
+    fn stored_metadata_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.txt");
+        std::fs::write(&source, b"hello").unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let accessed = UNIX_EPOCH + Duration::from_secs(1_650_000_000);
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(modified).set_accessed(accessed))
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o750)).unwrap();
+        }
+        let metadata = std::fs::metadata(&source).unwrap();
+
+        let path = dir.path().join("meta.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer.start_file("a.txt", file_options(&source, &metadata)).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let entry = archive.by_index(0).unwrap();
+        let (start, extra, mode, dos) = (entry.header_start(), entry.extra_data().unwrap_or_default().to_vec(), entry.unix_mode(), entry.last_modified());
+        drop(entry);
+        let stored = read_entry_metadata(&mut File::open(&path).unwrap(), start, &extra, mode, dos).unwrap();
+        assert_eq!(stored.modified, Some(1_600_000_000));
+        assert_eq!(stored.accessed, Some(1_650_000_000));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(stored.mode.map(|mode| mode & 0o777), Some(0o750));
+            assert_eq!(stored.uid, Some(metadata.uid()));
+            assert_eq!(stored.gid, Some(metadata.gid()));
+        }
+
+        // Restoring onto a fresh file brings the times and mode back
+        let restored = dir.path().join("b.txt");
+        std::fs::write(&restored, b"hello").unwrap();
+        restore_metadata(&restored, &stored, RestorePolicy::Everything, false).unwrap();
+        let restored = std::fs::metadata(&restored).unwrap();
+        assert_eq!(restored.modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(restored.permissions().mode() & 0o777, 0o750);
+        }
+    }
+}
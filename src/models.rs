@@ -1,33 +1,96 @@
+use crate::metadata::has_xattrs;
+#[derive(Default)]
 pub struct ArchiveZone {
     pub rect: Option<egui::Rect>,
-    pub is_compress_zone: bool,
 }
 
-impl Default for ArchiveZone {
-    fn default() -> Self {
+#[derive(Clone)]
+pub struct ArchiveFile {
+    pub name: String,
+    pub is_directory: bool,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub modified: Option<zip::DateTime>,
+    pub crc32: u32,
+    pub compression: String,
+    pub encrypted: bool,
+    pub unix_mode: Option<u32>,
+    pub comment: String,
+    pub has_xattrs: bool,
+}
+
+impl ArchiveFile {
+    pub fn from_zip_file(file: &zip::read::ZipFile) -> Self {
         Self {
-            rect: None,
-            is_compress_zone: false,
+            name: file.name().to_string(),
+            is_directory: file.is_dir(),
+            size: file.size(),
+            compressed_size: file.compressed_size(),
+            modified: file.last_modified(),
+            crc32: file.crc32(),
+            compression: file.compression().to_string(),
+            encrypted: file.encrypted(),
+            unix_mode: file.unix_mode(),
+            comment: file.comment().to_string(),
+            has_xattrs: file.extra_data().is_some_and(has_xattrs),
         }
     }
-}
 
-#[derive(Default, Debug)]
-pub struct Password(pub Option<String>);
+    /// Compressed size as a percentage of the original size
+    pub fn ratio(&self) -> f64 {
+        if self.size > 0 {
+            self.compressed_size as f64 / self.size as f64 * 100.0
+        } else {
+            0.0
+        }
+    }
 
-impl Password {
-    pub fn set(&mut self, value: String) {
-        self.0 = if value.is_empty() { None } else { Some(value) };
+    pub fn formatted_modified(&self) -> String {
+        match self.modified {
+            Some(dt) => format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                dt.year(),
+                dt.month(),
+                dt.day(),
+                dt.hour(),
+                dt.minute(),
+                dt.second()
+            ),
+            None => String::new(),
+        }
     }
 
-    pub fn as_str(&self) -> Option<&str> {
-        self.0.as_deref()
+    /// Unix permission bits in `ls -l` style, e.g. `drwxr-xr-x`, with a
+    /// trailing '@' when the entry carries extended attributes
+    pub fn formatted_attributes(&self) -> String {
+        let Some(mode) = self.unix_mode else {
+            return String::new();
+        };
+        let kind = match mode & 0o170000 {
+            0o040000 => 'd',
+            0o120000 => 'l',
+            _ => '-',
+        };
+        let mut text = String::with_capacity(10);
+        text.push(kind);
+        for shift in [6, 3, 0] {
+            let bits = (mode >> shift) & 0o7;
+            text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        if self.has_xattrs {
+            text.push('@');
+        }
+        text
     }
 }
 
-#[derive(Clone)]
-pub struct ArchiveFile {
-    pub name: String,
-    pub is_directory: bool,
-    pub size: u64,
+#[derive(Clone, Default)]
+pub enum SignatureStatus {
+    #[default]
+    Unsigned,
+    Trusted(String),
+    Untrusted(String),
+    Invalid(String),
 }
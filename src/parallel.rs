@@ -1,26 +1,81 @@
 use std::fs::File;
-use std::io::{Read, Write, BufReader, BufWriter};
-use std::path::PathBuf;
-use std::sync::mpsc::Sender;
+use std::io::{self, Read, BufReader, BufWriter};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use rayon::prelude::*;
-use tracing::info;
-use zip::write::FileOptions;
-use crate::app::{ArchiveManager, CompressionStats};
+use tracing::warn;
+use crate::app::CompressionStats;
+use crate::metadata::{file_options, Reproducible};
+use crate::rewrite::NewEntry;
+use crate::sparse::SparseReader;
 
 const BUFFER_SIZE: usize = 1024 * 1024; // 1MB buffer
 const COMPRESSION_LEVEL: i32 = 5; // Faster compression, still decent ratio
+const SAMPLE_FILES: usize = 16;
+const SAMPLE_SIZE: u64 = 256 * 1024; // 256KB per sampled file
+const ENTRY_OVERHEAD: u64 = 128; // local + central headers, excluding the name
 
+/// Estimates the archive size by deflating a sample from up to `SAMPLE_FILES`
+/// inputs spread across the list and extrapolating the ratio to the total.
+pub fn estimate_compressed_size(entries: &[NewEntry], total_size: u64) -> u64 {
+    let files: Vec<&NewEntry> = entries.iter().filter(|entry| entry.is_file()).collect();
+    let step = (files.len() / SAMPLE_FILES).max(1);
+    let mut sampled = 0u64;
+    let mut compressed = 0u64;
+
+    for entry in files.iter().step_by(step).take(SAMPLE_FILES) {
+        let Ok(file) = File::open(&entry.source) else {
+            continue;
+        };
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(COMPRESSION_LEVEL as u32));
+        let Ok(read) = std::io::copy(&mut file.take(SAMPLE_SIZE), &mut encoder) else {
+            continue;
+        };
+        if let Ok(output) = encoder.finish() {
+            sampled += read;
+            compressed += output.len() as u64;
+        }
+    }
+
+    let ratio = if sampled > 0 { compressed as f64 / sampled as f64 } else { 1.0 };
+    let overhead: u64 = entries
+        .iter()
+        .map(|entry| ENTRY_OVERHEAD + 2 * entry.name.len() as u64)
+        .sum();
+    (total_size as f64 * ratio) as u64 + overhead
+}
+
+/// Compresses `entries`, as listed by `collect_new_entries`, into a new archive
+/// at `output_path`. With `reproducible` set, entries are written in name
+/// order with normalized metadata. A message on `cancel_rx` stops compression
+/// with an error.
 pub fn compress_files_parallel(
-    files: Vec<PathBuf>,
+    mut entries: Vec<NewEntry>,
     output_path: PathBuf,
     progress_tx: Sender<(f32, CompressionStats)>,
-    cancel_rx: Arc<Mutex<Sender<()>>>,
+    cancel_rx: Arc<Mutex<Receiver<()>>>,
     stats: Arc<Mutex<CompressionStats>>,
-    password: String
+    password: String,
+    reproducible: Option<Reproducible>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let total_size: u64 = files.iter()
-        .filter_map(|path| std::fs::metadata(path).ok())
+    if reproducible.is_some() {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        if !password.is_empty() {
+            warn!("Encrypted archives are not reproducible: AES uses a random salt");
+        }
+    }
+    let options_for = |path: &Path, metadata: &Metadata| match reproducible {
+        Some(reproducible) => reproducible.file_options(metadata),
+        None => file_options(path, metadata),
+    };
+
+    let total_size: u64 = entries.iter()
+        .filter(|entry| entry.is_file())
+        .filter_map(|entry| entry.metadata().ok())
         .map(|meta| meta.len())
         .sum();
 
@@ -29,58 +84,53 @@ pub fn compress_files_parallel(
     let zip = Arc::new(Mutex::new(zip::ZipWriter::new(file)));
     let processed_size = Arc::new(Mutex::new(0u64));
 
-    // Pre-calculate file metadata to avoid redundant filesystem operations
-    let file_metadata: Vec<_> = files.iter()
-        .filter_map(|path| {
-            std::fs::metadata(path)
-                .ok()
-                .map(|meta| (path.clone(), meta.len()))
-        })
-        .collect();
-
-    // Process files in chunks for better parallelization
-    file_metadata.par_chunks(4).try_for_each(|chunk| -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut buffer = Vec::with_capacity(BUFFER_SIZE);
-
-        for (path, file_size) in chunk {
-            // Check cancellation
-            if cancel_rx.lock().unwrap().send(()).is_ok() {
-                return Ok(());
+    let compress_chunk = |chunk: &[NewEntry]| -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for entry in chunk {
+            if cancel_rx.lock().unwrap().try_recv().is_ok() {
+                return Err("Compression cancelled".into());
             }
 
-            let file = File::open(path)?;
-            let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
-            buffer.clear();
-            reader.read_to_end(&mut buffer)?;
+            let path = &entry.source;
+            let file_name = entry.name.as_str();
 
-            let file_name = path.file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .into_owned();
+            // Links are stored without opening them, so a link to a folder or
+            // to itself cannot stall compression
+            if let Some(target) = &entry.link_target {
+                let link_metadata = std::fs::symlink_metadata(path)?;
+                let mut zip = zip.lock().unwrap();
+                zip.add_symlink(file_name, target.to_string_lossy(), options_for(path, &link_metadata))?;
+                continue;
+            }
+            if !entry.is_file() {
+                let metadata = std::fs::metadata(path)?;
+                let mut zip = zip.lock().unwrap();
+                zip.add_directory(file_name.trim_end_matches('/'), options_for(path, &metadata))?;
+                continue;
+            }
 
-            let mut options: FileOptions<'_, ()> = FileOptions::default()
-                .compression_method(zip::CompressionMethod::Deflated)
-                .compression_level(Some(COMPRESSION_LEVEL as i64))
-                .unix_permissions(0o755);
+            let file = SparseReader::open(path)?;
+            let metadata = file.metadata()?;
+            let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
 
-            info!("Password enc: {:?}", password.as_str());
+            let mut options = options_for(path, &metadata)
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(COMPRESSION_LEVEL as i64));
 
             if !password.is_empty() {
                 options = options
                     .with_aes_encryption(zip::AesMode::Aes256, password.as_str());
             }
 
-
-            // Minimize lock contention by reducing the critical section
+            // Streamed, so large files and their holes are never held in memory
             {
                 let mut zip = zip.lock().unwrap();
-                zip.start_file(&file_name, options)?;
-                zip.write_all(&buffer)?;
+                zip.start_file(file_name, options)?;
+                io::copy(&mut reader, &mut *zip)?;
             }
 
             // Update progress
             let mut processed = processed_size.lock().unwrap();
-            *processed += file_size;
+            *processed += metadata.len();
             let progress = *processed as f32 / total_size as f32;
 
             // Update stats less frequently to reduce lock contention
@@ -99,7 +149,17 @@ pub fn compress_files_parallel(
             }
         }
         Ok(())
-    }).expect("Failed to parallel");
+    };
+
+    // Workers race for the writer, so a reproducible archive is written in order
+    // on one thread; compression happens under the writer's lock either way
+    let result = if reproducible.is_some() {
+        entries.chunks(4).try_for_each(compress_chunk)
+    } else {
+        // Process files in chunks for better parallelization
+        entries.par_chunks(4).try_for_each(compress_chunk)
+    };
+    result.map_err(|e| e.to_string())?;
 
     // Finalize the zip file
     let final_zip = Arc::try_unwrap(zip)
@@ -115,4 +175,85 @@ pub fn compress_files_parallel(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterSet;
+    use crate::rewrite::{collect_new_entries, LinkPolicy};
+    use std::io::Write;
+    use std::sync::mpsc::channel;
+    use std::time::{Duration, Instant};
+
+    fn compress(entries: Vec<NewEntry>, output: &Path, cancel_rx: Receiver<()>) -> Result<(), Box<dyn std::error::Error>> {
+        let stats = CompressionStats {
+            original_size: 0,
+            compressed_size: 0,
+            start_time: Instant::now(),
+            estimated_time: Duration::from_secs(0),
+            output_path: output.to_path_buf(),
+            files_processed: 0,
+            total_files: entries.len(),
+        };
+        let (progress_tx, _progress_rx) = channel();
+        let cancel_rx = Arc::new(Mutex::new(cancel_rx));
+        compress_files_parallel(entries, output.to_path_buf(), progress_tx, cancel_rx, Arc::new(Mutex::new(stats)), String::new(), None)
+    }
+
+    #[test]
+    fn files_gone_before_compression_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        let entries = collect_new_entries(std::slice::from_ref(&source), "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+        std::fs::remove_file(source.join("a.txt")).unwrap();
+
+        let (_cancel_tx, cancel_rx) = channel();
+        assert!(compress(entries, &dir.path().join("out.zip"), cancel_rx).is_err());
+    }
+
+    #[test]
+    fn every_entry_is_written_until_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        for index in 0..10 {
+            std::fs::write(source.join(format!("{}.txt", index)), b"hello").unwrap();
+        }
+        let entries = collect_new_entries(std::slice::from_ref(&source), "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+        let count = entries.len();
+
+        let output = dir.path().join("out.zip");
+        let (_cancel_tx, cancel_rx) = channel();
+        compress(entries.clone(), &output, cancel_rx).unwrap();
+        assert_eq!(zip::ZipArchive::new(File::open(&output).unwrap()).unwrap().len(), count);
+
+        let (cancel_tx, cancel_rx) = channel();
+        cancel_tx.send(()).unwrap();
+        assert!(compress(entries, &output, cancel_rx).is_err());
+    }
+
+    #[test]
+    fn large_sparse_files_are_streamed_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        let file = File::create(source.join("disk.img")).unwrap();
+        file.set_len(16 * 1024 * 1024).unwrap();
+        drop(file);
+        std::fs::OpenOptions::new().append(true).open(source.join("disk.img")).unwrap().write_all(b"end").unwrap();
+        let entries = collect_new_entries(std::slice::from_ref(&source), "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+
+        let output = dir.path().join("out.zip");
+        let (_cancel_tx, cancel_rx) = channel();
+        compress(entries, &output, cancel_rx).unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut entry = archive.by_name("proj/disk.img").unwrap();
+        assert_eq!(entry.size(), 16 * 1024 * 1024 + 3);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        assert!(data.ends_with(b"\0end"));
+        assert_eq!(data.iter().filter(|&&b| b != 0).count(), 3);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use zip::ZipArchive;
+
+/// Bytes decompressed per "Load more" step for text and hex previews
+pub const PREVIEW_CHUNK: u64 = 256 * 1024;
+/// Images larger than this are shown as hex instead of being decoded
+const MAX_IMAGE_SIZE: u64 = 32 * 1024 * 1024;
+/// Only formats with a decoder compiled into the image loaders
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "bmp", "ico", "tga", "pbm", "pgm", "ppm", "pnm", "hdr",
+];
+
+#[derive(Clone)]
+pub enum PreviewContent {
+    Text { text: String, encoding: &'static str },
+    Image { uri: String, bytes: Arc<[u8]> },
+    Hex(Vec<u8>),
+}
+
+#[derive(Clone)]
+pub struct Preview {
+    pub entry: String,
+    pub size: u64,
+    /// Bytes decompressed so far; less than `size` when the preview is capped
+    pub loaded: u64,
+    /// File extension, used to pick a syntax highlighter
+    pub language: String,
+    pub content: PreviewContent,
+}
+
+impl Preview {
+    pub fn is_truncated(&self) -> bool {
+        self.loaded < self.size
+    }
+}
+
+pub fn is_image_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Decompresses an entry on a worker thread. The decompressor stays open
+/// between chunks, so "Load more" continues where the last chunk ended.
+/// Dropping the loader ends the worker.
+pub struct PreviewLoader {
+    pub entry: String,
+    more: Sender<u64>,
+}
+
+impl PreviewLoader {
+    /// Loads the first `limit` bytes of `entry`, reporting every chunk to
+    /// `on_loaded`. Images are always read whole so they can be decoded.
+    pub fn start(
+        archive_path: PathBuf,
+        entry: String,
+        password: Option<String>,
+        limit: u64,
+        on_loaded: impl Fn(Result<Preview, String>) + Send + 'static,
+    ) -> Self {
+        let (more, receiver) = channel();
+        let worker_entry = entry.clone();
+        thread::spawn(move || {
+            let result = preview_worker(&archive_path, &worker_entry, password.as_deref(), limit, &receiver, &on_loaded);
+            if let Err(e) = result {
+                on_loaded(Err(e.to_string()));
+            }
+        });
+        Self { entry, more }
+    }
+
+    /// Extends the preview to the first `limit` bytes.
+    pub fn load_more(&self, limit: u64) {
+        let _ = self.more.send(limit);
+    }
+}
+
+fn preview_worker(
+    archive_path: &Path,
+    entry: &str,
+    password: Option<&str>,
+    limit: u64,
+    receiver: &Receiver<u64>,
+    on_loaded: &dyn Fn(Result<Preview, String>),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+    let mut file = match password {
+        Some(password) => archive.by_name_decrypt(entry, password.as_bytes())?,
+        None => archive.by_name(entry)?,
+    };
+    let size = file.size();
+    let image = is_image_name(entry) && size <= MAX_IMAGE_SIZE;
+    let mut limit = if image { size } else { limit.min(size) };
+
+    let mut data = Vec::with_capacity(limit as usize);
+    loop {
+        (&mut file).take(limit.saturating_sub(data.len() as u64)).read_to_end(&mut data)?;
+        on_loaded(Ok(build_preview(archive_path, entry, size, &data, image)));
+
+        // Keep only the largest of any requests that piled up
+        match receiver.recv() {
+            Ok(more) => limit = receiver.try_iter().fold(more, u64::max).min(size),
+            Err(_) => return Ok(()),
+        }
+    }
+}
+
+fn build_preview(archive_path: &Path, entry: &str, size: u64, data: &[u8], image: bool) -> Preview {
+    let language = Path::new(entry)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let content = if image {
+        PreviewContent::Image {
+            uri: format!("bytes://{}/{}", archive_path.display(), entry),
+            bytes: data.into(),
+        }
+    } else {
+        match decode_text(data) {
+            Some((text, encoding)) => PreviewContent::Text { text, encoding },
+            None => PreviewContent::Hex(data.to_vec()),
+        }
+    };
+
+    Preview {
+        entry: entry.to_string(),
+        size,
+        loaded: data.len() as u64,
+        language,
+        content,
+    }
+}
+
+/// Detects the text encoding from a byte-order mark or by validation, falling
+/// back to Latin-1. Returns `None` for data that looks binary.
+fn decode_text(data: &[u8]) -> Option<(String, &'static str)> {
+    if let Some(rest) = data.strip_prefix(b"\xEF\xBB\xBF") {
+        return Some((String::from_utf8_lossy(rest).into_owned(), "UTF-8 (BOM)"));
+    }
+    if let Some(rest) = data.strip_prefix(b"\xFF\xFE") {
+        return Some((decode_utf16(rest, u16::from_le_bytes), "UTF-16LE"));
+    }
+    if let Some(rest) = data.strip_prefix(b"\xFE\xFF") {
+        return Some((decode_utf16(rest, u16::from_be_bytes), "UTF-16BE"));
+    }
+
+    let sample = &data[..data.len().min(8192)];
+    if memchr::memchr(0, sample).is_some() {
+        return None;
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(text) => Some((text.to_string(), "UTF-8")),
+        // A capped chunk may end in the middle of a character
+        Err(e) if e.error_len().is_none() => {
+            Some((String::from_utf8_lossy(&data[..e.valid_up_to()]).into_owned(), "UTF-8"))
+        }
+        Err(_) => {
+            let controls = sample
+                .iter()
+                .filter(|&&b| b < 0x20 && !matches!(b, b'\n' | b'\r' | b'\t' | 0x0C))
+                .count();
+            (controls * 20 < sample.len().max(1)).then(|| (data.iter().map(|&b| b as char).collect(), "ISO-8859-1"))
+        }
+    }
+}
+
+fn decode_utf16(data: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units = data.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// One line of a classic hex dump: offset, 16 hex bytes and their ASCII form.
+pub fn hex_line(data: &[u8], offset: usize) -> String {
+    let row = &data[offset..(offset + 16).min(data.len())];
+    let mut line = format!("{:08x}  ", offset);
+    for i in 0..16 {
+        match row.get(i) {
+            Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+            None => line.push_str("   "),
+        }
+        if i == 7 {
+            line.push(' ');
+        }
+    }
+    line.push(' ');
+    line.extend(row.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::Duration;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    #[test]
+    fn load_more_continues_the_same_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text.zip");
+        let text: String = (0..100_000).map(|line| format!("{}\n", line)).collect();
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer.start_file("numbers.txt", SimpleFileOptions::default()).unwrap();
+        writer.write_all(text.as_bytes()).unwrap();
+        writer.finish().unwrap();
+
+        let (sender, results) = channel();
+        let loader = PreviewLoader::start(path, "numbers.txt".to_string(), None, PREVIEW_CHUNK, move |result| {
+            let _ = sender.send(result);
+        });
+        let first = results.recv().unwrap().unwrap();
+        assert_eq!(first.loaded, PREVIEW_CHUNK);
+        assert!(first.is_truncated());
+
+        loader.load_more(u64::MAX);
+        let rest = results.recv().unwrap().unwrap();
+        assert!(!rest.is_truncated());
+        match rest.content {
+            PreviewContent::Text { text: shown, .. } => assert_eq!(shown, text),
+            _ => panic!("expected text"),
+        }
+
+        drop(loader);
+        assert!(matches!(results.recv_timeout(Duration::from_secs(5)), Err(RecvTimeoutError::Disconnected)));
+    }
+
+    #[test]
+    fn missing_entry_reports_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.zip");
+        ZipWriter::new(File::create(&path).unwrap()).finish().unwrap();
+        let (sender, results) = channel();
+        let _loader = PreviewLoader::start(path, "missing".to_string(), None, PREVIEW_CHUNK, move |result| {
+            let _ = sender.send(result);
+        });
+        assert!(results.recv().unwrap().is_err());
+    }
+
+    #[test]
+    fn detects_text_encodings() {
+        assert_eq!(decode_text(b"\xEF\xBB\xBFhi").unwrap().1, "UTF-8 (BOM)");
+        assert_eq!(decode_text(b"\xFF\xFEh\0i\0").unwrap().0, "hi");
+        assert_eq!(decode_text("caf\u{e9}".as_bytes()).unwrap().1, "UTF-8");
+        assert_eq!(decode_text(b"caf\xe9 au lait").unwrap(), ("caf\u{e9} au lait".to_string(), "ISO-8859-1"));
+        assert!(decode_text(b"\0\x01\x02binary").is_none());
+    }
+}
@@ -0,0 +1,394 @@
+use crate::models::ArchiveFile;
+use crate::preview::is_image_name;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+/// Pages decoded ahead of and behind the current one
+pub const PREFETCH_PAGES: usize = 2;
+pub const THUMBNAIL_SIZE: [u32; 2] = [128, 180];
+const MAX_THUMBNAILS: usize = 400;
+const COMIC_EXTENSIONS: &[&str] = &["cbz", "cbr", "cb7"];
+
+/// Compares names so that runs of digits sort by value: "page2" < "page10".
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_digits, b_digits) = (&a[..a_len], &b[..b_len]);
+                let a_trimmed = &a_digits[a_digits.iter().take_while(|&&c| c == b'0').count()..];
+                let b_trimmed = &b_digits[b_digits.iter().take_while(|&&c| c == b'0').count()..];
+                let ordering = a_trimmed
+                    .len()
+                    .cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed))
+                    .then_with(|| a_len.cmp(&b_len));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+/// Image entries in reading order.
+pub fn comic_pages(files: &[ArchiveFile]) -> Vec<String> {
+    let mut pages: Vec<String> = files
+        .iter()
+        .filter(|file| !file.is_directory && is_image_name(&file.name))
+        .map(|file| file.name.clone())
+        .collect();
+    pages.sort_by(|a, b| natural_cmp(a, b));
+    pages
+}
+
+/// Image entries of a 7z-packed comic, in reading order.
+pub fn seven_zip_pages(path: &Path, password: Option<&str>) -> Result<Vec<String>, sevenz_rust::Error> {
+    let reader = SevenZReader::open(path, password.map(Password::from).unwrap_or_else(Password::empty))?;
+    let mut pages: Vec<String> = reader
+        .archive()
+        .files
+        .iter()
+        .filter(|entry| !entry.is_directory() && is_image_name(entry.name()))
+        .map(|entry| entry.name().to_string())
+        .collect();
+    pages.sort_by(|a, b| natural_cmp(a, b));
+    Ok(pages)
+}
+
+/// True for .cbz/.cbr/.cb7 files and for archives that are mostly images.
+pub fn looks_like_comic(path: &Path, files: &[ArchiveFile]) -> bool {
+    let is_comic_extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| COMIC_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    let entries = files.iter().filter(|file| !file.is_directory).count();
+    let images = files.iter().filter(|file| !file.is_directory && is_image_name(&file.name)).count();
+    images > 0 && (is_comic_extension || (images >= 2 && images * 5 >= entries * 4))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Request {
+    Page(usize),
+    Thumbnail(usize),
+}
+
+type Decoded = Result<egui::TextureHandle, String>;
+
+#[derive(Default)]
+struct Decodes {
+    pages: HashMap<usize, Decoded>,
+    thumbnails: HashMap<usize, Decoded>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReaderMode {
+    Grid,
+    Page,
+}
+
+/// Pages are decoded in memory by a worker thread and uploaded as textures;
+/// nothing is written to disk.
+pub struct ComicReader {
+    pub pages: Vec<String>,
+    pub current: usize,
+    pub mode: ReaderMode,
+    decodes: Arc<Mutex<Decodes>>,
+    requested: HashSet<Request>,
+    requests: Sender<Request>,
+}
+
+impl ComicReader {
+    pub fn new(ctx: &egui::Context, archive_path: PathBuf, password: Option<String>, pages: Vec<String>) -> Self {
+        let decodes = Arc::new(Mutex::new(Decodes::default()));
+        let (requests, receiver) = channel();
+
+        let worker_decodes = Arc::clone(&decodes);
+        let worker_pages = pages.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            decode_worker(ctx, archive_path, password, worker_pages, receiver, worker_decodes);
+        });
+
+        Self {
+            pages,
+            current: 0,
+            mode: ReaderMode::Grid,
+            decodes,
+            requested: HashSet::new(),
+            requests,
+        }
+    }
+
+    fn request(&mut self, request: Request) {
+        if self.requested.insert(request) {
+            let _ = self.requests.send(request);
+        }
+    }
+
+    /// The decoded page, or `None` while it is still loading.
+    pub fn page(&mut self, page: usize) -> Option<Decoded> {
+        let decoded = self.decodes.lock().ok()?.pages.get(&page).cloned();
+        if decoded.is_none() {
+            self.request(Request::Page(page));
+        }
+        decoded
+    }
+
+    pub fn thumbnail(&mut self, page: usize) -> Option<Decoded> {
+        let decoded = self.decodes.lock().ok()?.thumbnails.get(&page).cloned();
+        if decoded.is_none() {
+            self.request(Request::Thumbnail(page));
+        }
+        decoded
+    }
+
+    /// Opens `page` in the single-page view, decoding its neighbours ahead of
+    /// time and dropping pages that moved out of the prefetch window.
+    pub fn go_to(&mut self, page: usize) {
+        if self.pages.is_empty() {
+            return;
+        }
+        self.current = page.min(self.pages.len() - 1);
+        self.mode = ReaderMode::Page;
+
+        let window = self.current.saturating_sub(PREFETCH_PAGES)..=self.current + PREFETCH_PAGES;
+        if let Ok(mut decodes) = self.decodes.lock() {
+            decodes.pages.retain(|page, _| window.contains(page));
+        }
+        self.requested
+            .retain(|request| !matches!(request, Request::Page(page) if !window.contains(page)));
+
+        self.request(Request::Page(self.current));
+        for distance in 1..=PREFETCH_PAGES {
+            if self.current + distance < self.pages.len() {
+                self.request(Request::Page(self.current + distance));
+            }
+            if let Some(page) = self.current.checked_sub(distance) {
+                self.request(Request::Page(page));
+            }
+        }
+    }
+
+    pub fn next_page(&mut self) {
+        if self.current + 1 < self.pages.len() {
+            self.go_to(self.current + 1);
+        }
+    }
+
+    pub fn previous_page(&mut self) {
+        if self.current > 0 {
+            self.go_to(self.current - 1);
+        }
+    }
+
+    /// Keeps thumbnail memory bounded by dropping those furthest from `visible`.
+    pub fn trim_thumbnails(&mut self, visible: usize) {
+        let Ok(mut decodes) = self.decodes.lock() else {
+            return;
+        };
+        if decodes.thumbnails.len() <= MAX_THUMBNAILS {
+            return;
+        }
+        let mut cached: Vec<usize> = decodes.thumbnails.keys().copied().collect();
+        cached.sort_by_key(|&page| page.abs_diff(visible));
+        for page in cached.split_off(MAX_THUMBNAILS / 2) {
+            decodes.thumbnails.remove(&page);
+            self.requested.remove(&Request::Thumbnail(page));
+        }
+    }
+}
+
+/// Where the worker reads page data from.
+enum PageArchive {
+    Zip(ZipArchive<File>),
+    /// 7z comics are usually solid, so single pages cannot be decompressed on
+    /// their own; the compressed image files are read into memory in one pass.
+    SevenZ(HashMap<String, Vec<u8>>),
+}
+
+impl PageArchive {
+    fn open(path: &Path, password: Option<&str>, pages: &[String]) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        if let Ok(archive) = ZipArchive::new(file) {
+            return Ok(PageArchive::Zip(archive));
+        }
+
+        let wanted: HashSet<&str> = pages.iter().map(String::as_str).collect();
+        let mut data = HashMap::new();
+        let mut reader = SevenZReader::open(path, password.map(Password::from).unwrap_or_else(Password::empty))
+            .map_err(|e| e.to_string())?;
+        reader
+            .for_each_entries(|entry, content| {
+                if wanted.contains(entry.name()) {
+                    let mut bytes = Vec::with_capacity(entry.size() as usize);
+                    content.read_to_end(&mut bytes)?;
+                    data.insert(entry.name().to_string(), bytes);
+                } else {
+                    std::io::copy(content, &mut std::io::sink())?;
+                }
+                Ok(true)
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(PageArchive::SevenZ(data))
+    }
+
+    fn read(&mut self, name: &str, password: Option<&str>) -> Result<Vec<u8>, String> {
+        match self {
+            PageArchive::Zip(archive) => {
+                let mut file = match password {
+                    Some(password) => archive.by_name_decrypt(name, password.as_bytes()),
+                    None => archive.by_name(name),
+                }
+                .map_err(|e| e.to_string())?;
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+                Ok(data)
+            }
+            PageArchive::SevenZ(pages) => pages.get(name).cloned().ok_or_else(|| format!("{} is missing", name)),
+        }
+    }
+}
+
+fn decode_worker(
+    ctx: egui::Context,
+    archive_path: PathBuf,
+    password: Option<String>,
+    pages: Vec<String>,
+    receiver: Receiver<Request>,
+    decodes: Arc<Mutex<Decodes>>,
+) {
+    let mut archive = match PageArchive::open(&archive_path, password.as_deref(), &pages) {
+        Ok(archive) => archive,
+        Err(e) => {
+            tracing::error!("Comic reader cannot open {:?}: {}", archive_path, e);
+            if let Ok(mut decodes) = decodes.lock() {
+                for page in 0..pages.len() {
+                    decodes.pages.insert(page, Err(e.clone()));
+                    decodes.thumbnails.insert(page, Err(e.clone()));
+                }
+            }
+            ctx.request_repaint();
+            return;
+        }
+    };
+
+    let mut queue = Vec::new();
+    loop {
+        // Wait for work, then take everything pending so pages can jump ahead
+        // of thumbnails
+        if queue.is_empty() {
+            match receiver.recv() {
+                Ok(request) => queue.push(request),
+                Err(_) => return,
+            }
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok(request) => queue.push(request),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        let position = queue
+            .iter()
+            .position(|request| matches!(request, Request::Page(_)))
+            .unwrap_or(0);
+        let request = queue.remove(position);
+
+        let (page, thumbnail) = match request {
+            Request::Page(page) => (page, false),
+            Request::Thumbnail(page) => (page, true),
+        };
+        let decoded = decode_page(&ctx, &mut archive, &pages[page], password.as_deref(), thumbnail);
+        if let Err(e) = &decoded {
+            tracing::warn!("Cannot decode page {}: {}", pages[page], e);
+        }
+
+        if let Ok(mut decodes) = decodes.lock() {
+            if thumbnail {
+                decodes.thumbnails.insert(page, decoded);
+            } else {
+                decodes.pages.insert(page, decoded);
+            }
+        }
+        ctx.request_repaint();
+    }
+}
+
+fn decode_page(
+    ctx: &egui::Context,
+    archive: &mut PageArchive,
+    name: &str,
+    password: Option<&str>,
+    thumbnail: bool,
+) -> Decoded {
+    let data = archive.read(name, password)?;
+
+    let mut image = image::load_from_memory(&data).map_err(|e| e.to_string())?;
+    if thumbnail {
+        image = image.thumbnail(THUMBNAIL_SIZE[0], THUMBNAIL_SIZE[1]);
+    }
+    let image = image.to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+
+    let texture_name = format!("{}{}", if thumbnail { "thumb:" } else { "page:" }, name);
+    Ok(ctx.load_texture(texture_name, color_image, egui::TextureOptions::LINEAR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+
+    #[test]
+    fn pages_sort_naturally() {
+        let mut names = vec!["p10.jpg", "p2.jpg", "P1.webp"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["P1.webp", "p2.jpg", "p10.jpg"]);
+    }
+
+    #[test]
+    fn reads_pages_from_seven_zip_comics() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("comic.cb7");
+        let mut writer = SevenZWriter::create(&path).unwrap();
+        for (name, data) in [("p10.jpg", b"ten".as_slice()), ("p2.gif", b"two"), ("notes.txt", b"notes")] {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            entry.has_stream = true;
+            writer.push_archive_entry(entry, Some(data)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let pages = seven_zip_pages(&path, None).unwrap();
+        assert_eq!(pages, ["p2.gif", "p10.jpg"]);
+        let mut archive = PageArchive::open(&path, None, &pages).unwrap();
+        assert_eq!(archive.read("p10.jpg", None).unwrap(), b"ten");
+        assert!(archive.read("notes.txt", None).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::metadata::has_xattrs;
+use crate::models::ArchiveFile;
+use crate::rewrite::{
+    write_raw_archive, RawEntry, DATA_DESCRIPTOR_SIGNATURE, FLAG_DATA_DESCRIPTOR, LOCAL_HEADER_SIGNATURE, ZIP64_EXTRA_ID,
+};
+use crate::utils::{create_temp_beside, u16_at, u32_at, u64_at};
+use memchr::memmem;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SCAN_CHUNK: usize = 1024 * 1024; // 1MB scan window
+
+#[derive(Clone)]
+pub struct RecoveredEntry {
+    pub name: String,
+    pub raw_name: Vec<u8>,
+    pub version_needed: u16,
+    pub flags: u16,
+    pub method: u16,
+    pub mod_time: u16,
+    pub mod_date: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    /// Extra fields from the local header, minus any Zip64 field (rebuilt on write)
+    pub extra: Vec<u8>,
+    pub data_start: u64,
+    /// False when the entry's data runs past the end of the file or its end could not be found
+    pub complete: bool,
+}
+
+impl RecoveredEntry {
+    pub fn is_directory(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn to_archive_file(&self) -> ArchiveFile {
+        let compression = match self.method {
+            0 => "Stored".to_string(),
+            8 => "Deflated".to_string(),
+            9 => "Deflate64".to_string(),
+            12 => "Bzip2".to_string(),
+            14 => "Lzma".to_string(),
+            93 => "Zstd".to_string(),
+            95 => "Xz".to_string(),
+            99 => "Aes".to_string(),
+            other => format!("Unknown({})", other),
+        };
+
+        ArchiveFile {
+            name: self.name.clone(),
+            is_directory: self.is_directory(),
+            size: self.size,
+            compressed_size: self.compressed_size,
+            modified: zip::DateTime::try_from_msdos(self.mod_date, self.mod_time).ok(),
+            crc32: self.crc32,
+            compression,
+            encrypted: self.flags & 0x0001 != 0,
+            unix_mode: None,
+            comment: String::new(),
+            has_xattrs: has_xattrs(&self.extra),
+        }
+    }
+}
+
+/// Returns the offset of the next occurrence of `signature` at or after `from`.
+fn find_signature(file: &mut File, from: u64, signature: &[u8; 4]) -> io::Result<Option<u64>> {
+    let finder = memmem::Finder::new(signature);
+    let mut buffer = vec![0u8; SCAN_CHUNK];
+    let mut offset = from;
+
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let n = file.read(&mut buffer[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        if filled < signature.len() {
+            return Ok(None);
+        }
+        if let Some(pos) = finder.find(&buffer[..filled]) {
+            return Ok(Some(offset + pos as u64));
+        }
+        if filled < buffer.len() {
+            return Ok(None);
+        }
+        // Overlap chunks so a signature spanning the boundary is not missed
+        offset += (filled - signature.len() + 1) as u64;
+    }
+}
+
+/// Splits the Zip64 extended information field out of a local header's extra data.
+pub fn split_zip64_extra(extra: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+    let mut kept = Vec::with_capacity(extra.len());
+    let mut zip64 = None;
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let id = u16_at(extra, pos);
+        let len = u16_at(extra, pos + 2) as usize;
+        let end = (pos + 4 + len).min(extra.len());
+        if id == ZIP64_EXTRA_ID {
+            zip64 = Some(extra[pos + 4..end].to_vec());
+        } else {
+            kept.extend_from_slice(&extra[pos..end]);
+        }
+        pos = end;
+    }
+    (kept, zip64)
+}
+
+/// Locates the data descriptor that ends an entry written with general purpose
+/// flag bit 3, returning (crc32, compressed size, size, descriptor end).
+/// Entries with a Zip64 field in their local header use 8-byte sizes; the low
+/// half of such a size would also pass for the 4-byte layout, so that layout
+/// is only tried second.
+fn find_data_descriptor(file: &mut File, data_start: u64, file_len: u64, zip64: bool) -> io::Result<Option<(u32, u64, u64, u64)>> {
+    let mut search_from = data_start;
+    while let Some(pos) = find_signature(file, search_from, DATA_DESCRIPTOR_SIGNATURE)? {
+        let mut descriptor = [0u8; 24];
+        file.seek(SeekFrom::Start(pos))?;
+        let available = (file_len - pos).min(24) as usize;
+        file.read_exact(&mut descriptor[..available])?;
+
+        let stored = pos - data_start;
+        let long = (available >= 24 && u64_at(&descriptor, 8) == stored)
+            .then(|| (u32_at(&descriptor, 4), stored, u64_at(&descriptor, 16), pos + 24));
+        let short = (available >= 16 && u32_at(&descriptor, 8) as u64 == stored)
+            .then(|| (u32_at(&descriptor, 4), stored, u32_at(&descriptor, 12) as u64, pos + 16));
+        let found = if zip64 { long.or(short) } else { short.or(long) };
+        if found.is_some() {
+            return Ok(found);
+        }
+        search_from = pos + 1;
+    }
+    Ok(None)
+}
+
+/// Rebuilds an entry table by scanning for local file headers, for archives
+/// whose central directory is missing or unreadable.
+pub fn scan_local_headers(path: &Path) -> io::Result<Vec<RecoveredEntry>> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut entries = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(header_start) = find_signature(&mut file, search_from, LOCAL_HEADER_SIGNATURE)? {
+        search_from = header_start + 4;
+
+        let mut header = [0u8; 30];
+        file.seek(SeekFrom::Start(header_start))?;
+        if file.read_exact(&mut header).is_err() {
+            break;
+        }
+
+        let name_len = u16_at(&header, 26) as usize;
+        let extra_len = u16_at(&header, 28) as usize;
+        let data_start = header_start + 30 + (name_len + extra_len) as u64;
+        if name_len == 0 || data_start > file_len {
+            continue;
+        }
+
+        let mut raw_name = vec![0u8; name_len];
+        let mut extra = vec![0u8; extra_len];
+        file.read_exact(&mut raw_name)?;
+        file.read_exact(&mut extra)?;
+        let (extra, zip64) = split_zip64_extra(&extra);
+        let has_zip64 = zip64.is_some();
+
+        let flags = u16_at(&header, 6);
+        let mut crc32 = u32_at(&header, 14);
+        let mut compressed_size = u32_at(&header, 18) as u64;
+        let mut size = u32_at(&header, 22) as u64;
+        if let Some(zip64) = zip64.filter(|field| field.len() >= 16) {
+            size = u64_at(&zip64, 0);
+            compressed_size = u64_at(&zip64, 8);
+        }
+
+        let mut complete = true;
+        let mut data_end = data_start + compressed_size;
+        if flags & FLAG_DATA_DESCRIPTOR != 0 {
+            match find_data_descriptor(&mut file, data_start, file_len, has_zip64)? {
+                Some((descriptor_crc, descriptor_compressed, descriptor_size, descriptor_end)) => {
+                    crc32 = descriptor_crc;
+                    compressed_size = descriptor_compressed;
+                    size = descriptor_size;
+                    data_end = descriptor_end;
+                }
+                None => {
+                    complete = false;
+                    data_end = data_start;
+                }
+            }
+        } else if data_end > file_len {
+            complete = false;
+        }
+
+        entries.push(RecoveredEntry {
+            name: String::from_utf8_lossy(&raw_name).into_owned(),
+            raw_name,
+            version_needed: u16_at(&header, 4),
+            flags,
+            method: u16_at(&header, 8),
+            mod_time: u16_at(&header, 10),
+            mod_date: u16_at(&header, 12),
+            crc32,
+            compressed_size,
+            size,
+            extra,
+            data_start,
+            complete,
+        });
+
+        if complete {
+            search_from = data_end;
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Writes the complete entries into a fresh archive with a rebuilt central
+/// directory. Entry data is copied raw, so nothing is recompressed or decrypted.
+/// The output is written to a temporary file and renamed into place.
+pub fn write_repaired_archive(source: &Path, entries: &[RecoveredEntry], output: &Path) -> io::Result<usize> {
+    let entries: Vec<RawEntry> = entries
+        .iter()
+        .filter(|entry| entry.complete)
+        .map(|entry| RawEntry {
+            name: entry.name.clone(),
+            raw_name: entry.raw_name.clone(),
+            version_made_by: 45, // MS-DOS, spec 4.5
+            version_needed: entry.version_needed,
+            // Sizes were recovered, so no data descriptor is needed
+            flags: entry.flags & !FLAG_DATA_DESCRIPTOR,
+            method: entry.method,
+            mod_time: entry.mod_time,
+            mod_date: entry.mod_date,
+            crc32: entry.crc32,
+            compressed_size: entry.compressed_size,
+            size: entry.size,
+            local_extra: entry.extra.clone(),
+            central_extra: entry.extra.clone(),
+            comment: Vec::new(),
+            internal_attributes: 0,
+            external_attributes: if entry.is_directory() { 0x10 } else { 0 },
+            data_start: entry.data_start,
+        })
+        .collect();
+
+    let (temp_path, file) = create_temp_beside(output)?;
+    let result = (|| -> io::Result<()> {
+        let mut writer = BufWriter::new(file);
+        write_raw_archive(source, &entries, &[], &mut writer, |_| {})?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&temp_path, output)
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// A stored, streamed entry: local header with flag bit 3 and zeroed
+    /// sizes, the data, then a data descriptor in the 4- or 8-byte size layout.
+    fn streamed_entry(name: &str, data: &[u8], zip64: bool) -> Vec<u8> {
+        let crc = crc32fast::hash(data);
+        let extra: Vec<u8> = if zip64 {
+            [&ZIP64_EXTRA_ID.to_le_bytes()[..], &16u16.to_le_bytes(), &[0u8; 16]].concat()
+        } else {
+            Vec::new()
+        };
+        let mut out = Vec::new();
+        out.extend_from_slice(LOCAL_HEADER_SIGNATURE);
+        out.extend_from_slice(&45u16.to_le_bytes());
+        out.extend_from_slice(&FLAG_DATA_DESCRIPTOR.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // stored
+        out.extend_from_slice(&[0u8; 4]); // time and date
+        out.extend_from_slice(&[0u8; 12]); // crc and sizes, in the descriptor
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&extra);
+        out.extend_from_slice(data);
+        out.extend_from_slice(DATA_DESCRIPTOR_SIGNATURE);
+        out.extend_from_slice(&crc.to_le_bytes());
+        if zip64 {
+            out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        } else {
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn recovers_streamed_entries_in_both_descriptor_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let damaged = dir.path().join("damaged.zip");
+        let bytes = [streamed_entry("big.txt", b"hello zip64", true), streamed_entry("small.txt", b"hi", false)].concat();
+        std::fs::write(&damaged, bytes).unwrap();
+
+        let entries = scan_local_headers(&damaged).unwrap();
+        let summary: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.compressed_size, e.size, e.complete)).collect();
+        assert_eq!(summary, [("big.txt", 11, 11, true), ("small.txt", 2, 2, true)]);
+
+        let repaired = dir.path().join("repaired.zip");
+        assert_eq!(write_repaired_archive(&damaged, &entries, &repaired).unwrap(), 2);
+        let mut archive = zip::ZipArchive::new(File::open(&repaired).unwrap()).unwrap();
+        let mut text = String::new();
+        archive.by_name("big.txt").unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello zip64");
+    }
+
+    #[test]
+    fn failed_repair_leaves_no_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let damaged = dir.path().join("damaged.zip");
+        std::fs::write(&damaged, streamed_entry("a.txt", b"data", false)).unwrap();
+        let entries = scan_local_headers(&damaged).unwrap();
+        std::fs::remove_file(&damaged).unwrap();
+
+        assert!(write_repaired_archive(&damaged, &entries, &dir.path().join("out.zip")).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
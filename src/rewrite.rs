@@ -0,0 +1,684 @@
+use crate::filters::{FilterSet, IgnoreFile};
+use crate::metadata::file_options;
+use crate::recovery::split_zip64_extra;
+use crate::utils::{u16_at, u32_at, u64_at};
+use crate::sparse::SparseReader;
+use crate::utils::create_temp_beside;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+pub const LOCAL_HEADER_SIGNATURE: &[u8; 4] = b"PK\x03\x04";
+pub const CENTRAL_HEADER_SIGNATURE: &[u8; 4] = b"PK\x01\x02";
+pub const DATA_DESCRIPTOR_SIGNATURE: &[u8; 4] = b"PK\x07\x08";
+pub const END_OF_CENTRAL_DIRECTORY_SIGNATURE: &[u8; 4] = b"PK\x05\x06";
+pub const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: &[u8; 4] = b"PK\x06\x06";
+pub const ZIP64_LOCATOR_SIGNATURE: &[u8; 4] = b"PK\x06\x07";
+pub const ZIP64_EXTRA_ID: u16 = 0x0001;
+const UNICODE_PATH_EXTRA_ID: u16 = 0x7075;
+const EXTENDED_TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+pub const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const FLAG_UTF8: u16 = 0x0800;
+pub const MAX_32: u64 = 0xFFFF_FFFF;
+pub const COMPRESSION_LEVEL: i64 = 5;
+
+/// An entry copied byte for byte from a source archive. Headers are rebuilt
+/// from these fields, so the entry can be renamed without touching its data;
+/// encrypted entries stay encrypted.
+#[derive(Clone)]
+pub struct RawEntry {
+    pub name: String,
+    pub raw_name: Vec<u8>,
+    pub version_made_by: u16,
+    pub version_needed: u16,
+    pub flags: u16,
+    pub method: u16,
+    pub mod_time: u16,
+    pub mod_date: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    /// Extra fields without Zip64 information, which is rebuilt on write
+    pub local_extra: Vec<u8>,
+    pub central_extra: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub internal_attributes: u16,
+    pub external_attributes: u32,
+    pub data_start: u64,
+}
+
+impl RawEntry {
+    pub fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+        self.raw_name = name.as_bytes().to_vec();
+        self.flags |= FLAG_UTF8;
+        // A Unicode path field would override the new name in some readers
+        self.local_extra = without_extra_field(&self.local_extra, UNICODE_PATH_EXTRA_ID);
+        self.central_extra = without_extra_field(&self.central_extra, UNICODE_PATH_EXTRA_ID);
+    }
+
+    /// Modification time in Unix seconds from the extended timestamp field, if present.
+    pub fn modified_unix(&self) -> Option<i64> {
+        let field = extra_field(&self.central_extra, EXTENDED_TIMESTAMP_EXTRA_ID)?;
+        // Flag bit 0 says the modification time comes first
+        if field.len() < 5 || field[0] & 1 == 0 {
+            return None;
+        }
+        Some(u32_at(field, 1) as i32 as i64)
+    }
+}
+
+fn extra_field(extra: &[u8], id: u16) -> Option<&[u8]> {
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let len = u16_at(extra, pos + 2) as usize;
+        let end = (pos + 4 + len).min(extra.len());
+        if u16_at(extra, pos) == id {
+            return Some(&extra[pos + 4..end]);
+        }
+        pos = end;
+    }
+    None
+}
+
+fn without_extra_field(extra: &[u8], id: u16) -> Vec<u8> {
+    let mut kept = Vec::with_capacity(extra.len());
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let len = u16_at(extra, pos + 2) as usize;
+        let end = (pos + 4 + len).min(extra.len());
+        if u16_at(extra, pos) != id {
+            kept.extend_from_slice(&extra[pos..end]);
+        }
+        pos = end;
+    }
+    kept
+}
+
+/// Reads every central directory record of `path`, resolving Zip64 sizes and
+/// offsets, along with the archive comment.
+pub fn read_raw_entries(path: &Path) -> Result<(Vec<RawEntry>, Vec<u8>), Box<dyn std::error::Error>> {
+    let archive = ZipArchive::new(File::open(path)?)?;
+    let directory_start = archive.central_directory_start();
+    let comment = archive.comment().to_vec();
+
+    let mut reader = BufReader::new(File::open(path)?);
+    reader.seek(SeekFrom::Start(directory_start))?;
+
+    let mut entries = Vec::new();
+    let mut header = [0u8; 46];
+    loop {
+        if reader.read_exact(&mut header).is_err() || &header[0..4] != CENTRAL_HEADER_SIGNATURE {
+            break;
+        }
+        let name_len = u16_at(&header, 28) as usize;
+        let extra_len = u16_at(&header, 30) as usize;
+        let comment_len = u16_at(&header, 32) as usize;
+
+        let mut variable = vec![0u8; name_len + extra_len + comment_len];
+        reader.read_exact(&mut variable)?;
+        let raw_name = variable[..name_len].to_vec();
+        let (central_extra, zip64) = split_zip64_extra(&variable[name_len..name_len + extra_len]);
+
+        let mut size = u32_at(&header, 24) as u64;
+        let mut compressed_size = u32_at(&header, 20) as u64;
+        let mut header_offset = u32_at(&header, 42) as u64;
+        if let Some(zip64) = zip64 {
+            // Only the fields saturated in the fixed header are present, in this order
+            let mut values = zip64.chunks_exact(8).map(|chunk| u64_at(chunk, 0));
+            for field in [&mut size, &mut compressed_size, &mut header_offset] {
+                if *field == MAX_32 {
+                    *field = values.next().ok_or("Truncated Zip64 extra field")?;
+                }
+            }
+        }
+
+        entries.push(RawEntry {
+            name: String::from_utf8_lossy(&raw_name).into_owned(),
+            raw_name,
+            version_made_by: u16_at(&header, 4),
+            version_needed: u16_at(&header, 6),
+            flags: u16_at(&header, 8),
+            method: u16_at(&header, 10),
+            mod_time: u16_at(&header, 12),
+            mod_date: u16_at(&header, 14),
+            crc32: u32_at(&header, 16),
+            compressed_size,
+            size,
+            local_extra: Vec::new(),
+            central_extra,
+            comment: variable[name_len + extra_len..].to_vec(),
+            internal_attributes: u16_at(&header, 36),
+            external_attributes: u32_at(&header, 38),
+            data_start: header_offset,
+        });
+    }
+
+    // The local header's extra fields may differ from the central copy
+    let mut file = File::open(path)?;
+    for entry in &mut entries {
+        let header_offset = entry.data_start;
+        let mut local = [0u8; 30];
+        file.seek(SeekFrom::Start(header_offset))?;
+        file.read_exact(&mut local)?;
+        if &local[0..4] != LOCAL_HEADER_SIGNATURE {
+            return Err(format!("Missing local header for {}", entry.name).into());
+        }
+        let name_len = u16_at(&local, 26) as u64;
+        let extra_len = u16_at(&local, 28) as usize;
+        let mut extra = vec![0u8; extra_len];
+        file.seek(SeekFrom::Start(header_offset + 30 + name_len))?;
+        file.read_exact(&mut extra)?;
+        entry.local_extra = split_zip64_extra(&extra).0;
+        entry.data_start = header_offset + 30 + name_len + extra_len as u64;
+    }
+
+    Ok((entries, comment))
+}
+
+fn zip64_field(values: &[u64]) -> Vec<u8> {
+    let mut field = Vec::with_capacity(4 + values.len() * 8);
+    field.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+    field.extend_from_slice(&((values.len() * 8) as u16).to_le_bytes());
+    for value in values {
+        field.extend_from_slice(&value.to_le_bytes());
+    }
+    field
+}
+
+fn clamp_32(value: u64) -> u32 {
+    value.min(MAX_32) as u32
+}
+
+/// Writes `entries` into a complete archive, copying their data raw from
+/// `source`. Nothing is recompressed or decrypted. `progress` receives the
+/// number of data bytes copied so far.
+pub fn write_raw_archive<W: Write>(
+    source: &Path,
+    entries: &[RawEntry],
+    comment: &[u8],
+    writer: &mut W,
+    mut progress: impl FnMut(u64),
+) -> io::Result<()> {
+    let mut input = File::open(source)?;
+
+    let mut written = Vec::with_capacity(entries.len());
+    let mut offset = 0u64;
+    let mut copied_total = 0u64;
+    for entry in entries {
+        let needs_zip64 = entry.size >= MAX_32 || entry.compressed_size >= MAX_32;
+        let descriptor = entry.flags & FLAG_DATA_DESCRIPTOR != 0;
+        let mut extra = entry.local_extra.clone();
+        if needs_zip64 {
+            extra.extend_from_slice(&zip64_field(&[entry.size, entry.compressed_size]));
+        }
+        let version_needed = if needs_zip64 { entry.version_needed.max(45) } else { entry.version_needed };
+        // With a data descriptor the local header leaves CRC and sizes empty
+        let (local_crc, local_compressed, local_size) = match (descriptor, needs_zip64) {
+            (true, false) => (0, 0, 0),
+            (true, true) => (0, MAX_32, MAX_32),
+            (false, true) => (entry.crc32, MAX_32, MAX_32),
+            (false, false) => (entry.crc32, entry.compressed_size, entry.size),
+        };
+
+        writer.write_all(LOCAL_HEADER_SIGNATURE)?;
+        writer.write_all(&version_needed.to_le_bytes())?;
+        writer.write_all(&entry.flags.to_le_bytes())?;
+        writer.write_all(&entry.method.to_le_bytes())?;
+        writer.write_all(&entry.mod_time.to_le_bytes())?;
+        writer.write_all(&entry.mod_date.to_le_bytes())?;
+        writer.write_all(&local_crc.to_le_bytes())?;
+        writer.write_all(&clamp_32(local_compressed).to_le_bytes())?;
+        writer.write_all(&clamp_32(local_size).to_le_bytes())?;
+        writer.write_all(&(entry.raw_name.len() as u16).to_le_bytes())?;
+        writer.write_all(&(extra.len() as u16).to_le_bytes())?;
+        writer.write_all(&entry.raw_name)?;
+        writer.write_all(&extra)?;
+
+        input.seek(SeekFrom::Start(entry.data_start))?;
+        let copied = io::copy(&mut (&mut input).take(entry.compressed_size), writer)?;
+        if copied != entry.compressed_size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} is truncated", entry.name)));
+        }
+        copied_total += copied;
+        progress(copied_total);
+
+        let mut descriptor_len = 0;
+        if descriptor {
+            writer.write_all(DATA_DESCRIPTOR_SIGNATURE)?;
+            writer.write_all(&entry.crc32.to_le_bytes())?;
+            if needs_zip64 {
+                writer.write_all(&entry.compressed_size.to_le_bytes())?;
+                writer.write_all(&entry.size.to_le_bytes())?;
+                descriptor_len = 24;
+            } else {
+                writer.write_all(&(entry.compressed_size as u32).to_le_bytes())?;
+                writer.write_all(&(entry.size as u32).to_le_bytes())?;
+                descriptor_len = 16;
+            }
+        }
+
+        written.push((entry, offset, version_needed));
+        offset += 30 + (entry.raw_name.len() + extra.len()) as u64 + entry.compressed_size + descriptor_len;
+    }
+
+    let directory_start = offset;
+    for (entry, header_offset, version_needed) in &written {
+        let mut zip64_values = Vec::new();
+        if entry.size >= MAX_32 {
+            zip64_values.push(entry.size);
+        }
+        if entry.compressed_size >= MAX_32 {
+            zip64_values.push(entry.compressed_size);
+        }
+        if *header_offset >= MAX_32 {
+            zip64_values.push(*header_offset);
+        }
+        let mut extra = entry.central_extra.clone();
+        if !zip64_values.is_empty() {
+            extra.extend_from_slice(&zip64_field(&zip64_values));
+        }
+
+        writer.write_all(CENTRAL_HEADER_SIGNATURE)?;
+        writer.write_all(&entry.version_made_by.to_le_bytes())?;
+        writer.write_all(&version_needed.to_le_bytes())?;
+        writer.write_all(&entry.flags.to_le_bytes())?;
+        writer.write_all(&entry.method.to_le_bytes())?;
+        writer.write_all(&entry.mod_time.to_le_bytes())?;
+        writer.write_all(&entry.mod_date.to_le_bytes())?;
+        writer.write_all(&entry.crc32.to_le_bytes())?;
+        writer.write_all(&clamp_32(entry.compressed_size).to_le_bytes())?;
+        writer.write_all(&clamp_32(entry.size).to_le_bytes())?;
+        writer.write_all(&(entry.raw_name.len() as u16).to_le_bytes())?;
+        writer.write_all(&(extra.len() as u16).to_le_bytes())?;
+        writer.write_all(&(entry.comment.len() as u16).to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?; // disk number
+        writer.write_all(&entry.internal_attributes.to_le_bytes())?;
+        writer.write_all(&entry.external_attributes.to_le_bytes())?;
+        writer.write_all(&clamp_32(*header_offset).to_le_bytes())?;
+        writer.write_all(&entry.raw_name)?;
+        writer.write_all(&extra)?;
+        writer.write_all(&entry.comment)?;
+        offset += 46 + (entry.raw_name.len() + extra.len() + entry.comment.len()) as u64;
+    }
+
+    let directory_size = offset - directory_start;
+    let count = written.len() as u64;
+    if count >= 0xFFFF || directory_start >= MAX_32 || directory_size >= MAX_32 {
+        writer.write_all(ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE)?;
+        writer.write_all(&44u64.to_le_bytes())?;
+        writer.write_all(&45u16.to_le_bytes())?;
+        writer.write_all(&45u16.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&count.to_le_bytes())?;
+        writer.write_all(&count.to_le_bytes())?;
+        writer.write_all(&directory_size.to_le_bytes())?;
+        writer.write_all(&directory_start.to_le_bytes())?;
+
+        writer.write_all(ZIP64_LOCATOR_SIGNATURE)?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&1u32.to_le_bytes())?;
+    }
+
+    let comment = &comment[..comment.len().min(u16::MAX as usize)];
+    writer.write_all(END_OF_CENTRAL_DIRECTORY_SIGNATURE)?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&(count.min(0xFFFF) as u16).to_le_bytes())?;
+    writer.write_all(&(count.min(0xFFFF) as u16).to_le_bytes())?;
+    writer.write_all(&clamp_32(directory_size).to_le_bytes())?;
+    writer.write_all(&clamp_32(directory_start).to_le_bytes())?;
+    writer.write_all(&(comment.len() as u16).to_le_bytes())?;
+    writer.write_all(comment)?;
+    Ok(())
+}
+
+/// How symbolic links met while collecting files are archived.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LinkPolicy {
+    /// Store the link itself, using the Unix mode convention
+    #[default]
+    Store,
+    /// Archive what the link points to; directory loops are skipped
+    Follow,
+    Skip,
+}
+
+impl LinkPolicy {
+    pub const ALL: [LinkPolicy; 3] = [LinkPolicy::Store, LinkPolicy::Follow, LinkPolicy::Skip];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            LinkPolicy::Store => "Store as links",
+            LinkPolicy::Follow => "Follow links",
+            LinkPolicy::Skip => "Skip links",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "store" => Some(LinkPolicy::Store),
+            "follow" => Some(LinkPolicy::Follow),
+            "skip" => Some(LinkPolicy::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// A file from disk to be compressed into the archive.
+#[derive(Clone)]
+pub struct NewEntry {
+    pub source: PathBuf,
+    /// Entry name inside the archive; a trailing '/' adds a directory
+    pub name: String,
+    pub compression: zip::CompressionMethod,
+    pub encrypt: bool,
+    pub aes_mode: zip::AesMode,
+    /// Target of a symbolic link stored as a link rather than followed
+    pub link_target: Option<PathBuf>,
+}
+
+impl NewEntry {
+    pub fn new(source: PathBuf, name: String, encrypt: bool) -> Self {
+        Self {
+            source,
+            name,
+            compression: zip::CompressionMethod::Deflated,
+            encrypt,
+            aes_mode: zip::AesMode::Aes256,
+            link_target: None,
+        }
+    }
+
+    /// False for folders and stored links
+    pub fn is_file(&self) -> bool {
+        !self.name.ends_with('/') && self.link_target.is_none()
+    }
+
+    /// Metadata of the file itself, or of the link when it is stored as one.
+    pub fn metadata(&self) -> io::Result<std::fs::Metadata> {
+        match self.link_target {
+            Some(_) => std::fs::symlink_metadata(&self.source),
+            None => std::fs::metadata(&self.source),
+        }
+    }
+}
+
+/// Identifies a directory so that loops through followed links can be detected.
+fn directory_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Entry names for `paths` placed under `folder` (empty or ending in '/').
+/// Directories are walked recursively and keep their own entries; symbolic
+/// links are handled according to `links`. Entries rejected by `filters` are
+/// left out, and excluded folders are not walked.
+pub fn collect_new_entries(
+    paths: &[PathBuf],
+    folder: &str,
+    encrypt: bool,
+    links: LinkPolicy,
+    filters: &FilterSet,
+) -> io::Result<Vec<NewEntry>> {
+    let sources: Vec<(PathBuf, String)> = paths
+        .iter()
+        .map(|path| (path.clone(), format!("{}{}", folder, path.file_name().unwrap_or_default().to_string_lossy())))
+        .collect();
+    collect_named_entries(&sources, encrypt, links, filters)
+}
+
+/// Like [`collect_new_entries`], with the entry name of each source given
+/// (without a trailing '/'); names below a folder follow the one given for it.
+pub fn collect_named_entries(
+    sources: &[(PathBuf, String)],
+    encrypt: bool,
+    links: LinkPolicy,
+    filters: &FilterSet,
+) -> io::Result<Vec<NewEntry>> {
+    struct Walk<'a> {
+        encrypt: bool,
+        links: LinkPolicy,
+        filters: &'a FilterSet,
+        /// Ignore files of the directories being walked
+        ignores: Vec<IgnoreFile>,
+        /// Directories being walked, from the top down
+        ancestors: Vec<(u64, u64)>,
+        entries: Vec<NewEntry>,
+    }
+
+    fn walk(path: &Path, name: String, top_level: bool, state: &mut Walk) -> io::Result<()> {
+        let link_metadata = std::fs::symlink_metadata(path)?;
+        let is_link = link_metadata.file_type().is_symlink();
+        if is_link {
+            match state.links {
+                LinkPolicy::Skip => return Ok(()),
+                LinkPolicy::Store => {
+                    if state.filters.excludes(&state.ignores, &name, &link_metadata, top_level) {
+                        return Ok(());
+                    }
+                    let mut entry = NewEntry::new(path.to_path_buf(), name, state.encrypt);
+                    entry.link_target = Some(std::fs::read_link(path)?);
+                    state.entries.push(entry);
+                    return Ok(());
+                }
+                LinkPolicy::Follow => {}
+            }
+        }
+
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if is_link => {
+                tracing::warn!("Skipping broken link {}: {}", path.display(), e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        if state.filters.excludes(&state.ignores, &name, &metadata, top_level) {
+            return Ok(());
+        }
+        if !metadata.is_dir() {
+            state.entries.push(NewEntry::new(path.to_path_buf(), name, state.encrypt));
+            return Ok(());
+        }
+
+        let id = directory_id(&metadata);
+        if id.is_some_and(|id| state.ancestors.contains(&id)) {
+            tracing::warn!("Skipping {}: link loops back to a parent folder", path.display());
+            return Ok(());
+        }
+        let name = format!("{}/", name);
+        let directory = state.entries.len();
+        state.entries.push(NewEntry::new(path.to_path_buf(), name.clone(), state.encrypt));
+        state.ancestors.extend(id);
+        let ignore_file = state.filters.ignore_file(path, &name);
+        let has_ignore_file = ignore_file.is_some();
+        state.ignores.extend(ignore_file);
+        let mut children: Vec<_> = std::fs::read_dir(path)?.collect::<Result<_, _>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let child_name = format!("{}{}", name, child.file_name().to_string_lossy());
+            walk(&child.path(), child_name, false, state)?;
+        }
+        if has_ignore_file {
+            state.ignores.pop();
+        }
+        if id.is_some() {
+            state.ancestors.pop();
+        }
+        // With an include list, only folders leading to included files are kept
+        if state.filters.has_includes() && state.entries.len() == directory + 1 {
+            state.entries.pop();
+        }
+        Ok(())
+    }
+
+    let mut state = Walk {
+        encrypt,
+        links,
+        filters,
+        ignores: Vec::new(),
+        ancestors: Vec::new(),
+        entries: Vec::new(),
+    };
+    for (path, name) in sources {
+        walk(path, name.clone(), true, &mut state)?;
+    }
+    Ok(state.entries)
+}
+
+/// Rewrites `archive_path` so it holds `keep` (raw-copied, in order) followed by
+/// `add`. The result is built next to the archive and renamed over it, so the
+/// original stays intact if anything fails. `progress` receives a 0..1 fraction.
+pub fn rewrite_archive(
+    archive_path: &Path,
+    keep: &[RawEntry],
+    add: &[NewEntry],
+    comment: &[u8],
+    password: Option<&str>,
+    progress: impl Fn(f32),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keep_bytes: u64 = keep.iter().map(|entry| entry.compressed_size).sum();
+    let add_bytes: u64 = add
+        .iter()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .sum();
+    let total = (keep_bytes + add_bytes).max(1) as f32;
+
+    let (temp_path, file) = create_temp_beside(archive_path)?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(file);
+        write_raw_archive(archive_path, keep, comment, &mut writer, |copied| progress(copied as f32 / total))?;
+        writer.into_inner().map_err(|e| e.into_error())?;
+
+        if !add.is_empty() {
+            let file = OpenOptions::new().read(true).write(true).open(&temp_path)?;
+            let mut zip = zip::ZipWriter::new_append(file)?;
+            let mut added = 0u64;
+            for entry in add {
+                let metadata = entry.metadata()?;
+                let mut options = file_options(&entry.source, &metadata)
+                    .compression_method(entry.compression)
+                    .large_file(metadata.len() > MAX_32);
+                if entry.compression == zip::CompressionMethod::Deflated {
+                    options = options.compression_level(Some(COMPRESSION_LEVEL));
+                }
+                if let (true, Some(password)) = (entry.encrypt, password) {
+                    options = options.with_aes_encryption(entry.aes_mode, password);
+                }
+
+                if entry.name.ends_with('/') {
+                    zip.add_directory(entry.name.trim_end_matches('/'), options)?;
+                    continue;
+                }
+                if let Some(target) = &entry.link_target {
+                    zip.add_symlink(entry.name.as_str(), target.to_string_lossy(), options)?;
+                    continue;
+                }
+                zip.start_file(entry.name.as_str(), options)?;
+                let mut input = SparseReader::open(&entry.source)?;
+                let mut buffer = vec![0; 1024 * 1024];
+                loop {
+                    let n = input.read(&mut buffer)?;
+                    if n == 0 {
+                        break;
+                    }
+                    zip.write_all(&buffer[..n])?;
+                    added += n as u64;
+                    progress((keep_bytes + added) as f32 / total);
+                }
+            }
+            zip.finish()?;
+        }
+
+        File::open(&temp_path)?.sync_all()?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    std::fs::rename(&temp_path, archive_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn read_all(path: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        (0..archive.len())
+            .map(|index| {
+                let mut file = archive.by_index(index).unwrap();
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).unwrap();
+                (file.name().to_string(), data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rewrite_keeps_renames_and_adds_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("foo.zip");
+        let mut writer = ZipWriter::new(File::create(&archive).unwrap());
+        for (name, data) in [("a.txt", "alpha"), ("b.txt", "beta")] {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+        writer.set_comment("note");
+        writer.finish().unwrap();
+        // Must survive the rewrite untouched
+        std::fs::write(dir.path().join("foo.partial"), b"unrelated").unwrap();
+        let added = dir.path().join("c.txt");
+        std::fs::write(&added, b"gamma").unwrap();
+
+        let (mut keep, comment) = read_raw_entries(&archive).unwrap();
+        keep.retain(|entry| entry.name != "a.txt");
+        keep[0].rename("docs/b.txt");
+        let add = [NewEntry::new(added, "c.txt".to_string(), false)];
+        rewrite_archive(&archive, &keep, &add, &comment, None, |_| {}).unwrap();
+
+        assert_eq!(
+            read_all(&archive),
+            [("docs/b.txt".to_string(), b"beta".to_vec()), ("c.txt".to_string(), b"gamma".to_vec())]
+        );
+        assert_eq!(ZipArchive::new(File::open(&archive).unwrap()).unwrap().comment(), b"note");
+        assert_eq!(std::fs::read(dir.path().join("foo.partial")).unwrap(), b"unrelated");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn zip64_sizes_round_trip_through_the_raw_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("small.zip");
+        let mut writer = ZipWriter::new(File::create(&archive).unwrap());
+        writer.start_file("x", SimpleFileOptions::default().large_file(true)).unwrap();
+        writer.write_all(b"data").unwrap();
+        writer.finish().unwrap();
+
+        let (entries, comment) = read_raw_entries(&archive).unwrap();
+        let mut output = Vec::new();
+        write_raw_archive(&archive, &entries, &comment, &mut output, |_| {}).unwrap();
+        let copy = dir.path().join("copy.zip");
+        std::fs::write(&copy, output).unwrap();
+        assert_eq!(read_all(&copy), [("x".to_string(), b"data".to_vec())]);
+    }
+}
@@ -0,0 +1,368 @@
+use crate::integrity::find_overlapping_entries;
+use crate::utils::{available_space, get_formatted_size};
+use std::fmt;
+use std::io::{Read, Seek};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use zip::ZipArchive;
+
+/// Links followed while resolving one link target, as with Linux's ELOOP
+const MAX_LINK_HOPS: usize = 40;
+const NESTED_ARCHIVE_EXTENSIONS: &[&str] = &["zip", "7z", "rar", "gz", "tgz", "bz2", "xz", "zst", "tar", "jar"];
+
+#[derive(Clone)]
+pub struct SafetyLimits {
+    pub max_total_size: u64,
+    pub max_ratio: f64,
+    pub max_entries: usize,
+    pub detect_overlaps: bool,
+    pub check_free_space: bool,
+}
+
+impl Default for SafetyLimits {
+    fn default() -> Self {
+        Self {
+            max_total_size: 16 * 1024 * 1024 * 1024, // 16 GB
+            max_ratio: 100.0,
+            max_entries: 1_000_000,
+            detect_overlaps: true,
+            check_free_space: true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum SafetyViolation {
+    TooManyEntries { count: usize, limit: usize },
+    TotalSizeExceeded { size: u64, limit: u64 },
+    RatioExceeded { name: String, ratio: f64, limit: f64 },
+    OverlappingEntries { first: String, second: String },
+    NestedArchives { count: usize, example: String },
+    InsufficientSpace { needed: u64, available: u64 },
+}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafetyViolation::TooManyEntries { count, limit } => {
+                write!(f, "Archive has {} entries (limit {})", count, limit)
+            }
+            SafetyViolation::TotalSizeExceeded { size, limit } => write!(
+                f,
+                "Uncompressed size is {} (limit {})",
+                get_formatted_size(*size),
+                get_formatted_size(*limit)
+            ),
+            SafetyViolation::RatioExceeded { name, ratio, limit } => {
+                write!(f, "{} expands {:.0}x (limit {:.0}x)", name, ratio, limit)
+            }
+            SafetyViolation::OverlappingEntries { first, second } => {
+                write!(f, "Entries {} and {} share compressed data (overlapping-entry bomb)", first, second)
+            }
+            SafetyViolation::NestedArchives { count, example } => {
+                write!(f, "Archive contains {} nested archives, e.g. {}", count, example)
+            }
+            SafetyViolation::InsufficientSpace { needed, available } => write!(
+                f,
+                "Output needs about {} but only {} is free at the destination",
+                get_formatted_size(*needed),
+                get_formatted_size(*available)
+            ),
+        }
+    }
+}
+
+fn compression_ratio(size: u64, compressed_size: u64) -> f64 {
+    size as f64 / compressed_size.max(1) as f64
+}
+
+fn is_nested_archive(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| NESTED_ARCHIVE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Checks an archive's declared metadata before it is listed. Only headers are
+/// read, so this is cheap even for very large archives.
+pub fn check_archive<R: Read + Seek>(archive: &mut ZipArchive<R>, limits: &SafetyLimits) -> Vec<SafetyViolation> {
+    let mut violations = Vec::new();
+
+    if archive.len() > limits.max_entries {
+        violations.push(SafetyViolation::TooManyEntries {
+            count: archive.len(),
+            limit: limits.max_entries,
+        });
+    }
+
+    let mut total_size = 0u64;
+    let mut worst_ratio: Option<(String, f64)> = None;
+    let mut nested = Vec::new();
+    for i in 0..archive.len() {
+        let Ok(file) = archive.by_index_raw(i) else {
+            continue;
+        };
+        total_size = total_size.saturating_add(file.size());
+
+        let ratio = compression_ratio(file.size(), file.compressed_size());
+        if ratio > limits.max_ratio && worst_ratio.as_ref().is_none_or(|(_, worst)| ratio > *worst) {
+            worst_ratio = Some((file.name().to_string(), ratio));
+        }
+        if is_nested_archive(file.name()) {
+            nested.push(file.name().to_string());
+        }
+    }
+
+    if total_size > limits.max_total_size {
+        violations.push(SafetyViolation::TotalSizeExceeded {
+            size: total_size,
+            limit: limits.max_total_size,
+        });
+    }
+    if let Some((name, ratio)) = worst_ratio {
+        violations.push(SafetyViolation::RatioExceeded {
+            name,
+            ratio,
+            limit: limits.max_ratio,
+        });
+    }
+    if let Some(example) = nested.first() {
+        violations.push(SafetyViolation::NestedArchives {
+            count: nested.len(),
+            example: example.clone(),
+        });
+    }
+    if limits.detect_overlaps {
+        if let Some((first, second)) = find_overlapping_entries(archive).into_iter().next() {
+            violations.push(SafetyViolation::OverlappingEntries { first, second });
+        }
+    }
+
+    violations
+}
+
+/// Checks a single entry before it is written to `destination`.
+pub fn check_extraction(
+    name: &str,
+    size: u64,
+    compressed_size: u64,
+    destination: &Path,
+    limits: &SafetyLimits,
+) -> Vec<SafetyViolation> {
+    let mut violations = Vec::new();
+
+    if size > limits.max_total_size {
+        violations.push(SafetyViolation::TotalSizeExceeded {
+            size,
+            limit: limits.max_total_size,
+        });
+    }
+
+    let ratio = compression_ratio(size, compressed_size);
+    if ratio > limits.max_ratio {
+        violations.push(SafetyViolation::RatioExceeded {
+            name: name.to_string(),
+            ratio,
+            limit: limits.max_ratio,
+        });
+    }
+
+    violations.extend(check_output_space(size, destination, limits));
+    violations
+}
+
+/// Checks that `needed` bytes fit on the filesystem holding `destination`.
+pub fn check_output_space(needed: u64, destination: &Path, limits: &SafetyLimits) -> Option<SafetyViolation> {
+    if !limits.check_free_space {
+        return None;
+    }
+    let available = available_space(destination).ok()?;
+    (needed > available).then_some(SafetyViolation::InsufficientSpace { needed, available })
+}
+
+/// True if a link stored at `entry_path` (relative to the extraction folder)
+/// and pointing at `target` would resolve outside that folder. Absolute
+/// targets always escape.
+pub fn link_escapes(entry_path: &Path, target: &Path) -> bool {
+    let mut depth = entry_path
+        .parent()
+        .map_or(0, |parent| parent.components().filter(|c| matches!(c, Component::Normal(_))).count());
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Where a link at `link_path` pointing at `target` really leads, following
+/// every link already on disk along the way; parts that do not exist yet are
+/// taken as written. `None` for absolute targets and link loops.
+fn resolve_link(link_path: &Path, target: &Path) -> Option<PathBuf> {
+    if target.has_root() {
+        return None;
+    }
+    let mut path = link_path.parent()?.canonicalize().ok()?;
+    let names = |path: &Path| -> Vec<OsString> { path.components().map(|c| c.as_os_str().to_os_string()).collect() };
+    let mut pending: VecDeque<OsString> = names(target).into();
+    let mut hops = 0;
+    while let Some(name) = pending.pop_front() {
+        match Path::new(&name).components().next() {
+            Some(Component::Normal(_)) => {}
+            Some(Component::ParentDir) => {
+                path.pop();
+                continue;
+            }
+            _ => continue,
+        }
+        let next = path.join(&name);
+        if !std::fs::symlink_metadata(&next).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            path = next;
+            continue;
+        }
+        hops += 1;
+        if hops > MAX_LINK_HOPS {
+            return None;
+        }
+        // Carry on from the folder holding the link, or from the root
+        let link = std::fs::read_link(&next).ok()?;
+        if link.has_root() {
+            path = PathBuf::from(Component::RootDir.as_os_str());
+        }
+        for name in names(&link).into_iter().rev() {
+            pending.push_front(name);
+        }
+    }
+    Some(path)
+}
+
+/// True if a link created at `link_path`, inside the extraction folder whose
+/// canonical path is `destination`, would lead outside it once resolved
+/// through the links already created there.
+pub fn link_leaves(destination: &Path, link_path: &Path, target: &Path) -> bool {
+    !resolve_link(link_path, target).is_some_and(|resolved| resolved.starts_with(destination))
+}
+
+/// True if any folder between `destination` and `relative` is a symbolic link,
+/// so that writing there could land outside the destination.
+pub fn passes_through_link(destination: &Path, relative: &Path) -> bool {
+    let mut path = destination.to_path_buf();
+    let Some(parent) = relative.parent() else {
+        return false;
+    };
+    parent.components().any(|component| {
+        path.push(component);
+        std::fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn limits() -> SafetyLimits {
+        SafetyLimits {
+            max_total_size: 1000,
+            max_ratio: 100.0,
+            max_entries: 3,
+            detect_overlaps: true,
+            check_free_space: false,
+        }
+    }
+
+    /// A stored archive of ten-byte entries whose central directory declares
+    /// the given uncompressed sizes.
+    fn archive_with(entries: &[(&str, u32)]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, _) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(&[0; 10]).unwrap();
+        }
+        let mut bytes = writer.finish().unwrap().into_inner();
+
+        let mut offset = ZipArchive::new(Cursor::new(&bytes)).unwrap().central_directory_start() as usize;
+        for (_, size) in entries {
+            bytes[offset + 24..offset + 28].copy_from_slice(&size.to_le_bytes());
+            let field = |at: usize| u16::from_le_bytes([bytes[offset + at], bytes[offset + at + 1]]) as usize;
+            offset += 46 + field(28) + field(30) + field(32);
+        }
+        ZipArchive::new(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn archives_at_every_limit_pass() {
+        let mut archive = archive_with(&[("a.bin", 1000), ("b.txt", 0), ("c.txt", 0)]);
+        assert!(check_archive(&mut archive, &limits()).is_empty());
+    }
+
+    #[test]
+    fn entry_count_is_limited() {
+        let mut archive = archive_with(&[("a", 10), ("b", 10), ("c", 10), ("d", 10)]);
+        let violations = check_archive(&mut archive, &limits());
+        assert!(matches!(violations[..], [SafetyViolation::TooManyEntries { count: 4, limit: 3 }]));
+    }
+
+    #[test]
+    fn total_size_is_limited() {
+        let mut archive = archive_with(&[("a.txt", 600), ("b.txt", 401)]);
+        let violations = check_archive(&mut archive, &limits());
+        assert!(matches!(violations[..], [SafetyViolation::TotalSizeExceeded { size: 1001, limit: 1000 }]));
+    }
+
+    #[test]
+    fn the_worst_ratio_is_reported() {
+        let mut limits = limits();
+        limits.max_total_size = u64::MAX;
+        let mut archive = archive_with(&[("a.bin", 1001), ("b.bin", 2000), ("c.bin", 1000)]);
+        let violations = check_archive(&mut archive, &limits);
+        let [SafetyViolation::RatioExceeded { name, ratio, .. }] = &violations[..] else {
+            panic!("expected one ratio violation");
+        };
+        assert_eq!(name, "b.bin");
+        assert_eq!(*ratio, 200.0);
+    }
+
+    #[test]
+    fn nested_archives_are_counted() {
+        let mut archive = archive_with(&[("notes.txt", 10), ("inner.ZIP", 10), ("data.tar", 10)]);
+        let violations = check_archive(&mut archive, &limits());
+        assert!(matches!(&violations[..], [SafetyViolation::NestedArchives { count: 2, example }] if example == "inner.ZIP"));
+    }
+
+    #[test]
+    fn extraction_is_checked_at_the_limits() {
+        let destination = Path::new(".");
+        assert!(check_extraction("a", 1000, 10, destination, &limits()).is_empty());
+        assert!(matches!(
+            check_extraction("a", 1001, 1001, destination, &limits())[..],
+            [SafetyViolation::TotalSizeExceeded { size: 1001, limit: 1000 }]
+        ));
+        assert!(matches!(
+            check_extraction("a", 101, 1, destination, &limits())[..],
+            [SafetyViolation::RatioExceeded { .. }]
+        ));
+        // Empty compressed data counts as one byte
+        assert!(check_extraction("a", 100, 0, destination, &limits()).is_empty());
+    }
+
+    #[test]
+    fn free_space_is_only_checked_when_enabled() {
+        let destination = std::env::temp_dir();
+        assert!(check_output_space(u64::MAX, &destination, &limits()).is_none());
+
+        let limits = SafetyLimits { check_free_space: true, ..limits() };
+        assert!(check_output_space(0, &destination, &limits).is_none());
+        assert!(matches!(
+            check_output_space(u64::MAX, &destination, &limits),
+            Some(SafetyViolation::InsufficientSpace { needed: u64::MAX, .. })
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+use rayon::prelude::*;
+use regex::bytes::{Regex, RegexBuilder};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use zip::ZipArchive;
+
+/// Lines longer than this are searched in pieces so a file without newlines
+/// cannot exhaust memory.
+const MAX_LINE: u64 = 64 * 1024;
+const MAX_HITS_PER_ENTRY: usize = 1000;
+/// Bytes of context kept on each side of a match
+const SNIPPET_CONTEXT: usize = 60;
+
+#[derive(Clone)]
+pub struct SearchOptions {
+    pub pattern: String,
+    pub use_regex: bool,
+    pub case_insensitive: bool,
+    pub skip_binary: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            use_regex: false,
+            case_insensitive: true,
+            skip_binary: true,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn build_matcher(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.use_regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        RegexBuilder::new(&pattern).case_insensitive(self.case_insensitive).build()
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchHit {
+    pub entry: String,
+    pub line_number: u64,
+    pub snippet: String,
+}
+
+/// Streams every entry through the decompressor and reports matching lines.
+/// Entries are searched in parallel; `on_entry` receives (entries done, total,
+/// hits in the entry just finished). Setting `cancel` stops the search early.
+pub fn search_contents(
+    path: &Path,
+    password: Option<&str>,
+    matcher: &Regex,
+    skip_binary: bool,
+    cancel: &AtomicBool,
+    on_entry: impl Fn(usize, usize, Vec<SearchHit>) + Sync,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let total = ZipArchive::new(File::open(path)?)?.len();
+
+    let done = AtomicUsize::new(0);
+    (0..total).into_par_iter().for_each_init(
+        || ZipArchive::new(File::open(path).ok()?).ok(),
+        |archive, index| {
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            let hits = match archive.as_mut() {
+                Some(archive) => search_entry(archive, index, password, matcher, skip_binary, cancel),
+                None => Vec::new(),
+            };
+            on_entry(done.fetch_add(1, Ordering::Relaxed) + 1, total, hits);
+        },
+    );
+
+    Ok(())
+}
+
+fn search_entry(
+    archive: &mut ZipArchive<File>,
+    index: usize,
+    password: Option<&str>,
+    matcher: &Regex,
+    skip_binary: bool,
+    cancel: &AtomicBool,
+) -> Vec<SearchHit> {
+    let file = match password {
+        Some(password) => archive.by_index_decrypt(index, password.as_bytes()),
+        None => archive.by_index(index),
+    };
+    let file = match file {
+        Ok(file) if !file.is_dir() => file,
+        Ok(_) => return Vec::new(),
+        Err(e) => {
+            tracing::warn!("Skipping entry #{} in content search: {}", index, e);
+            return Vec::new();
+        }
+    };
+    let entry = file.name().to_string();
+    let mut reader = BufReader::with_capacity(64 * 1024, file);
+
+    if skip_binary {
+        match reader.fill_buf() {
+            Ok(start) if memchr::memchr(0, start).is_some() => return Vec::new(),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("Skipping {} in content search: {}", entry, e);
+                return Vec::new();
+            }
+        }
+    }
+
+    let mut hits = Vec::new();
+    let mut line = Vec::new();
+    let mut line_number = 1;
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        line.clear();
+        let read = match (&mut reader).take(MAX_LINE).read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                tracing::warn!("Stopped searching {}: {}", entry, e);
+                break;
+            }
+        };
+
+        if let Some(found) = matcher.find(&line) {
+            hits.push(SearchHit {
+                entry: entry.clone(),
+                line_number,
+                snippet: snippet(&line, found.start(), found.end()),
+            });
+            if hits.len() >= MAX_HITS_PER_ENTRY {
+                break;
+            }
+        }
+
+        // A piece cut at MAX_LINE continues the same line
+        if line.last() == Some(&b'\n') || read as u64 != MAX_LINE {
+            line_number += 1;
+        }
+    }
+    hits
+}
+
+fn snippet(line: &[u8], start: usize, end: usize) -> String {
+    let from = start.saturating_sub(SNIPPET_CONTEXT);
+    let to = (end + SNIPPET_CONTEXT).min(line.len());
+    let text = String::from_utf8_lossy(&line[from..to]);
+    let text = text.trim();
+    format!(
+        "{}{}{}",
+        if from > 0 { "…" } else { "" },
+        text,
+        if to < line.len() && line[to..].iter().any(|b| !b.is_ascii_whitespace()) { "…" } else { "" }
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Mutex;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn archive_with_lines(dir: &Path) -> std::path::PathBuf {
+        let path = dir.join("lines.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer.start_file("a.txt", SimpleFileOptions::default()).unwrap();
+        for _ in 0..1000 {
+            writer.write_all(b"needle\n").unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn finds_matching_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = archive_with_lines(dir.path());
+        let matcher = SearchOptions { pattern: "needle".into(), ..Default::default() }.build_matcher().unwrap();
+        let hits = Mutex::new(Vec::new());
+        search_contents(&path, None, &matcher, true, &AtomicBool::new(false), |_, _, found| {
+            hits.lock().unwrap().extend(found)
+        })
+        .unwrap();
+        let hits = hits.into_inner().unwrap();
+        assert_eq!(hits.len(), MAX_HITS_PER_ENTRY);
+        assert_eq!(hits[1].line_number, 2);
+    }
+
+    #[test]
+    fn cancelled_search_stops_inside_an_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = archive_with_lines(dir.path());
+        let matcher = SearchOptions { pattern: "needle".into(), ..Default::default() }.build_matcher().unwrap();
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let hits = search_entry(&mut archive, 0, None, &matcher, true, &AtomicBool::new(true));
+        assert!(hits.is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::app::CompressionStats;
+use crate::metadata::{zip_datetime, Reproducible};
+use crate::rewrite::NewEntry;
+use crate::safety::{check_output_space, SafetyLimits, SafetyViolation};
+use crate::sparse::SparseReader;
+use sevenz_rust::lzma::LZMA2Options;
+use sevenz_rust::{AesEncoderOptions, Password, SevenZArchiveEntry, SevenZMethodConfiguration, SevenZReader, SevenZWriter};
+use std::fs::{File, Metadata};
+use std::io::{self, BufWriter, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// Directory bit of the Windows attributes
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+/// Set by p7zip when the high 16 bits of the attributes hold a Unix mode
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+const LZMA2_PRESET: u32 = 6;
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+/// The ZIP copy of a 7z archive is temporary, so it favours speed over size
+const COPY_COMPRESSION_LEVEL: i64 = 1;
+
+/// True for names that ask for a 7z archive. Numbered `.7z.001` volumes are
+/// made by splitting the finished `.7z`.
+pub fn is_seven_zip(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().to_ascii_lowercase().ends_with(".7z"))
+}
+
+/// Attributes with the Unix mode in the high bits, as p7zip writes them.
+fn attributes(metadata: &Metadata, is_directory: bool, reproducible: bool) -> u32 {
+    let directory = if is_directory { FILE_ATTRIBUTE_DIRECTORY } else { 0 };
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::MetadataExt;
+        if reproducible {
+            let executable = metadata.mode() & 0o111 != 0;
+            let permissions = if is_directory || executable { 0o755 } else { 0o644 };
+            metadata.mode() & 0o170000 | permissions
+        } else {
+            metadata.mode() & 0o177777
+        }
+    };
+    #[cfg(not(unix))]
+    let mode = {
+        let _ = (metadata, reproducible);
+        if is_directory { 0o040755 } else { 0o100644 }
+    };
+    directory | FILE_ATTRIBUTE_UNIX_EXTENSION | (mode << 16)
+}
+
+/// The Unix mode p7zip keeps in the high bits of the attributes, if any.
+fn unix_mode(entry: &SevenZArchiveEntry) -> Option<u32> {
+    let attributes = entry.windows_attributes();
+    (entry.has_windows_attributes && attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0).then_some(attributes >> 16)
+}
+
+/// Copies the 7z archive at `path` into a ZIP at `output`, so that it can be
+/// listed, previewed and extracted like any other archive. Entries keep their
+/// modification times, permissions and links; with a password, the copy is
+/// encrypted with it. The entry count, total size and free space are checked
+/// against `limits` before anything is unpacked. `progress` is called with the
+/// fraction done.
+pub fn unpack_to_zip(
+    path: &Path,
+    password: Option<&str>,
+    output: &Path,
+    limits: &SafetyLimits,
+    progress: impl Fn(f32),
+) -> Result<(), sevenz_rust::Error> {
+    let mut reader = SevenZReader::open(path, password.map(Password::from).unwrap_or_else(Password::empty))?;
+    let count = reader.archive().files.len();
+    let total_size: u64 = reader.archive().files.iter().map(|entry| entry.size()).sum();
+    let directory = output.parent().unwrap_or(Path::new("."));
+    let violation = if count > limits.max_entries {
+        Some(SafetyViolation::TooManyEntries { count, limit: limits.max_entries })
+    } else if total_size > limits.max_total_size {
+        Some(SafetyViolation::TotalSizeExceeded { size: total_size, limit: limits.max_total_size })
+    } else {
+        check_output_space(total_size, directory, limits)
+    };
+    if let Some(violation) = violation {
+        return Err(sevenz_rust::Error::other(format!("Safety warning: {}", violation)));
+    }
+
+    let mut writer = ZipWriter::new(BufWriter::new(File::create(output)?));
+    let mut unpacked = 0u64;
+    reader.for_each_entries(|entry, data| {
+        let mut options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(Some(COPY_COMPRESSION_LEVEL));
+        let modified = entry.has_last_modified_date.then(|| SystemTime::from(entry.last_modified_date()));
+        if let Some(time) = modified.and_then(zip_datetime) {
+            options = options.last_modified_time(time);
+        }
+        let mode = unix_mode(entry);
+        if let Some(mode) = mode {
+            options = options.unix_permissions(mode & 0o7777);
+        }
+        if let Some(password) = password {
+            options = options.with_aes_encryption(zip::AesMode::Aes256, password);
+        }
+
+        let name = entry.name();
+        if entry.is_directory() {
+            writer.add_directory(name, options).map_err(io::Error::other)?;
+        } else if mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+            let mut target = String::new();
+            data.read_to_string(&mut target)?;
+            writer.add_symlink(name, target, options).map_err(io::Error::other)?;
+        } else {
+            writer.start_file(name, options).map_err(io::Error::other)?;
+            io::copy(data, &mut writer)?;
+        }
+        unpacked += entry.size();
+        progress(if total_size > 0 { unpacked as f32 / total_size as f32 } else { 1.0 });
+        Ok(true)
+    })?;
+    let file = writer.finish().map_err(io::Error::other)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
+
+/// Writes `entries`, as listed by `collect_new_entries`, into a 7z archive at
+/// `output_path` with LZMA2, encrypting contents and names with AES-256 when a
+/// password is given. Entries keep their times and Unix permissions; stored
+/// links hold their target, as p7zip stores them. The 7z format has no place
+/// for owners or hard links. With `reproducible` set, entries are written in
+/// name order, all dated its time.
+pub fn compress_to_seven_zip(
+    mut entries: Vec<NewEntry>,
+    output_path: PathBuf,
+    password: Option<String>,
+    progress_tx: Sender<(f32, CompressionStats)>,
+    stats: Arc<Mutex<CompressionStats>>,
+    reproducible: Option<Reproducible>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if reproducible.is_some() {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    let mut writer = SevenZWriter::create(&output_path)?;
+    let mut methods: Vec<SevenZMethodConfiguration> = Vec::new();
+    if let Some(password) = password.as_deref() {
+        methods.push(AesEncoderOptions::new(password.into()).into());
+    }
+    methods.push(LZMA2Options::with_preset(LZMA2_PRESET).into());
+    writer.set_content_methods(methods);
+    writer.set_encrypt_header(password.is_some());
+
+    let total_size: u64 = entries
+        .iter()
+        .filter(|entry| entry.is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .sum();
+    let mut processed = 0u64;
+    for (index, entry) in entries.iter().enumerate() {
+        let metadata = entry.metadata()?;
+        let is_directory = entry.name.ends_with('/');
+        let mut archive_entry = match reproducible {
+            Some(reproducible) => {
+                let mut archive_entry = SevenZArchiveEntry::new();
+                archive_entry.last_modified_date = reproducible.system_time().try_into()?;
+                archive_entry.has_last_modified_date = true;
+                archive_entry
+            }
+            None => SevenZArchiveEntry::from_path(&entry.source, String::new()),
+        };
+        archive_entry.name = entry.name.trim_end_matches('/').to_string();
+        archive_entry.is_directory = is_directory;
+        archive_entry.has_windows_attributes = true;
+        archive_entry.windows_attributes = attributes(&metadata, is_directory, reproducible.is_some());
+
+        let data: Option<Box<dyn Read>> = match &entry.link_target {
+            Some(target) => Some(Box::new(Cursor::new(target.to_string_lossy().into_owned().into_bytes()))),
+            None if is_directory => None,
+            None => Some(Box::new(SparseReader::open(&entry.source)?)),
+        };
+        writer
+            .push_archive_entry(archive_entry, data)
+            .map_err(|e| io::Error::other(format!("{}: {}", entry.name, e)))?;
+
+        if entry.is_file() {
+            processed += metadata.len();
+        }
+        if let Ok(mut stats) = stats.lock() {
+            let progress = if total_size > 0 { processed as f32 / total_size as f32 } else { 1.0 };
+            stats.files_processed = index + 1;
+            if progress > 0.0 {
+                stats.estimated_time = Duration::from_secs_f32(stats.start_time.elapsed().as_secs_f32() / progress);
+            }
+            progress_tx.send((progress, stats.clone()))?;
+        }
+    }
+    writer.finish()?.sync_all()?;
+
+    let compressed_size = std::fs::metadata(&output_path)?.len();
+    if let Ok(mut stats) = stats.lock() {
+        stats.compressed_size = compressed_size;
+        progress_tx.send((1.0, stats.clone()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterSet;
+    use crate::rewrite::{collect_new_entries, LinkPolicy};
+    use sevenz_rust::{Password, SevenZReader};
+    use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    fn write_seven_zip(source: &Path, output: &Path, password: Option<&str>, reproducible: Option<Reproducible>) {
+        let entries = collect_new_entries(&[source.to_path_buf()], "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+        let stats = CompressionStats {
+            original_size: 0,
+            compressed_size: 0,
+            start_time: Instant::now(),
+            estimated_time: Duration::from_secs(0),
+            output_path: output.to_path_buf(),
+            files_processed: 0,
+            total_files: entries.len(),
+        };
+        let (progress_tx, _progress_rx) = channel();
+        let password = password.map(str::to_string);
+        compress_to_seven_zip(entries, output.to_path_buf(), password, progress_tx, Arc::new(Mutex::new(stats)), reproducible).unwrap();
+    }
+
+    fn read_seven_zip(path: &Path, password: &str) -> HashMap<String, (u32, Vec<u8>)> {
+        let mut reader = SevenZReader::open(path, Password::from(password)).unwrap();
+        let mut contents = HashMap::new();
+        reader
+            .for_each_entries(|entry, data| {
+                let mut bytes = Vec::new();
+                data.read_to_end(&mut bytes)?;
+                contents.insert(entry.name().to_string(), (entry.windows_attributes, bytes));
+                Ok(true)
+            })
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn recognises_seven_zip_names() {
+        assert!(is_seven_zip(Path::new("a/b.7Z")));
+        assert!(!is_seven_zip(Path::new("b.7z.001")));
+        assert!(!is_seven_zip(Path::new("b.zip")));
+    }
+
+    #[test]
+    fn entries_round_trip_with_their_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir_all(source.join("bin")).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        std::fs::write(source.join("bin/run"), b"#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(source.join("bin/run"), std::fs::Permissions::from_mode(0o750)).unwrap();
+            std::os::unix::fs::symlink("a.txt", source.join("link")).unwrap();
+        }
+
+        let output = dir.path().join("out.7z");
+        write_seven_zip(&source, &output, None, None);
+        let contents = read_seven_zip(&output, "");
+        assert_eq!(contents["proj/a.txt"].1, b"hello");
+        assert_eq!(contents["proj/bin/run"].1, b"#!/bin/sh\n");
+        assert!(contents["proj/bin"].0 & FILE_ATTRIBUTE_DIRECTORY != 0);
+        #[cfg(unix)]
+        {
+            assert_eq!(contents["proj/bin/run"].0 >> 16, 0o100750);
+            assert_eq!(contents["proj/link"].0 >> 16 & 0o170000, 0o120000);
+            assert_eq!(contents["proj/link"].1, b"a.txt");
+        }
+    }
+
+    #[test]
+    fn encrypted_archives_need_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("secret");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("note.txt"), b"attack at dawn").unwrap();
+
+        let output = dir.path().join("out.7z");
+        write_seven_zip(&source, &output, Some("hunter2"), None);
+        assert_eq!(read_seven_zip(&output, "hunter2")["secret/note.txt"].1, b"attack at dawn");
+        assert!(SevenZReader::open(&output, Password::from("wrong")).is_err());
+    }
+
+    #[test]
+    fn reproducible_entries_are_dated_the_given_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        let reproducible = Reproducible { modified: zip::DateTime::default(), unix_time: 1_700_000_000 };
+
+        let output = dir.path().join("out.7z");
+        write_seven_zip(&source, &output, None, Some(reproducible));
+        let mut reader = SevenZReader::open(&output, Password::empty()).unwrap();
+        let mut dates = Vec::new();
+        reader
+            .for_each_entries(|entry, _| {
+                dates.push((entry.has_last_modified_date, std::time::SystemTime::from(entry.last_modified_date())));
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(dates.len(), 2);
+        assert!(dates.iter().all(|&date| date == (true, reproducible.system_time())));
+    }
+
+    #[test]
+    fn archives_unpack_into_an_equivalent_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir_all(source.join("bin")).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        std::fs::write(source.join("bin/run"), b"#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(source.join("bin/run"), std::fs::Permissions::from_mode(0o750)).unwrap();
+            std::os::unix::fs::symlink("a.txt", source.join("link")).unwrap();
+        }
+        let archive = dir.path().join("out.7z");
+        write_seven_zip(&source, &archive, None, None);
+
+        let copy = dir.path().join("copy.zip");
+        unpack_to_zip(&archive, None, &copy, &SafetyLimits::default(), |_| {}).unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&copy).unwrap()).unwrap();
+        let mut contents = String::new();
+        zip.by_name("proj/a.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello");
+        assert!(zip.by_name("proj/bin/").unwrap().is_dir());
+        #[cfg(unix)]
+        {
+            assert_eq!(zip.by_name("proj/bin/run").unwrap().unix_mode().unwrap() & 0o7777, 0o750);
+            let mut link = zip.by_name("proj/link").unwrap();
+            assert!(link.is_symlink());
+            contents.clear();
+            link.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, "a.txt");
+        }
+    }
+
+    #[test]
+    fn unpacking_encrypted_archives_needs_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("secret");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("note.txt"), b"attack at dawn").unwrap();
+        let archive = dir.path().join("out.7z");
+        write_seven_zip(&source, &archive, Some("hunter2"), None);
+
+        let copy = dir.path().join("copy.zip");
+        let error = unpack_to_zip(&archive, None, &copy, &SafetyLimits::default(), |_| {}).unwrap_err();
+        assert!(matches!(error, sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::MaybeBadPassword(_)));
+
+        unpack_to_zip(&archive, Some("hunter2"), &copy, &SafetyLimits::default(), |_| {}).unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&copy).unwrap()).unwrap();
+        assert!(zip.by_name("secret/note.txt").is_err());
+        let mut contents = String::new();
+        zip.by_name_decrypt("secret/note.txt", b"hunter2").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "attack at dawn");
+    }
+
+    #[test]
+    fn limits_are_checked_before_unpacking() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), vec![b'a'; 4096]).unwrap();
+        let archive = dir.path().join("out.7z");
+        write_seven_zip(&source, &archive, None, None);
+
+        let copy = dir.path().join("copy.zip");
+        let limits = SafetyLimits { max_total_size: 4095, ..SafetyLimits::default() };
+        let error = unpack_to_zip(&archive, None, &copy, &limits, |_| {}).unwrap_err();
+        assert!(error.to_string().contains("Safety warning"));
+        assert!(!copy.exists());
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Ends the settings block between the extractor and the archive
+pub const SFX_MAGIC: &[u8; 8] = b"SZSFX001";
+/// The extractor program, built next to this one
+const STUB_NAME: &str = "seven_zipper-sfx";
+/// Names another extractor program, e.g. one built for musl
+const STUB_ENV: &str = "SEVEN_ZIPPER_SFX_STUB";
+
+/// Settings stored in a self-extracting archive.
+#[derive(Clone, Default)]
+pub struct SfxOptions {
+    /// Shell command run in the destination folder after extraction
+    pub run_after: String,
+}
+
+impl SfxOptions {
+    fn encode(&self) -> Vec<u8> {
+        let mut text = String::new();
+        if !self.run_after.trim().is_empty() {
+            text.push_str(&format!("run={}\n", self.run_after.trim().replace('\n', " ")));
+        }
+        text.into_bytes()
+    }
+}
+
+/// Finds the extractor program: `SEVEN_ZIPPER_SFX_STUB` if set, otherwise
+/// `seven_zipper-sfx` in this program's folder.
+fn stub_path() -> io::Result<PathBuf> {
+    let stub = match std::env::var_os(STUB_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_exe()?.with_file_name(format!("{}{}", STUB_NAME, std::env::consts::EXE_SUFFIX)),
+    };
+    if !stub.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("extractor {} not found; build it with `cargo build --release --bin {}` or set {}", stub.display(), STUB_NAME, STUB_ENV),
+        ));
+    }
+    Ok(stub)
+}
+
+/// Writes `output`, an executable that extracts `archive`: the
+/// `seven_zipper-sfx` stub followed by the settings and the archive.
+pub fn create_self_extractor(archive: &Path, output: &Path, options: &SfxOptions) -> Result<(), Box<dyn std::error::Error>> {
+    write_self_extractor(&stub_path()?, archive, output, options)
+}
+
+fn write_self_extractor(stub: &Path, archive: &Path, output: &Path, options: &SfxOptions) -> Result<(), Box<dyn std::error::Error>> {
+    // Fail before copying anything if the archive is unreadable
+    ZipArchive::new(File::open(archive)?)?;
+
+    let settings = options.encode();
+
+    let mut writer = io::BufWriter::new(File::create(output)?);
+    io::copy(&mut File::open(stub)?, &mut writer)?;
+    writer.write_all(&settings)?;
+    writer.write_all(&(settings.len() as u32).to_le_bytes())?;
+    writer.write_all(SFX_MAGIC)?;
+    io::copy(&mut File::open(archive)?, &mut writer)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(output, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn archive_and_settings_follow_the_stub() {
+        let dir = tempfile::tempdir().unwrap();
+        let stub = dir.path().join("stub");
+        std::fs::write(&stub, b"#!stub\n").unwrap();
+        let archive = dir.path().join("a.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive).unwrap());
+        writer.start_file("hello.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+
+        let output = dir.path().join("a.run");
+        let options = SfxOptions { run_after: "make install".to_string() };
+        write_self_extractor(&stub, &archive, &output, &options).unwrap();
+
+        let bytes = std::fs::read(&output).unwrap();
+        let settings = b"run=make install\n";
+        let trailer_end = b"#!stub\n".len() + settings.len() + 4 + SFX_MAGIC.len();
+        assert!(bytes.starts_with(b"#!stub\nrun=make install\n"));
+        assert_eq!(&bytes[trailer_end - 12..trailer_end - 8], &(settings.len() as u32).to_le_bytes());
+        assert_eq!(&bytes[trailer_end - 8..trailer_end], SFX_MAGIC);
+
+        let mut extractor = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        assert_eq!(extractor.offset(), trailer_end as u64);
+        let mut contents = String::new();
+        extractor.by_name("hello.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello");
+    }
+}
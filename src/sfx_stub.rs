@@ -0,0 +1,206 @@
+// The extractor that self-extracting archives start with. It only needs the
+// extraction code, so it builds without the GUI, including as a static musl
+// binary:
+//   cargo build --release --no-default-features --target x86_64-unknown-linux-musl --bin seven_zipper-sfx
+
+// The shared modules are only partly used here
+#![allow(dead_code)]
+
+#[path = "extract.rs"]
+mod extract;
+#[path = "integrity.rs"]
+mod integrity;
+#[path = "metadata.rs"]
+mod metadata;
+#[path = "safety.rs"]
+mod safety;
+#[path = "sfx.rs"]
+mod sfx;
+#[path = "sparse.rs"]
+mod sparse;
+#[path = "utils.rs"]
+mod utils;
+
+use extract::extract_entries;
+use metadata::RestorePolicy;
+use sfx::{SfxOptions, SFX_MAGIC};
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+const SFX_TRAILER_SIZE: u64 = 4 + SFX_MAGIC.len() as u64;
+const MAX_SETTINGS: u64 = 64 * 1024;
+
+const SFX_USAGE: &str = "Usage: <this file> [<folder>] [--password <password>] [--no-run]
+  Extracts the archive inside this file into <folder>, or the current folder.
+  --no-run skips the command the archive's author asked to run afterwards.";
+
+/// An archive found appended to an executable.
+struct Payload {
+    options: SfxOptions,
+    archive: ZipArchive<File>,
+}
+
+fn decode_options(text: &str) -> SfxOptions {
+    let mut options = SfxOptions::default();
+    for line in text.lines() {
+        if let Some(command) = line.strip_prefix("run=") {
+            options.run_after = command.to_string();
+        }
+    }
+    options
+}
+
+/// Looks for an archive and settings block after the executable at `path`.
+fn find_payload(path: &Path) -> Option<Payload> {
+    let archive = ZipArchive::new(File::open(path).ok()?).ok()?;
+    let archive_start = archive.offset();
+    if archive_start < SFX_TRAILER_SIZE {
+        return None;
+    }
+    let mut file = File::open(path).ok()?;
+    let mut trailer = [0u8; SFX_TRAILER_SIZE as usize];
+    file.seek(SeekFrom::Start(archive_start - SFX_TRAILER_SIZE)).ok()?;
+    file.read_exact(&mut trailer).ok()?;
+    if &trailer[4..] != SFX_MAGIC {
+        return None;
+    }
+    let settings_len = u32::from_le_bytes(trailer[..4].try_into().ok()?) as u64;
+    if settings_len > MAX_SETTINGS || settings_len > archive_start - SFX_TRAILER_SIZE {
+        return None;
+    }
+    let stub_len = archive_start - SFX_TRAILER_SIZE - settings_len;
+    let mut settings = vec![0u8; settings_len as usize];
+    file.seek(SeekFrom::Start(stub_len)).ok()?;
+    file.read_exact(&mut settings).ok()?;
+    Some(Payload {
+        options: decode_options(&String::from_utf8_lossy(&settings)),
+        archive,
+    })
+}
+
+/// Reads a line from the terminal with echo turned off.
+#[cfg(unix)]
+fn read_password(prompt: &str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let fd = libc::STDIN_FILENO;
+    // SAFETY: termios is plain data, filled in by tcgetattr before use
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: tcgetattr/tcsetattr only read and write the termios passed in
+    let is_terminal = unsafe { libc::tcgetattr(fd, &mut original) } == 0;
+    if is_terminal {
+        let mut silent = original;
+        silent.c_lflag &= !libc::ECHO;
+        // SAFETY: as above
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    }
+    let mut line = String::new();
+    let result = io::stdin().lock().read_line(&mut line);
+    if is_terminal {
+        // SAFETY: as above
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+        eprintln!();
+    }
+    result?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(not(unix))]
+fn read_password(prompt: &str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Extracts the archive appended to this executable and returns the process
+/// exit code.
+fn run(args: &[String]) -> i32 {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("Error locating this program: {}", e);
+            return 1;
+        }
+    };
+    let Some(mut payload) = find_payload(&exe) else {
+        eprintln!("Error: no archive is attached to {}", exe.display());
+        return 1;
+    };
+
+    let mut destination = None;
+    let mut password = None;
+    let mut run_after = true;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--password" | "-p" => match iter.next() {
+                Some(value) => password = Some(value.clone()),
+                None => {
+                    eprintln!("--password needs a value\n\n{}", SFX_USAGE);
+                    return 2;
+                }
+            },
+            "--no-run" => run_after = false,
+            "-h" | "--help" => {
+                println!("{}", SFX_USAGE);
+                return 0;
+            }
+            _ if destination.is_none() => destination = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}", SFX_USAGE);
+                return 2;
+            }
+        }
+    }
+    let destination = destination.unwrap_or_else(|| PathBuf::from("."));
+
+    let encrypted = (0..payload.archive.len())
+        .any(|index| payload.archive.by_index_raw(index).is_ok_and(|entry| entry.encrypted()));
+    if encrypted && password.is_none() {
+        match read_password("Password: ") {
+            Ok(entered) => password = Some(entered),
+            Err(e) => {
+                eprintln!("Error reading the password: {}", e);
+                return 1;
+            }
+        }
+    }
+
+    let names: Vec<String> = payload.archive.file_names().map(str::to_string).collect();
+    if let Err(e) = std::fs::create_dir_all(&destination) {
+        eprintln!("Error creating {}: {}", destination.display(), e);
+        return 1;
+    }
+    let result = extract_entries(&exe, &names, &destination, password.as_deref(), RestorePolicy::default(), false, |_, _, _| {});
+    match result {
+        Ok(count) => println!("Extracted {} entries into {}", count, destination.display()),
+        Err(e) => {
+            eprintln!("Error extracting into {}: {}", destination.display(), e);
+            return 1;
+        }
+    }
+
+    let command = payload.options.run_after;
+    if command.is_empty() || !run_after {
+        return 0;
+    }
+    println!("Running: {}", command);
+    match std::process::Command::new("sh").arg("-c").arg(&command).current_dir(&destination).status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            eprintln!("Error running {}: {}", command, e);
+            1
+        }
+    }
+}
+
+fn main() {
+    // Skipped entries are reported on stderr, keeping stdout for the summary
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(run(&args));
+}
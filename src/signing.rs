@@ -0,0 +1,404 @@
+use crate::models::SignatureStatus;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512, Digest};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::{ZipArchive, ZipWriter};
+
+type Blake2b256 = Blake2b<U32>;
+
+// Minisign algorithm identifiers
+const SIG_ALG: &[u8; 2] = b"Ed";
+const SIG_ALG_PREHASHED: &[u8; 2] = b"ED";
+const KDF_NONE: &[u8; 2] = b"\0\0";
+const CHECKSUM_ALG: &[u8; 2] = b"B2";
+
+const SECRET_KEY_LEN: usize = 158;
+const UNTRUSTED_PREFIX: &str = "untrusted comment: ";
+const TRUSTED_PREFIX: &str = "trusted comment: ";
+
+#[derive(Clone)]
+pub struct PublicKey {
+    pub key_id: [u8; 8],
+    pub verifying_key: VerifyingKey,
+}
+
+pub struct SecretKey {
+    pub key_id: [u8; 8],
+    pub signing_key: SigningKey,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SignatureMode {
+    Embedded,
+    Detached,
+}
+
+struct ParsedSignature {
+    prehashed: bool,
+    key_id: [u8; 8],
+    signature: Signature,
+    trusted_comment: String,
+    global_signature: Signature,
+}
+
+pub fn format_key_id(key_id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*key_id))
+}
+
+pub fn detached_signature_path(archive_path: &Path) -> PathBuf {
+    let mut name = archive_path.as_os_str().to_owned();
+    name.push(".minisig");
+    PathBuf::from(name)
+}
+
+/// Returns the first base64 payload line of a minisign key or signature file.
+fn payload_line(text: &str) -> Option<&str> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(UNTRUSTED_PREFIX))
+}
+
+pub fn parse_public_key(text: &str) -> Result<PublicKey, Box<dyn std::error::Error>> {
+    let line = payload_line(text).ok_or("Public key is empty")?;
+    let bytes = STANDARD.decode(line)?;
+    if bytes.len() != 42 || &bytes[..2] != SIG_ALG {
+        return Err("Not a minisign public key".into());
+    }
+
+    let mut key_id = [0u8; 8];
+    key_id.copy_from_slice(&bytes[2..10]);
+    let verifying_key = VerifyingKey::from_bytes(bytes[10..42].try_into()?)?;
+    Ok(PublicKey { key_id, verifying_key })
+}
+
+pub fn load_secret_key(path: &Path) -> Result<SecretKey, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let line = payload_line(&text).ok_or("Secret key file is empty")?;
+    let bytes = STANDARD.decode(line)?;
+    if bytes.len() != SECRET_KEY_LEN || &bytes[..2] != SIG_ALG || &bytes[4..6] != CHECKSUM_ALG {
+        return Err("Not a minisign secret key".into());
+    }
+    if &bytes[2..4] != KDF_NONE {
+        return Err("Password-protected minisign keys are not supported; create the key with `minisign -G -W`".into());
+    }
+
+    let key_material = &bytes[54..];
+    let (keynum, checksum) = key_material.split_at(72);
+    let mut hasher = Blake2b256::new();
+    hasher.update(SIG_ALG);
+    hasher.update(keynum);
+    if hasher.finalize().as_slice() != checksum {
+        return Err("Secret key checksum mismatch".into());
+    }
+
+    let mut key_id = [0u8; 8];
+    key_id.copy_from_slice(&keynum[..8]);
+    let signing_key = SigningKey::from_bytes(keynum[8..40].try_into()?);
+    Ok(SecretKey { key_id, signing_key })
+}
+
+/// Writes an unencrypted minisign key pair: the secret key to `secret_path`
+/// and the public key next to it with a `.pub` extension.
+pub fn generate_key_pair(secret_path: &Path) -> Result<PublicKey, Box<dyn std::error::Error>> {
+    let signing_key = SigningKey::from_bytes(&rand::random());
+    let key_id: [u8; 8] = rand::random();
+    let verifying_key = signing_key.verifying_key();
+
+    let mut keynum = Vec::with_capacity(72);
+    keynum.extend_from_slice(&key_id);
+    keynum.extend_from_slice(&signing_key.to_keypair_bytes());
+
+    let mut hasher = Blake2b256::new();
+    hasher.update(SIG_ALG);
+    hasher.update(&keynum);
+
+    let mut secret = Vec::with_capacity(SECRET_KEY_LEN);
+    secret.extend_from_slice(SIG_ALG);
+    secret.extend_from_slice(KDF_NONE);
+    secret.extend_from_slice(CHECKSUM_ALG);
+    secret.extend_from_slice(&[0u8; 48]); // kdf salt, opslimit and memlimit are unused
+    secret.extend_from_slice(&keynum);
+    secret.extend_from_slice(&hasher.finalize());
+
+    std::fs::write(
+        secret_path,
+        format!("{}minisign secret key\n{}\n", UNTRUSTED_PREFIX, STANDARD.encode(&secret)),
+    )?;
+    let public_key = PublicKey { key_id, verifying_key };
+    std::fs::write(secret_path.with_extension("pub"), encode_public_key(&public_key))?;
+
+    Ok(public_key)
+}
+
+/// Formats a public key as the contents of a minisign `.pub` file.
+pub fn encode_public_key(key: &PublicKey) -> String {
+    let mut public = Vec::with_capacity(42);
+    public.extend_from_slice(SIG_ALG);
+    public.extend_from_slice(&key.key_id);
+    public.extend_from_slice(key.verifying_key.as_bytes());
+    format!(
+        "{}minisign public key {}\n{}\n",
+        UNTRUSTED_PREFIX,
+        format_key_id(&key.key_id),
+        STANDARD.encode(&public)
+    )
+}
+
+/// File the trusted keys are kept in between runs, under the user's config folder.
+fn trusted_keys_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("seven_zipper").join("trusted_keys.pub"))
+}
+
+/// Reads the saved trusted keys, skipping any that no longer parse.
+pub fn load_trusted_keys() -> Vec<PublicKey> {
+    let Some(text) = trusted_keys_path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(UNTRUSTED_PREFIX))
+        .filter_map(|line| match parse_public_key(line) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                tracing::warn!("Skipping a saved trusted key: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn save_trusted_keys(keys: &[PublicKey]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = trusted_keys_path().ok_or("No config folder: neither XDG_CONFIG_HOME nor HOME is set")?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, keys.iter().map(encode_public_key).collect::<String>())?;
+    Ok(())
+}
+
+/// Builds the signed manifest: one line per entry with a BLAKE2b-256 digest of
+/// the stored (raw) data, the uncompressed size and the entry name prefixed by
+/// its length in bytes, sorted by name. The length keeps names that contain
+/// newlines unambiguous. Raw data is hashed so encrypted archives can be
+/// verified without a password.
+pub fn build_manifest<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut lines = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut file = archive.by_index_raw(i)?;
+        let mut hasher = Blake2b256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        let digest: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        let name = file.name();
+        lines.push((name.to_string(), format!("{} {} {}:{}\n", digest, file.size(), name.len(), name)));
+    }
+    lines.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(lines.into_iter().map(|(_, line)| line).collect::<String>().into_bytes())
+}
+
+fn parse_signature(text: &str) -> Result<ParsedSignature, Box<dyn std::error::Error>> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    lines.next().filter(|line| line.starts_with(UNTRUSTED_PREFIX)).ok_or("Missing untrusted comment")?;
+
+    let sig_bytes = STANDARD.decode(lines.next().ok_or("Missing signature")?)?;
+    if sig_bytes.len() != 74 {
+        return Err("Malformed signature".into());
+    }
+    let prehashed = match &sig_bytes[..2] {
+        alg if alg == SIG_ALG_PREHASHED => true,
+        alg if alg == SIG_ALG => false,
+        _ => return Err("Unsupported signature algorithm".into()),
+    };
+
+    let trusted_comment = lines
+        .next()
+        .and_then(|line| line.strip_prefix(TRUSTED_PREFIX))
+        .ok_or("Missing trusted comment")?
+        .to_string();
+    let global_bytes = STANDARD.decode(lines.next().ok_or("Missing global signature")?)?;
+
+    let mut key_id = [0u8; 8];
+    key_id.copy_from_slice(&sig_bytes[2..10]);
+    Ok(ParsedSignature {
+        prehashed,
+        key_id,
+        signature: Signature::from_slice(&sig_bytes[10..])?,
+        trusted_comment,
+        global_signature: Signature::from_slice(&global_bytes)?,
+    })
+}
+
+/// Produces a minisign signature (prehashed `ED` variant) over `message`.
+pub fn sign_message(key: &SecretKey, message: &[u8], trusted_comment: &str) -> String {
+    let signature = key.signing_key.sign(&Blake2b512::digest(message));
+
+    let mut sig_bytes = Vec::with_capacity(74);
+    sig_bytes.extend_from_slice(SIG_ALG_PREHASHED);
+    sig_bytes.extend_from_slice(&key.key_id);
+    sig_bytes.extend_from_slice(&signature.to_bytes());
+
+    let mut global_message = signature.to_bytes().to_vec();
+    global_message.extend_from_slice(trusted_comment.as_bytes());
+    let global_signature = key.signing_key.sign(&global_message);
+
+    format!(
+        "{}signature from seven_zipper secret key\n{}\n{}{}\n{}\n",
+        UNTRUSTED_PREFIX,
+        STANDARD.encode(&sig_bytes),
+        TRUSTED_PREFIX,
+        trusted_comment,
+        STANDARD.encode(global_signature.to_bytes())
+    )
+}
+
+/// Signs the manifest of a finished archive, either into the archive comment
+/// or into a detached `<archive>.minisig` file.
+pub fn sign_archive(path: &Path, key: &SecretKey, mode: SignatureMode) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let manifest = build_manifest(&mut archive)?;
+    drop(archive);
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let trusted_comment = format!("timestamp:{}\tfile:{}", timestamp, file_name);
+    let signature = sign_message(key, &manifest, &trusted_comment);
+
+    match mode {
+        SignatureMode::Embedded => {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            let mut zip = ZipWriter::new_append(file)?;
+            zip.set_comment(signature);
+            zip.finish()?;
+        }
+        SignatureMode::Detached => {
+            std::fs::write(detached_signature_path(path), signature)?;
+        }
+    }
+    Ok(())
+}
+
+/// True if an archive comment holds an embedded signature.
+pub fn is_signature_comment(comment: &[u8]) -> bool {
+    comment.starts_with(UNTRUSTED_PREFIX.as_bytes())
+}
+
+/// Looks for an embedded or detached signature and checks it against the trusted keys.
+pub fn verify_archive<R: Read + Seek>(
+    path: &Path,
+    archive: &mut ZipArchive<R>,
+    trusted_keys: &[PublicKey],
+) -> SignatureStatus {
+    let comment = String::from_utf8_lossy(archive.comment()).into_owned();
+    let signature_text = if comment.starts_with(UNTRUSTED_PREFIX) {
+        comment
+    } else {
+        match std::fs::read_to_string(detached_signature_path(path)) {
+            Ok(text) => text,
+            Err(_) => return SignatureStatus::Unsigned,
+        }
+    };
+
+    let parsed = match parse_signature(&signature_text) {
+        Ok(parsed) => parsed,
+        Err(e) => return SignatureStatus::Invalid(e.to_string()),
+    };
+    let key_id = format_key_id(&parsed.key_id);
+
+    let Some(key) = trusted_keys.iter().find(|key| key.key_id == parsed.key_id) else {
+        return SignatureStatus::Untrusted(key_id);
+    };
+
+    let manifest = match build_manifest(archive) {
+        Ok(manifest) => manifest,
+        Err(e) => return SignatureStatus::Invalid(e.to_string()),
+    };
+    let signed_ok = if parsed.prehashed {
+        key.verifying_key.verify(&Blake2b512::digest(&manifest), &parsed.signature)
+    } else {
+        key.verifying_key.verify(&manifest, &parsed.signature)
+    };
+    if signed_ok.is_err() {
+        return SignatureStatus::Invalid("Archive contents do not match the signature".to_string());
+    }
+
+    let mut global_message = parsed.signature.to_bytes().to_vec();
+    global_message.extend_from_slice(parsed.trusted_comment.as_bytes());
+    if key.verifying_key.verify(&global_message, &parsed.global_signature).is_err() {
+        return SignatureStatus::Invalid("Trusted comment has been tampered with".to_string());
+    }
+
+    SignatureStatus::Trusted(key_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    fn zip_with(entries: &[&str]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for name in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    fn test_key() -> (SecretKey, PublicKey) {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let key_id = [1, 2, 3, 4, 5, 6, 7, 8];
+        let public = PublicKey { key_id, verifying_key: signing_key.verifying_key() };
+        (SecretKey { key_id, signing_key }, public)
+    }
+
+    #[test]
+    fn manifest_names_with_newlines_are_unambiguous() {
+        let empty: String = Blake2b256::digest(b"").iter().map(|b| format!("{:02x}", b)).collect();
+        let forged = format!("x\n{} 0 y", empty);
+        let one = build_manifest(&mut zip_with(&[&forged])).unwrap();
+        let two = build_manifest(&mut zip_with(&["x", "y"])).unwrap();
+        assert_ne!(one, two);
+    }
+
+    #[test]
+    fn embedded_signature_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("signed.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("a.txt", stored).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+
+        let (secret, public) = test_key();
+        sign_archive(&path, &secret, SignatureMode::Embedded).unwrap();
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert!(matches!(verify_archive(&path, &mut archive, std::slice::from_ref(&public)), SignatureStatus::Trusted(_)));
+        assert!(matches!(verify_archive(&path, &mut archive, &[]), SignatureStatus::Untrusted(_)));
+
+        // Replacing an entry's data invalidates the signature
+        let mut bytes = std::fs::read(&path).unwrap();
+        let pos = bytes.windows(5).position(|window| window == b"hello").unwrap();
+        bytes[pos] = b'j';
+        std::fs::write(&path, bytes).unwrap();
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert!(matches!(verify_archive(&path, &mut archive, &[public]), SignatureStatus::Invalid(_)));
+    }
+
+    #[test]
+    fn public_keys_survive_encoding() {
+        let (_, public) = test_key();
+        let parsed = parse_public_key(&encode_public_key(&public)).unwrap();
+        assert_eq!(parsed.key_id, public.key_id);
+        assert_eq!(parsed.verifying_key, public.verifying_key);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Zero runs of this size, aligned to it, become holes on extraction
+const HOLE_BLOCK: u64 = 4096;
+const ZEROS: [u8; HOLE_BLOCK as usize] = [0; HOLE_BLOCK as usize];
+
+/// True if `file` occupies fewer disk blocks than its length needs.
+#[cfg(unix)]
+pub fn is_sparse(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512 < metadata.len()
+}
+
+#[cfg(not(unix))]
+pub fn is_sparse(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Reads a file, producing the zeros of its holes in memory instead of reading
+/// them from disk. Data and hole regions are found with SEEK_DATA/SEEK_HOLE.
+pub struct SparseReader {
+    file: File,
+    position: u64,
+    len: u64,
+    /// End of the region `position` is in
+    region_end: u64,
+    in_hole: bool,
+    sparse: bool,
+}
+
+impl SparseReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let sparse = is_sparse(&metadata);
+        if sparse {
+            tracing::debug!("{} is sparse; skipping its holes", path.display());
+        }
+        Ok(Self {
+            file,
+            position: 0,
+            len: metadata.len(),
+            region_end: 0,
+            in_hole: false,
+            sparse,
+        })
+    }
+
+    pub fn metadata(&self) -> io::Result<std::fs::Metadata> {
+        self.file.metadata()
+    }
+
+    /// Finds the data or hole region starting at `position`.
+    #[cfg(target_os = "linux")]
+    fn next_region(&mut self) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+        if !self.sparse {
+            self.region_end = self.len;
+            self.in_hole = false;
+            return Ok(());
+        }
+        let fd = self.file.as_raw_fd();
+        let offset = self.position as libc::off_t;
+        // SAFETY: lseek only moves the offset of a descriptor we own
+        let data = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data < 0 {
+            let error = io::Error::last_os_error();
+            return match error.raw_os_error() {
+                // No data after `position`: the rest of the file is a hole
+                Some(libc::ENXIO) => {
+                    self.region_end = self.len;
+                    self.in_hole = true;
+                    Ok(())
+                }
+                // The filesystem cannot report holes; read everything
+                _ => {
+                    self.sparse = false;
+                    self.file.seek(SeekFrom::Start(self.position))?;
+                    self.next_region()
+                }
+            };
+        }
+        if data as u64 > self.position {
+            self.region_end = (data as u64).min(self.len);
+            self.in_hole = true;
+            return Ok(());
+        }
+        // SAFETY: as above
+        let hole = unsafe { libc::lseek(fd, offset, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.region_end = (hole as u64).min(self.len);
+        self.in_hole = false;
+        self.file.seek(SeekFrom::Start(self.position))?;
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn next_region(&mut self) -> io::Result<()> {
+        self.region_end = self.len;
+        self.in_hole = false;
+        Ok(())
+    }
+}
+
+impl Read for SparseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.region_end {
+            if self.position >= self.len {
+                // The file may have grown since it was opened. After a hole
+                // the OS offset lags behind, so move it to where reading is
+                self.file.seek(SeekFrom::Start(self.position))?;
+                let n = self.file.read(buf)?;
+                self.position += n as u64;
+                return Ok(n);
+            }
+            self.next_region()?;
+        }
+        let wanted = buf.len().min((self.region_end - self.position) as usize);
+        let n = if self.in_hole {
+            buf[..wanted].fill(0);
+            wanted
+        } else {
+            self.file.read(&mut buf[..wanted])?
+        };
+        if n == 0 && wanted > 0 {
+            // Truncated while being read
+            self.len = self.position;
+            self.region_end = self.position;
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// Writes a file, seeking over aligned blocks of zeros so that the filesystem
+/// leaves holes there. `finish` must be called to set the final length.
+pub struct SparseWriter {
+    file: File,
+    position: u64,
+    /// Length of the file on disk; less than `position` after a trailing hole
+    written: u64,
+}
+
+impl SparseWriter {
+    pub fn new(file: File) -> Self {
+        Self { file, position: 0, written: 0 }
+    }
+
+    pub fn finish(self) -> io::Result<File> {
+        if self.written < self.position {
+            self.file.set_len(self.position)?;
+        }
+        Ok(self.file)
+    }
+}
+
+impl Write for SparseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Write everything up to the first aligned block of zeros in one go
+        let mut data = 0;
+        while data < buf.len() {
+            let to_boundary = (HOLE_BLOCK - (self.position + data as u64) % HOLE_BLOCK) as usize;
+            let chunk = &buf[data..buf.len().min(data + to_boundary)];
+            if chunk == ZEROS {
+                break;
+            }
+            data += chunk.len();
+        }
+        if data == 0 {
+            self.position += HOLE_BLOCK;
+            return Ok(HOLE_BLOCK as usize);
+        }
+        if self.written != self.position {
+            self.file.seek(SeekFrom::Start(self.position))?;
+        }
+        let n = self.file.write(&buf[..data])?;
+        self.position += n as u64;
+        self.written = self.position;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Builds a file of `len` bytes with `data` written at each offset, and
+    /// returns its expected contents.
+    fn sparse_file(path: &Path, len: u64, data: &[(u64, &[u8])]) -> Vec<u8> {
+        let mut file = File::create(path).unwrap();
+        let mut expected = vec![0u8; len as usize];
+        for &(offset, bytes) in data {
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(bytes).unwrap();
+            expected[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+        }
+        file.set_len(len).unwrap();
+        expected
+    }
+
+    fn assert_round_trip(len: u64, data: &[(u64, &[u8])]) {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let expected = sparse_file(&source, len, data);
+
+        // Small reads cross region boundaries at odd offsets
+        let mut reader = SparseReader::open(&source).unwrap();
+        let mut read = Vec::new();
+        let mut buffer = [0u8; 3000];
+        loop {
+            let n = reader.read(&mut buffer).unwrap();
+            if n == 0 {
+                break;
+            }
+            read.extend_from_slice(&buffer[..n]);
+        }
+        assert_eq!(read.len(), expected.len());
+        assert!(read == expected);
+
+        let copy = dir.path().join("copy");
+        let mut writer = SparseWriter::new(File::create(&copy).unwrap());
+        io::copy(&mut SparseReader::open(&source).unwrap(), &mut writer).unwrap();
+        writer.finish().unwrap();
+        assert!(std::fs::read(&copy).unwrap() == expected);
+        if is_sparse(&std::fs::metadata(&source).unwrap()) {
+            assert!(is_sparse(&std::fs::metadata(&copy).unwrap()));
+        }
+    }
+
+    #[test]
+    fn all_hole_files_round_trip() {
+        assert_round_trip(MIB, &[]);
+    }
+
+    #[test]
+    fn trailing_hole_files_round_trip() {
+        assert_round_trip(2 * MIB, &[(0, b"head"), (MIB / 2, b"middle")]);
+    }
+
+    #[test]
+    fn leading_hole_files_round_trip() {
+        assert_round_trip(MIB + 5, &[(MIB, b"tail!")]);
+    }
+
+    #[test]
+    fn growth_after_a_trailing_hole_is_read_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("growing");
+        sparse_file(&path, MIB, &[(0, b"start")]);
+        let mut reader = SparseReader::open(&path).unwrap();
+        let mut read = Vec::new();
+        let mut buffer = [0u8; 8192];
+        while read.len() < MIB as usize {
+            let n = reader.read(&mut buffer).unwrap();
+            read.extend_from_slice(&buffer[..n]);
+        }
+        File::options().append(true).open(&path).unwrap().write_all(b"more").unwrap();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read.len() as u64, MIB + 4);
+        assert!(read.ends_with(&[0, 0, b'm', b'o', b'r', b'e']));
+    }
+}
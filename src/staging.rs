@@ -0,0 +1,274 @@
+use crate::edits::validate_entry_path;
+use crate::filters::FilterSet;
+use crate::rewrite::{collect_named_entries, LinkPolicy, NewEntry};
+use crate::tree::ArchiveTree;
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A file or folder on disk queued for compression.
+pub struct StagedItem {
+    pub source: PathBuf,
+    /// Path inside the archive, without a trailing '/'
+    pub target: String,
+    pub is_dir: bool,
+    /// Bytes on disk, including everything below a folder
+    pub size: u64,
+}
+
+/// The compression list, laid out as it will appear in the archive. Items can
+/// be renamed and moved into folders that do not exist on disk. Folders are
+/// implied by the items inside them; empty ones are not stored.
+#[derive(Default)]
+pub struct StagingTree {
+    pub items: Vec<StagedItem>,
+    /// Folders created by the user, ending in '/'
+    pub folders: BTreeSet<String>,
+}
+
+/// Total size of the files at and below `path`, not following links.
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .map(|children| children.flatten().map(|child| disk_usage(&child.path())).sum())
+        .unwrap_or(0)
+}
+
+impl StagingTree {
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.folders.clear();
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.items.iter().map(|item| item.size).sum()
+    }
+
+    /// Queues `source` at the archive root, numbering its name if it is taken.
+    pub fn add(&mut self, source: PathBuf) {
+        let file_name = source.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let (stem, extension) = match file_name.rfind('.') {
+            Some(dot) if dot > 0 => file_name.split_at(dot),
+            _ => (file_name.as_str(), ""),
+        };
+        let mut target = file_name.clone();
+        let mut number = 2;
+        while self.exists(&target) {
+            target = format!("{} ({}){}", stem, number, extension);
+            number += 1;
+        }
+
+        let is_dir = std::fs::metadata(&source).is_ok_and(|meta| meta.is_dir());
+        let size = disk_usage(&source);
+        self.items.push(StagedItem { source, target, is_dir, size });
+    }
+
+    /// True if an item or folder occupies `path`, with or without a trailing '/'.
+    fn exists(&self, path: &str) -> bool {
+        let trimmed = path.trim_end_matches('/');
+        let folder = format!("{}/", trimmed);
+        self.items
+            .iter()
+            .any(|item| item.target == trimmed || item.target.starts_with(&folder))
+            || self.folders.iter().any(|existing| existing.starts_with(&folder))
+    }
+
+    /// Rejects paths that would place something inside a staged item.
+    fn check_parents(&self, path: &str) -> Result<(), String> {
+        let trimmed = path.trim_end_matches('/');
+        for (pos, _) in trimmed.match_indices('/') {
+            let parent = &trimmed[..pos];
+            if self.items.iter().any(|item| item.target == parent) {
+                return Err(format!("{} is not a folder", parent));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_folder(&mut self, path: &str) -> Result<(), String> {
+        let folder = format!("{}/", path.trim_matches('/'));
+        validate_entry_path(&folder)?;
+        if self.exists(&folder) {
+            return Err(format!("{} already exists", folder));
+        }
+        self.check_parents(&folder)?;
+        self.folders.insert(folder);
+        Ok(())
+    }
+
+    /// Renames or moves the item or folder (ending in '/') at `from` to `to`.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), String> {
+        if from == to {
+            return Ok(());
+        }
+        validate_entry_path(to)?;
+        if from.ends_with('/') != to.ends_with('/') {
+            return Err("A folder can only be renamed to a folder".to_string());
+        }
+        if from.ends_with('/') && to.starts_with(from) {
+            return Err(format!("{} cannot be moved into itself", from));
+        }
+        if self.exists(to) {
+            return Err(format!("{} already exists", to));
+        }
+        self.check_parents(to)?;
+
+        if from.ends_with('/') {
+            for item in &mut self.items {
+                if let Some(rest) = item.target.strip_prefix(from) {
+                    item.target = format!("{}{}", to, rest);
+                }
+            }
+            self.folders = std::mem::take(&mut self.folders)
+                .into_iter()
+                .map(|folder| match folder.strip_prefix(from) {
+                    Some(rest) => format!("{}{}", to, rest),
+                    None => folder,
+                })
+                .collect();
+        } else {
+            let item = self
+                .items
+                .iter_mut()
+                .find(|item| item.target == from)
+                .ok_or_else(|| format!("{} is not in the list", from))?;
+            item.target = to.to_string();
+        }
+        Ok(())
+    }
+
+    /// Takes the item, or the folder and everything in it, off the list.
+    pub fn remove(&mut self, path: &str) {
+        if path.ends_with('/') {
+            self.items.retain(|item| !item.target.starts_with(path));
+            self.folders.retain(|folder| !folder.starts_with(path));
+        } else {
+            self.items.retain(|item| item.target != path);
+        }
+    }
+
+    /// Every folder, created or implied by an item's path, ending in '/'.
+    pub fn folder_paths(&self) -> Vec<String> {
+        let mut folders = self.folders.clone();
+        for item in &self.items {
+            for (pos, _) in item.target.match_indices('/') {
+                folders.insert(item.target[..=pos].to_string());
+            }
+        }
+        folders.into_iter().collect()
+    }
+
+    /// Items followed by the created folders; a node's `file_index` below
+    /// `items.len()` refers to an item.
+    pub fn tree(&self) -> ArchiveTree {
+        let items = self.items.iter().map(|item| (item.target.as_str(), false, item.size));
+        let folders = self.folders.iter().map(|folder| (folder.as_str(), true, 0));
+        ArchiveTree::from_paths(items.chain(folders))
+    }
+
+    /// Each item's path on disk and its entry name, for `collect_named_entries`.
+    pub fn sources(&self) -> Vec<(PathBuf, String)> {
+        self.items.iter().map(|item| (item.source.clone(), item.target.clone())).collect()
+    }
+
+    /// The entries to compress, with folders walked and `filters` applied.
+    pub fn entries(&self, encrypt: bool, links: LinkPolicy, filters: &FilterSet) -> io::Result<Vec<NewEntry>> {
+        collect_named_entries(&self.sources(), encrypt, links, filters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(target: &str) -> StagedItem {
+        StagedItem { source: PathBuf::from(target), target: target.to_string(), is_dir: false, size: 1 }
+    }
+
+    fn staging(targets: &[&str], folders: &[&str]) -> StagingTree {
+        StagingTree {
+            items: targets.iter().map(|target| item(target)).collect(),
+            folders: folders.iter().map(|folder| folder.to_string()).collect(),
+        }
+    }
+
+    fn targets(tree: &StagingTree) -> Vec<&str> {
+        tree.items.iter().map(|item| item.target.as_str()).collect()
+    }
+
+    #[test]
+    fn taken_names_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("one"), dir.path().join("two"));
+        for folder in [&first, &second] {
+            std::fs::create_dir(folder).unwrap();
+            std::fs::write(folder.join("a.txt"), b"hello").unwrap();
+            std::fs::write(folder.join(".hidden"), b"").unwrap();
+        }
+
+        let mut tree = StagingTree::default();
+        for folder in [&first, &second] {
+            tree.add(folder.join("a.txt"));
+            tree.add(folder.join(".hidden"));
+        }
+        tree.add(first.clone());
+        assert_eq!(targets(&tree), ["a.txt", ".hidden", "a (2).txt", ".hidden (2)", "one"]);
+        assert!(tree.items[4].is_dir);
+        assert_eq!(tree.items[4].size, 5);
+        assert_eq!(tree.total_size(), 15);
+    }
+
+    #[test]
+    fn folders_exist_through_their_contents() {
+        let tree = staging(&["docs/a.txt"], &["empty/"]);
+        assert!(tree.exists("docs/a.txt"));
+        assert!(tree.exists("docs"));
+        assert!(tree.exists("docs/"));
+        assert!(tree.exists("empty"));
+        assert!(!tree.exists("doc"));
+        assert!(!tree.exists("docs/a"));
+    }
+
+    #[test]
+    fn items_are_renamed_and_moved() {
+        let mut tree = staging(&["a.txt", "b.txt"], &["docs/"]);
+        tree.rename("a.txt", "docs/c.txt").unwrap();
+        assert_eq!(targets(&tree), ["docs/c.txt", "b.txt"]);
+
+        assert_eq!(tree.rename("b.txt", "docs/c.txt").unwrap_err(), "docs/c.txt already exists");
+        assert_eq!(tree.rename("b.txt", "docs").unwrap_err(), "docs already exists");
+        assert_eq!(tree.rename("b.txt", "docs/c.txt/b.txt").unwrap_err(), "docs/c.txt is not a folder");
+        assert_eq!(tree.rename("x.txt", "y.txt").unwrap_err(), "x.txt is not in the list");
+        assert_eq!(targets(&tree), ["docs/c.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn folders_move_with_their_contents() {
+        let mut tree = staging(&["docs/a.txt", "docs/sub/b.txt", "c.txt"], &["docs/empty/"]);
+        tree.rename("docs/", "notes/docs/").unwrap();
+        assert_eq!(targets(&tree), ["notes/docs/a.txt", "notes/docs/sub/b.txt", "c.txt"]);
+        assert_eq!(tree.folders.iter().collect::<Vec<_>>(), ["notes/docs/empty/"]);
+
+        assert_eq!(
+            tree.rename("notes/", "notes/docs/notes/").unwrap_err(),
+            "notes/ cannot be moved into itself"
+        );
+    }
+
+    #[test]
+    fn folders_and_files_cannot_swap_kinds() {
+        let mut tree = staging(&["docs/a.txt", "c.txt"], &[]);
+        assert_eq!(tree.rename("docs/", "notes").unwrap_err(), "A folder can only be renamed to a folder");
+        assert_eq!(tree.rename("c.txt", "d/").unwrap_err(), "A folder can only be renamed to a folder");
+        assert_eq!(targets(&tree), ["docs/a.txt", "c.txt"]);
+    }
+}
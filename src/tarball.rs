@@ -0,0 +1,522 @@
+use crate::app::CompressionStats;
+use crate::metadata::{read_xattrs, Reproducible};
+use crate::rewrite::NewEntry;
+use crate::sparse::SparseReader;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tar::{Builder, EntryType, Header, HeaderMode};
+
+/// PAX keyword prefix for extended attributes, as written by GNU tar and star
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+/// Tar stores names in 100 bytes; PAX header names only need to be readable
+const MAX_PAX_HEADER_NAME: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TarCompression {
+    None,
+    Gzip,
+}
+
+/// The tar flavour `path` asks for by its extension: .tar, .tar.gz or .tgz.
+pub fn tar_format(path: &Path) -> Option<TarCompression> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".tar") {
+        Some(TarCompression::None)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(TarCompression::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Appends one PAX record: "<length> <keyword>=<value>\n", where the length
+/// counts the whole record including its own digits.
+fn push_pax_record(records: &mut Vec<u8>, keyword: &str, value: &[u8]) {
+    let body = keyword.len() + value.len() + 3; // space, '=' and newline
+    let mut length = body + 1;
+    while length != body + length.to_string().len() {
+        length = body + length.to_string().len();
+    }
+    records.extend_from_slice(format!("{} {}=", length, keyword).as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// User and group names by id, looked up once per archive.
+#[derive(Default)]
+struct OwnerNames {
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl OwnerNames {
+    /// Adds `uname` and `gname` records for the owner of `metadata`. The
+    /// numeric uid and gid are in the entry header itself.
+    #[cfg(unix)]
+    fn push_records(&mut self, metadata: &Metadata, records: &mut Vec<u8>) {
+        use crate::metadata::{group_name, user_name};
+        use std::os::unix::fs::MetadataExt;
+        if let Some(name) = self.users.entry(metadata.uid()).or_insert_with(|| user_name(metadata.uid())) {
+            push_pax_record(records, "uname", name.as_bytes());
+        }
+        if let Some(name) = self.groups.entry(metadata.gid()).or_insert_with(|| group_name(metadata.gid())) {
+            push_pax_record(records, "gname", name.as_bytes());
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn push_records(&mut self, _metadata: &Metadata, _records: &mut Vec<u8>) {}
+}
+
+/// PAX records carrying the owner names and the extended attributes of
+/// `entry`, including POSIX ACLs (`system.posix_acl_*`) and SELinux labels
+/// (`security.selinux`).
+fn pax_records(entry: &NewEntry, owners: &mut OwnerNames) -> Vec<u8> {
+    let mut records = Vec::new();
+    if let Ok(metadata) = entry.metadata() {
+        owners.push_records(&metadata, &mut records);
+    }
+    let path = &entry.source;
+    match read_xattrs(path) {
+        Ok(xattrs) => push_xattr_records(&mut records, &xattrs),
+        Err(e) => tracing::warn!("Cannot read extended attributes of {}: {}", path.display(), e),
+    }
+    records
+}
+
+/// Appends a `SCHILY.xattr.<name>` record for each extended attribute.
+fn push_xattr_records(records: &mut Vec<u8>, xattrs: &[(Vec<u8>, Vec<u8>)]) {
+    for (name, value) in xattrs {
+        let keyword = format!("{}{}", PAX_XATTR_PREFIX, String::from_utf8_lossy(name));
+        push_pax_record(records, &keyword, value);
+    }
+}
+
+/// Writes a PAX extended header, which applies to the entry that follows it.
+fn append_pax_header<W: Write>(builder: &mut Builder<W>, name: &str, records: &[u8]) -> io::Result<()> {
+    let mut pax_name = format!("PaxHeaders/{}", name.trim_end_matches('/'));
+    while pax_name.len() > MAX_PAX_HEADER_NAME {
+        pax_name.remove(11);
+    }
+    let mut header = Header::new_ustar();
+    header.set_path(&pax_name)?;
+    header.set_entry_type(EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(records.len() as u64);
+    header.set_cksum();
+    builder.append(&header, records)
+}
+
+/// Writes `entries`, as listed by `collect_new_entries`, into a tar archive at
+/// `output_path`, gzipped if asked. Entries keep their permissions, uid, gid
+/// and times; owner names and extended attributes go in PAX headers. Files
+/// with several hard links are stored once and linked to from the other names;
+/// sparse files are stored without their holes. With `reproducible` set,
+/// entries are written in name order without owner information, all dated
+/// its time, and files are stored whole.
+pub fn compress_to_tar(
+    mut entries: Vec<NewEntry>,
+    output_path: PathBuf,
+    compression: TarCompression,
+    progress_tx: Sender<(f32, CompressionStats)>,
+    stats: Arc<Mutex<CompressionStats>>,
+    reproducible: Option<Reproducible>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if reproducible.is_some() {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    let file = BufWriter::new(File::create(&output_path)?);
+    match compression {
+        TarCompression::None => {
+            let mut builder = Builder::new(file);
+            append_entries(&mut builder, &entries, &progress_tx, &stats, reproducible)?;
+            builder.into_inner()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        TarCompression::Gzip => {
+            let mut builder = Builder::new(GzEncoder::new(file, Compression::new(5)));
+            append_entries(&mut builder, &entries, &progress_tx, &stats, reproducible)?;
+            builder.into_inner()?.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+    }
+
+    let compressed_size = std::fs::metadata(&output_path)?.len();
+    if let Ok(mut stats) = stats.lock() {
+        stats.compressed_size = compressed_size;
+        progress_tx.send((1.0, stats.clone()))?;
+    }
+    Ok(())
+}
+
+/// Device and inode of a file with more than one hard link.
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Appends `entry` with a deterministic header dated `mtime`, which the tar
+/// crate has no option for. Files are stored whole, since whether they have
+/// holes depends on how they were copied.
+fn append_dated<W: Write>(builder: &mut Builder<W>, entry: &NewEntry, mtime: u64) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(&entry.metadata()?, HeaderMode::Deterministic);
+    header.set_mtime(mtime);
+    match &entry.link_target {
+        Some(target) => builder.append_link(&mut header, &entry.name, target),
+        None if entry.is_file() => builder.append_data(&mut header, &entry.name, SparseReader::open(&entry.source)?),
+        None => builder.append_data(&mut header, &entry.name, io::empty()),
+    }
+}
+
+fn append_entries<W: Write>(
+    builder: &mut Builder<W>,
+    entries: &[NewEntry],
+    progress_tx: &Sender<(f32, CompressionStats)>,
+    stats: &Mutex<CompressionStats>,
+    reproducible: Option<Reproducible>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mode = if reproducible.is_some() { HeaderMode::Deterministic } else { HeaderMode::Complete };
+    builder.mode(mode);
+    // Holes are listed in GNU sparse headers ('S' entries) instead of stored
+    builder.sparse(true);
+    let total_size: u64 = entries
+        .iter()
+        .filter(|entry| entry.is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .sum();
+
+    let mut owners = OwnerNames::default();
+    // Name of the first entry seen for each file with several hard links
+    let mut hard_links: HashMap<(u64, u64), &str> = HashMap::new();
+    let mut processed = 0u64;
+    for (index, entry) in entries.iter().enumerate() {
+        // Links stored as links are never opened; followed ones were resolved
+        // while collecting
+        builder.follow_symlinks(entry.link_target.is_none());
+
+        if reproducible.is_none() {
+            let records = pax_records(entry, &mut owners);
+            if !records.is_empty() {
+                append_pax_header(builder, &entry.name, &records)?;
+            }
+        }
+        let id = entry.is_file().then(|| entry.metadata().ok()).flatten().as_ref().and_then(file_id);
+        let first_name = id.and_then(|id| match hard_links.entry(id) {
+            Entry::Occupied(first) => Some(*first.get()),
+            Entry::Vacant(slot) => {
+                slot.insert(&entry.name);
+                None
+            }
+        });
+        match first_name {
+            Some(first_name) => {
+                // The data is already in the archive; a link entry points at it
+                let mut header = Header::new_gnu();
+                header.set_metadata_in_mode(&entry.metadata()?, mode);
+                header.set_entry_type(EntryType::Link);
+                header.set_size(0);
+                if let Some(reproducible) = reproducible {
+                    header.set_mtime(reproducible.unix_time);
+                }
+                builder.append_link(&mut header, &entry.name, first_name)?;
+            }
+            None => match reproducible {
+                Some(reproducible) => append_dated(builder, entry, reproducible.unix_time)?,
+                None => builder.append_path_with_name(&entry.source, &entry.name)?,
+            },
+        }
+
+        if entry.is_file() && first_name.is_none() {
+            processed += entry.metadata().map_or(0, |meta| meta.len());
+        }
+        if let Ok(mut stats) = stats.lock() {
+            let progress = if total_size > 0 { processed as f32 / total_size as f32 } else { 1.0 };
+            stats.files_processed = index + 1;
+            if progress > 0.0 {
+                stats.estimated_time = Duration::from_secs_f32(stats.start_time.elapsed().as_secs_f32() / progress);
+            }
+            progress_tx.send((progress, stats.clone()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterSet;
+    use crate::rewrite::{collect_new_entries, LinkPolicy};
+    use std::io::Read;
+    use std::sync::mpsc::channel;
+    use std::time::{Instant, SystemTime};
+
+    pub fn write_tar(source: &Path, output: &Path, reproducible: Option<Reproducible>) {
+        let entries = collect_new_entries(&[source.to_path_buf()], "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+        let stats = CompressionStats {
+            original_size: 0,
+            compressed_size: 0,
+            start_time: Instant::now(),
+            estimated_time: Duration::from_secs(0),
+            output_path: output.to_path_buf(),
+            files_processed: 0,
+            total_files: entries.len(),
+        };
+        let (progress_tx, _progress_rx) = channel();
+        let compression = tar_format(output).unwrap();
+        compress_to_tar(entries, output.to_path_buf(), compression, progress_tx, Arc::new(Mutex::new(stats)), reproducible).unwrap();
+    }
+
+    #[test]
+    fn pax_record_lengths_count_themselves() {
+        for value_len in [0, 1, 80, 89, 90, 95, 990, 995] {
+            let mut records = Vec::new();
+            push_pax_record(&mut records, "SCHILY.xattr.user.x", &vec![b'v'; value_len]);
+            let length: usize = std::str::from_utf8(&records[..records.iter().position(|&b| b == b' ').unwrap()])
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(length, records.len());
+        }
+    }
+
+    #[test]
+    fn recognises_tar_names() {
+        assert_eq!(tar_format(Path::new("a/b.TAR")), Some(TarCompression::None));
+        assert_eq!(tar_format(Path::new("b.tar.gz")), Some(TarCompression::Gzip));
+        assert_eq!(tar_format(Path::new("b.tgz")), Some(TarCompression::Gzip));
+        assert_eq!(tar_format(Path::new("b.gz")), None);
+        assert_eq!(tar_format(Path::new("b.zip")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_keep_their_owner() {
+        use crate::metadata::{group_name, user_name};
+        use std::os::unix::fs::MetadataExt;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        let owner = std::fs::metadata(source.join("a.txt")).unwrap();
+
+        let output = dir.path().join("out.tar");
+        write_tar(&source, &output, None);
+        let mut archive = tar::Archive::new(File::open(&output).unwrap());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.path().unwrap() != Path::new("proj/a.txt") {
+                continue;
+            }
+            let header = entry.header();
+            assert_eq!(header.uid().unwrap(), owner.uid() as u64);
+            assert_eq!(header.gid().unwrap(), owner.gid() as u64);
+            assert_eq!(header.mtime().unwrap(), owner.mtime() as u64);
+            assert_eq!(header.mode().unwrap() & 0o7777, owner.mode() & 0o7777);
+
+            let mut names = HashMap::new();
+            for extension in entry.pax_extensions().unwrap().into_iter().flatten() {
+                let extension = extension.unwrap();
+                names.insert(extension.key().unwrap().to_string(), extension.value().unwrap().to_string());
+            }
+            assert_eq!(names.get("uname").cloned(), user_name(owner.uid()));
+            assert_eq!(names.get("gname").cloned(), group_name(owner.gid()));
+            return;
+        }
+        panic!("proj/a.txt is missing");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"shared contents").unwrap();
+        std::fs::hard_link(source.join("a.txt"), source.join("b.txt")).unwrap();
+
+        let output = dir.path().join("out.tar");
+        write_tar(&source, &output, None);
+        let mut archive = tar::Archive::new(File::open(&output).unwrap());
+        let mut files = Vec::new();
+        let mut links = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            match entry.header().entry_type() {
+                EntryType::Regular => files.push(name),
+                EntryType::Link => links.push((name, entry.link_name().unwrap().unwrap().to_string_lossy().into_owned())),
+                _ => {}
+            }
+        }
+        assert_eq!(files.len(), 1);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].1, files[0]);
+
+        let extracted = dir.path().join("extracted");
+        tar::Archive::new(File::open(&output).unwrap()).unpack(&extracted).unwrap();
+        assert_eq!(std::fs::read(extracted.join(&links[0].0)).unwrap(), b"shared contents");
+    }
+
+    #[test]
+    fn reproducible_entries_are_dated_the_given_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir_all(source.join("bin")).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", source.join("link")).unwrap();
+        let reproducible = Reproducible { modified: zip::DateTime::default(), unix_time: 1_700_000_000 };
+
+        let first = dir.path().join("first.tar");
+        write_tar(&source, &first, Some(reproducible));
+        File::options()
+            .write(true)
+            .open(source.join("a.txt"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+        let second = dir.path().join("second.tar");
+        write_tar(&source, &second, Some(reproducible));
+        assert_eq!(std::fs::read(&first).unwrap(), std::fs::read(&second).unwrap());
+
+        let mut archive = tar::Archive::new(File::open(&first).unwrap());
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            assert_eq!(entry.header().mtime().unwrap(), 1_700_000_000);
+            assert_eq!(entry.header().uid().unwrap(), 0);
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            if name == "proj/a.txt" {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                assert_eq!(data, b"hello");
+            }
+            names.push(name);
+        }
+        assert!(names.contains(&"proj/a.txt".to_string()));
+        assert!(names.is_sorted());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "needs a temp filesystem with sparse files; run with --ignored"]
+    fn sparse_files_keep_their_holes() {
+        use crate::sparse::is_sparse;
+        use std::io::{Seek, SeekFrom};
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        let mut file = File::create(source.join("disk.img")).unwrap();
+        file.seek(SeekFrom::Start(4 * 1024 * 1024)).unwrap();
+        file.write_all(b"data").unwrap();
+        file.set_len(8 * 1024 * 1024).unwrap();
+        drop(file);
+        assert!(is_sparse(&std::fs::metadata(source.join("disk.img")).unwrap()), "the temp filesystem made no holes");
+
+        let output = dir.path().join("out.tar");
+        write_tar(&source, &output, None);
+        assert!(std::fs::metadata(&output).unwrap().len() < 64 * 1024);
+        let mut archive = tar::Archive::new(File::open(&output).unwrap());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.path().unwrap() == Path::new("proj/disk.img") {
+                assert_eq!(entry.header().entry_type(), EntryType::GNUSparse);
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                assert_eq!(data.len(), 8 * 1024 * 1024);
+                assert_eq!(&data[4 * 1024 * 1024..4 * 1024 * 1024 + 4], b"data");
+                assert!(data.iter().filter(|&&b| b != 0).count() == 4);
+                return;
+            }
+        }
+        panic!("proj/disk.img is missing");
+    }
+
+    #[test]
+    fn xattrs_are_read_back_from_pax_headers() {
+        let xattrs = [
+            (b"user.color".to_vec(), b"blue".to_vec()),
+            (b"system.posix_acl_access".to_vec(), vec![2, 0, 0, 0, 1, 0, 6, 0, 0xff, 0xff, 0xff, 0xff]),
+        ];
+        let mut records = Vec::new();
+        push_xattr_records(&mut records, &xattrs);
+        let mut builder = Builder::new(Vec::new());
+        append_pax_header(&mut builder, "proj/a.txt", &records).unwrap();
+        let mut header = Header::new_gnu();
+        header.set_path("proj/a.txt").unwrap();
+        header.set_size(0);
+        header.set_cksum();
+        builder.append(&header, io::empty()).unwrap();
+
+        let data = builder.into_inner().unwrap();
+        let mut archive = tar::Archive::new(data.as_slice());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap(), Path::new("proj/a.txt"));
+        let found: Vec<(String, Vec<u8>)> = entry
+            .pax_extensions()
+            .unwrap()
+            .unwrap()
+            .map(|extension| {
+                let extension = extension.unwrap();
+                (extension.key().unwrap().to_string(), extension.value_bytes().to_vec())
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("SCHILY.xattr.user.color".to_string(), b"blue".to_vec()),
+                ("SCHILY.xattr.system.posix_acl_access".to_string(), xattrs[1].1.clone()),
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "needs user xattrs on the temp filesystem; run with --ignored"]
+    fn extended_attributes_go_in_pax_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("proj");
+        std::fs::create_dir(&source).unwrap();
+        let file = source.join("a.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        let path = std::ffi::CString::new(file.to_str().unwrap()).unwrap();
+        // SAFETY: both strings are NUL-terminated and the value is 4 bytes long
+        let set = unsafe { libc::setxattr(path.as_ptr(), c"user.color".as_ptr(), b"blue".as_ptr().cast(), 4, 0) };
+        assert_eq!(set, 0, "cannot set user xattrs: {}", io::Error::last_os_error());
+
+        let output = dir.path().join("out.tar.gz");
+        write_tar(&source, &output, None);
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(File::open(&output).unwrap()));
+        let mut found = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            if let Some(extensions) = entry.pax_extensions().unwrap() {
+                for extension in extensions {
+                    let extension = extension.unwrap();
+                    if !extension.key().unwrap().starts_with(PAX_XATTR_PREFIX) {
+                        continue;
+                    }
+                    found.push((name.clone(), extension.key().unwrap().to_string(), extension.value_bytes().to_vec()));
+                }
+            }
+        }
+        assert_eq!(found, [("proj/a.txt".to_string(), "SCHILY.xattr.user.color".to_string(), b"blue".to_vec())]);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::models::ArchiveFile;
+use std::collections::{HashMap, HashSet};
+
+pub const ROOT: usize = 0;
+
+/// Splits a path without trailing '/' into its parent directory (with
+/// trailing '/', empty at the root) and its last component.
+fn split_parent(trimmed: &str) -> (&str, &str) {
+    match trimmed.rfind('/') {
+        Some(pos) => (&trimmed[..=pos], &trimmed[pos + 1..]),
+        None => ("", trimmed),
+    }
+}
+
+pub struct TreeNode {
+    pub name: String,
+    /// Full entry path; directories carry a trailing '/'
+    pub path: String,
+    pub is_directory: bool,
+    /// Index into the archive's file list, `None` for implicit directories
+    pub file_index: Option<usize>,
+    /// File size, or the aggregated size of everything below a directory
+    pub size: u64,
+    pub file_count: usize,
+    pub parent: usize,
+    pub children: Vec<usize>,
+}
+
+/// Folder hierarchy built from flat entry paths. Nodes live in an arena and
+/// refer to each other by index; node 0 is the archive root.
+pub struct ArchiveTree {
+    pub nodes: Vec<TreeNode>,
+}
+
+impl ArchiveTree {
+    pub fn build(files: &[ArchiveFile]) -> Self {
+        Self::from_paths(files.iter().map(|file| (file.name.as_str(), file.is_directory, file.size)))
+    }
+
+    /// Builds the tree from (path, is directory, size) triples. A node's
+    /// `file_index` is the position of its triple.
+    pub fn from_paths<'a>(paths: impl Iterator<Item = (&'a str, bool, u64)>) -> Self {
+        let mut tree = ArchiveTree {
+            nodes: vec![TreeNode {
+                name: String::new(),
+                path: String::new(),
+                is_directory: true,
+                file_index: None,
+                size: 0,
+                file_count: 0,
+                parent: ROOT,
+                children: Vec::new(),
+            }],
+        };
+        let mut directories: HashMap<String, usize> = HashMap::new();
+
+        for (index, (path, is_directory, size)) in paths.enumerate() {
+            let trimmed = path.trim_end_matches('/');
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let (parent_path, name) = split_parent(trimmed);
+            let parent = tree.directory(&mut directories, parent_path);
+
+            if is_directory {
+                let node = tree.directory(&mut directories, &format!("{}/", trimmed));
+                tree.nodes[node].file_index = Some(index);
+            } else {
+                let node = tree.push(parent, name, path, false);
+                tree.nodes[node].file_index = Some(index);
+                tree.nodes[node].size = size;
+                tree.nodes[node].file_count = 1;
+            }
+        }
+
+        // Children are always created after their parents, so a reverse pass
+        // sees every subtree before the directory that contains it
+        for node in (1..tree.nodes.len()).rev() {
+            let (size, count, parent) = (tree.nodes[node].size, tree.nodes[node].file_count, tree.nodes[node].parent);
+            tree.nodes[parent].size += size;
+            tree.nodes[parent].file_count += count;
+        }
+
+        for node in 0..tree.nodes.len() {
+            let mut children = std::mem::take(&mut tree.nodes[node].children);
+            children.sort_by(|&a, &b| {
+                let (a, b) = (&tree.nodes[a], &tree.nodes[b]);
+                b.is_directory.cmp(&a.is_directory).then_with(|| a.name.cmp(&b.name))
+            });
+            tree.nodes[node].children = children;
+        }
+
+        tree
+    }
+
+    fn push(&mut self, parent: usize, name: &str, path: &str, is_directory: bool) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(TreeNode {
+            name: name.to_string(),
+            path: path.to_string(),
+            is_directory,
+            file_index: None,
+            size: 0,
+            file_count: 0,
+            parent,
+            children: Vec::new(),
+        });
+        self.nodes[parent].children.push(node);
+        node
+    }
+
+    /// Returns the node for directory `path` (with trailing '/'), creating it
+    /// and any missing ancestors as implicit directories.
+    fn directory(&mut self, directories: &mut HashMap<String, usize>, path: &str) -> usize {
+        // Walk up to the nearest existing directory, then create the rest on
+        // the way down. Entry paths can nest tens of thousands of levels deep,
+        // too many to recurse through
+        let mut missing = Vec::new();
+        let mut current = path;
+        let mut node = loop {
+            if current.is_empty() {
+                break ROOT;
+            }
+            if let Some(&node) = directories.get(current) {
+                break node;
+            }
+            let (parent_path, name) = split_parent(current.trim_end_matches('/'));
+            missing.push((current, name));
+            current = parent_path;
+        };
+
+        for (path, name) in missing.into_iter().rev() {
+            node = self.push(node, name, path, true);
+            directories.insert(path.to_string(), node);
+        }
+        node
+    }
+
+    /// Flattens the tree into display rows of (node, depth), descending only
+    /// into expanded directories.
+    pub fn visible_rows(&self, expanded: &HashSet<usize>) -> Vec<(usize, usize)> {
+        let mut rows = Vec::new();
+        let mut stack: Vec<(usize, usize)> = self.nodes[ROOT].children.iter().rev().map(|&child| (child, 0)).collect();
+        while let Some((node, depth)) = stack.pop() {
+            rows.push((node, depth));
+            if self.nodes[node].is_directory && expanded.contains(&node) {
+                stack.extend(self.nodes[node].children.iter().rev().map(|&child| (child, depth + 1)));
+            }
+        }
+        rows
+    }
+
+    pub fn directories(&self) -> impl Iterator<Item = usize> + '_ {
+        (1..self.nodes.len()).filter(|&node| self.nodes[node].is_directory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_of(paths: &[(&str, bool, u64)]) -> ArchiveTree {
+        ArchiveTree::from_paths(paths.iter().copied())
+    }
+
+    fn node<'a>(tree: &'a ArchiveTree, path: &str) -> &'a TreeNode {
+        tree.nodes.iter().find(|node| node.path == path).unwrap()
+    }
+
+    #[test]
+    fn missing_folders_are_created_implicitly() {
+        let tree = tree_of(&[("a/b/c.txt", false, 1), ("a/", true, 0)]);
+        assert_eq!(tree.nodes.len(), 4);
+
+        let a = node(&tree, "a/");
+        assert!(a.is_directory);
+        assert_eq!(a.file_index, Some(1));
+        let b = node(&tree, "a/b/");
+        assert!(b.is_directory);
+        assert_eq!((b.name.as_str(), b.file_index), ("b", None));
+        assert_eq!(tree.nodes[b.parent].path, "a/");
+        assert_eq!(node(&tree, "a/b/c.txt").file_index, Some(0));
+    }
+
+    #[test]
+    fn folders_add_up_everything_below_them() {
+        let tree = tree_of(&[("a/b/c.txt", false, 10), ("a/d.txt", false, 5), ("e.txt", false, 1), ("a/b/", true, 0)]);
+        assert_eq!((tree.nodes[ROOT].size, tree.nodes[ROOT].file_count), (16, 3));
+        assert_eq!((node(&tree, "a/").size, node(&tree, "a/").file_count), (15, 2));
+        assert_eq!((node(&tree, "a/b/").size, node(&tree, "a/b/").file_count), (10, 1));
+    }
+
+    #[test]
+    fn folders_are_listed_before_files() {
+        let tree = tree_of(&[("b.txt", false, 1), ("z/x", false, 1), ("a.txt", false, 1), ("c/", true, 0)]);
+        let names: Vec<&str> = tree.nodes[ROOT].children.iter().map(|&child| tree.nodes[child].name.as_str()).collect();
+        assert_eq!(names, ["c", "z", "a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn deeply_nested_paths_are_built() {
+        let depth = 4096;
+        let path = format!("{}f", "a/".repeat(depth));
+        let tree = tree_of(&[(path.as_str(), false, 7)]);
+        assert_eq!(tree.nodes.len(), depth + 2);
+
+        let mut levels = 0;
+        let mut current = tree.nodes.len() - 1;
+        while current != ROOT {
+            assert_eq!(tree.nodes[current].size, 7);
+            current = tree.nodes[current].parent;
+            levels += 1;
+        }
+        assert_eq!(levels, depth + 1);
+    }
+
+    #[test]
+    fn only_expanded_folders_show_their_children() {
+        let tree = tree_of(&[("a/b.txt", false, 1), ("c.txt", false, 1)]);
+        let a = tree.nodes.iter().position(|node| node.path == "a/").unwrap();
+        assert_eq!(tree.visible_rows(&HashSet::new()).len(), 2);
+        let rows = tree.visible_rows(&HashSet::from([a]));
+        let paths: Vec<(&str, usize)> = rows.iter().map(|&(node, depth)| (tree.nodes[node].path.as_str(), depth)).collect();
+        assert_eq!(paths, [("a/", 0), ("a/b.txt", 1), ("c.txt", 0)]);
+    }
+}
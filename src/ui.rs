@@ -1,28 +1,18 @@
+use crate::models::SignatureStatus;
 use crate::utils::get_formatted_size;
+use egui::text::{LayoutJob, TextFormat};
 use egui::{Color32, Frame, Label, RichText, Sense, Window};
+use std::collections::HashSet;
 use std::time::Duration;
 use log::info;
-use crate::app::ArchiveManager;
-
-pub fn draw_file_list(ui: &mut egui::Ui, files: &[std::path::PathBuf], files_to_remove: &mut Vec<usize>) {
-    ui.horizontal(|ui| {
-        if !files.is_empty() {
-            if ui.button("Clear All").clicked() {
-                // Add all indices to files_to_remove
-                files_to_remove.extend(0..files.len());
-            }
-        }
-    });
-
-    for (idx, path) in files.iter().enumerate() {
-        ui.horizontal(|ui| {
-            if ui.button("❌").clicked() {
-                files_to_remove.push(idx);
-            }
-            ui.label(path.file_name().unwrap_or_default().to_string_lossy());
-        });
-    }
-}
+use crate::app::{ArchiveManager, EditKind, EntryEdit, UpdateRequest};
+use crate::edits::EntryChange;
+use crate::preview::PREVIEW_CHUNK;
+use crate::reader::{ReaderMode, THUMBNAIL_SIZE};
+use crate::listing::{ContentsIndex, ContentsView, EntryFilter, FilterMode, SortColumn};
+use crate::tree::ROOT;
+use crate::update::{ChangeDetection, UpdateMode};
+use egui_extras::{Column, TableBuilder};
 
 
 pub fn format_duration(duration: Duration) -> String {
@@ -58,6 +48,16 @@ pub fn draw_unified_drop_zone(ui: &mut egui::Ui) -> egui::Response {
         .response
 }
 
+pub fn draw_signature_badge(ui: &mut egui::Ui, status: &SignatureStatus) {
+    let (text, color, hover) = match status {
+        SignatureStatus::Unsigned => return,
+        SignatureStatus::Trusted(key_id) => ("✔ Trusted", Color32::GREEN, format!("Signed by trusted key {}", key_id)),
+        SignatureStatus::Untrusted(key_id) => ("⚠ Untrusted", Color32::YELLOW, format!("Signed by unknown key {}", key_id)),
+        SignatureStatus::Invalid(reason) => ("✖ Invalid signature", Color32::RED, reason.clone()),
+    };
+    ui.label(RichText::new(text).color(color).strong()).on_hover_text(hover);
+}
+
 pub fn draw_action_dialog(
     ctx: &egui::Context,
     show: &mut bool,
@@ -90,6 +90,428 @@ pub fn draw_action_dialog(
         });
 }
 
+/// Lays out `prefix` + `text`, highlighting the parts of `text` the filter matched.
+fn highlighted_text(ui: &egui::Ui, prefix: &str, text: &str, filter: Option<&EntryFilter>, color: Color32) -> LayoutJob {
+    let font_id = egui::TextStyle::Body.resolve(ui.style());
+    let plain = TextFormat::simple(font_id.clone(), color);
+    let highlight = TextFormat {
+        background: ui.visuals().selection.bg_fill,
+        ..TextFormat::simple(font_id, ui.visuals().selection.stroke.color)
+    };
+
+    let mut job = LayoutJob::default();
+    job.append(prefix, 0.0, plain.clone());
+    let mut last = 0;
+    for range in filter.map(|filter| filter.highlight_ranges(text)).unwrap_or_default() {
+        if range.start < last {
+            continue;
+        }
+        job.append(&text[last..range.start], 0.0, plain.clone());
+        job.append(&text[range.clone()], 0.0, highlight.clone());
+        last = range.end;
+    }
+    job.append(&text[last..], 0.0, plain);
+    job
+}
+
+enum RowAction {
+    Toggle(usize),
+    Select(usize),
+    Open(String),
+    Sort(SortColumn),
+    Delete(String),
+    Edit(EditKind, String),
+}
+
+/// Context menu offering the staged edits for the entry or folder at `path`.
+/// `delete_label` names the action that takes it away.
+fn entry_context_menu(response: &egui::Response, path: &str, delete_label: &str, action: &mut Option<RowAction>) {
+    response.context_menu(|ui| {
+        if ui.button("Rename…").clicked() {
+            *action = Some(RowAction::Edit(EditKind::Rename, path.to_string()));
+            ui.close_menu();
+        }
+        if ui.button("Move to folder…").clicked() {
+            *action = Some(RowAction::Edit(EditKind::Move, path.to_string()));
+            ui.close_menu();
+        }
+        if ui.button(delete_label).clicked() {
+            *action = Some(RowAction::Delete(path.to_string()));
+            ui.close_menu();
+        }
+    });
+}
+
+/// Describes how staged changes affect an entry: deleted, or its new name.
+fn staged_label(pending: &std::collections::HashMap<String, Option<String>>, path: &str) -> Option<String> {
+    match pending.get(path)? {
+        None => Some("(deleted)".to_string()),
+        Some(name) if name != path => Some(format!("→ {}", name)),
+        Some(_) => None,
+    }
+}
+
+impl ArchiveManager {
+    /// Draws the compression list as the folder tree it will have in the
+    /// archive, with the size of each item and folder.
+    fn draw_staging_tree(&mut self, ui: &mut egui::Ui) {
+        let tree = self.staging.tree();
+        let expanded: HashSet<usize> = tree
+            .directories()
+            .filter(|&node| !self.staging_collapsed.contains(&tree.nodes[node].path))
+            .collect();
+        let mut action = None;
+        let mut clear = false;
+
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} items, {}",
+                self.staging.items.len(),
+                get_formatted_size(self.staging.total_size())
+            ));
+            if ui.button("New folder…").clicked() {
+                action = Some(RowAction::Edit(EditKind::NewFolder, String::new()));
+            }
+            if ui.button("Clear All").clicked() {
+                clear = true;
+            }
+        });
+
+        for (node, depth) in tree.visible_rows(&expanded) {
+            let entry = &tree.nodes[node];
+            let item = entry.file_index.and_then(|index| self.staging.items.get(index));
+            ui.horizontal(|ui| {
+                ui.add_space(depth as f32 * 16.0);
+                if ui.small_button("❌").on_hover_text("Remove from the list").clicked() {
+                    action = Some(RowAction::Delete(entry.path.clone()));
+                }
+                let response = if entry.is_directory {
+                    let arrow = if expanded.contains(&node) { "⏷" } else { "⏵" };
+                    let response = ui.add(Label::new(format!("{} 📁 {}", arrow, entry.name)).sense(Sense::click()));
+                    if response.clicked() {
+                        action = Some(RowAction::Toggle(node));
+                    }
+                    ui.weak(format!("{} · {} items", get_formatted_size(entry.size), entry.file_count));
+                    response
+                } else {
+                    let icon = if item.is_some_and(|item| item.is_dir) { "📂" } else { "📄" };
+                    let response = ui.add(Label::new(format!("{} {}", icon, entry.name)).sense(Sense::click()));
+                    ui.weak(get_formatted_size(entry.size));
+                    match item {
+                        Some(item) => response.on_hover_text(format!("From {}", item.source.display())),
+                        None => response,
+                    }
+                };
+                entry_context_menu(&response, &entry.path, "Remove from list", &mut action);
+            });
+        }
+
+        match action {
+            Some(RowAction::Toggle(node)) => {
+                let path = &tree.nodes[node].path;
+                if !self.staging_collapsed.remove(path) {
+                    self.staging_collapsed.insert(path.clone());
+                }
+            }
+            Some(RowAction::Delete(path)) => self.staging.remove(&path),
+            Some(RowAction::Edit(kind, path)) => self.entry_edit = Some(EntryEdit::new(kind, path, true)),
+            _ => {}
+        }
+        if clear {
+            self.staging.clear();
+            self.staging_collapsed.clear();
+        }
+    }
+
+    /// Draws one tree row and reports what the user did with it, if anything.
+    fn draw_tree_row(&mut self, ui: &mut egui::Ui, index: &ContentsIndex, node: usize, depth: usize) -> Option<RowAction> {
+        let entry = &index.tree.nodes[node];
+        let mut action = None;
+
+        ui.horizontal(|ui| {
+            ui.add_space(depth as f32 * 16.0);
+
+            if entry.is_directory {
+                let expanded = index.is_expanded(node);
+                let prefix = format!("{} 📁 ", if expanded { "⏷" } else { "⏵" });
+                let text = highlighted_text(ui, &prefix, &entry.name, index.filter(), ui.visuals().text_color());
+                let response = ui.add(Label::new(text).sense(Sense::click()));
+                if response.clicked() {
+                    action = Some(RowAction::Toggle(node));
+                }
+                entry_context_menu(&response, &entry.path, "Delete", &mut action);
+                ui.weak(format!("{} · {} files", get_formatted_size(entry.size), entry.file_count));
+                if let Some(staged) = staged_label(&self.pending_view, &entry.path) {
+                    ui.colored_label(Color32::LIGHT_RED, staged);
+                }
+                return;
+            }
+
+            let is_hovered = self.hover_file.as_deref() == Some(entry.path.as_str());
+            let color = if is_hovered {
+                Color32::YELLOW
+            } else {
+                ui.style().visuals.text_color()
+            };
+            let selected = entry.file_index.is_some_and(|file| self.selected_entries.contains(&file));
+            let text = highlighted_text(ui, "📄 ", &entry.name, index.filter(), color);
+            let response = ui.add(egui::SelectableLabel::new(selected, text));
+            ui.weak(get_formatted_size(entry.size));
+            if let Some(staged) = staged_label(&self.pending_view, &entry.path) {
+                ui.colored_label(Color32::LIGHT_RED, staged);
+            }
+
+            if response.hovered() {
+                self.hover_file = Some(entry.path.clone());
+                ui.output_mut(|o| o.cursor_icon = egui::CursorIcon::PointingHand);
+            } else if is_hovered {
+                self.hover_file = None;
+            }
+
+            if response.double_clicked() {
+                action = Some(RowAction::Open(entry.path.clone()));
+            } else if let (true, Some(file)) = (response.clicked(), entry.file_index) {
+                action = Some(RowAction::Select(file));
+            }
+
+            entry_context_menu(&response, &entry.path, "Delete", &mut action);
+            response.on_hover_text("Click to select, double-click to open, right-click to edit");
+        });
+
+        action
+    }
+
+    /// Draws the sortable metadata table. Only the rows in view are laid out.
+    fn draw_contents_table(&mut self, ui: &mut egui::Ui, index: &ContentsIndex, max_height: f32) -> Option<RowAction> {
+        let mut action = None;
+        let row_height = ui.text_style_height(&egui::TextStyle::Body) + 4.0;
+
+        TableBuilder::new(ui)
+            .striped(true)
+            .resizable(true)
+            .sense(Sense::click())
+            .max_scroll_height(max_height)
+            .column(Column::initial(240.0).at_least(80.0).clip(true))
+            .columns(Column::auto().at_least(40.0), SortColumn::ALL.len() - 2)
+            .column(Column::remainder().clip(true))
+            .header(row_height, |mut header| {
+                for column in SortColumn::ALL {
+                    header.col(|ui| {
+                        let arrow = match (self.sort_column == column, self.sort_ascending) {
+                            (true, true) => " ⏶",
+                            (true, false) => " ⏷",
+                            (false, _) => "",
+                        };
+                        if ui.button(format!("{}{}", column.title(), arrow)).clicked() {
+                            action = Some(RowAction::Sort(column));
+                        }
+                    });
+                }
+            })
+            .body(|body| {
+                let rows = index.table_rows();
+                body.rows(row_height, rows.len(), |mut row| {
+                    let file_index = rows[row.index()];
+                    let file = &index.files[file_index];
+                    row.set_selected(self.selected_entries.contains(&file_index));
+                    row.col(|ui| {
+                        let prefix = if file.is_directory { "📁 " } else { "📄 " };
+                        let color = ui.visuals().text_color();
+                        ui.add(Label::new(highlighted_text(ui, prefix, &file.name, index.filter(), color)).selectable(false));
+                        if let Some(staged) = staged_label(&self.pending_view, &file.name) {
+                            ui.colored_label(Color32::LIGHT_RED, staged);
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.label(get_formatted_size(file.size));
+                    });
+                    row.col(|ui| {
+                        ui.label(get_formatted_size(file.compressed_size));
+                    });
+                    row.col(|ui| {
+                        ui.label(format!("{:.1}%", file.ratio()));
+                    });
+                    row.col(|ui| {
+                        ui.label(file.formatted_modified());
+                    });
+                    row.col(|ui| {
+                        ui.monospace(format!("{:08X}", file.crc32));
+                    });
+                    row.col(|ui| {
+                        ui.label(&file.compression);
+                    });
+                    row.col(|ui| {
+                        ui.label(if file.encrypted { "🔒" } else { "" });
+                    });
+                    row.col(|ui| {
+                        ui.monospace(file.formatted_attributes());
+                    });
+                    row.col(|ui| {
+                        ui.label(&file.comment);
+                    });
+
+                    let response = row.response();
+                    if response.double_clicked() && !file.is_directory {
+                        action = Some(RowAction::Open(file.name.clone()));
+                    } else if response.clicked() {
+                        action = Some(RowAction::Select(file_index));
+                    }
+                    entry_context_menu(&response, &file.name, "Delete", &mut action);
+                });
+            });
+
+        action
+    }
+    /// Draws the comic-book reader: a thumbnail grid, or one page at a time
+    /// with keyboard paging. Returns false once the user leaves the reader.
+    fn draw_comic_reader(&mut self, ui: &mut egui::Ui) -> bool {
+        let Some(reader) = &mut self.comic_reader else {
+            return false;
+        };
+        let mut keep_open = true;
+
+        ui.horizontal(|ui| {
+            if ui.button("⬅ Back to contents").clicked() {
+                keep_open = false;
+            }
+            ui.separator();
+            if ui.selectable_label(reader.mode == ReaderMode::Grid, "Pages").clicked() {
+                reader.mode = ReaderMode::Grid;
+            }
+            if ui.selectable_label(reader.mode == ReaderMode::Page, "Reader").clicked() {
+                reader.go_to(reader.current);
+            }
+            ui.separator();
+            ui.label(format!("Page {} of {}", reader.current + 1, reader.pages.len()));
+        });
+        ui.separator();
+
+        match reader.mode {
+            ReaderMode::Grid => {
+                let cell = egui::vec2(THUMBNAIL_SIZE[0] as f32, THUMBNAIL_SIZE[1] as f32 + 20.0);
+                let spacing = ui.spacing().item_spacing;
+                let per_row = ((ui.available_width() + spacing.x) / (cell.x + spacing.x)).floor().max(1.0) as usize;
+                let rows = reader.pages.len().div_ceil(per_row);
+                let mut open_page = None;
+                let mut first_visible = 0;
+
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .show_rows(ui, cell.y, rows, |ui, range| {
+                        first_visible = range.start * per_row;
+                        for row in range {
+                            ui.horizontal(|ui| {
+                                for page in row * per_row..((row + 1) * per_row).min(reader.pages.len()) {
+                                    let (rect, response) = ui.allocate_exact_size(cell, Sense::click());
+                                    let image_rect =
+                                        egui::Rect::from_min_size(rect.min, egui::vec2(cell.x, cell.y - 20.0));
+                                    match reader.thumbnail(page) {
+                                        Some(Ok(texture)) => {
+                                            let size = texture.size_vec2();
+                                            let scale = (image_rect.width() / size.x).min(image_rect.height() / size.y);
+                                            let fitted = egui::Rect::from_center_size(image_rect.center(), size * scale);
+                                            egui::Image::new(&texture).paint_at(ui, fitted);
+                                        }
+                                        Some(Err(_)) => {
+                                            ui.painter().text(
+                                                image_rect.center(),
+                                                egui::Align2::CENTER_CENTER,
+                                                "⚠",
+                                                egui::FontId::proportional(24.0),
+                                                Color32::YELLOW,
+                                            );
+                                        }
+                                        None => {
+                                            ui.put(image_rect, egui::Spinner::new());
+                                        }
+                                    }
+                                    ui.painter().text(
+                                        egui::pos2(rect.center().x, rect.bottom() - 10.0),
+                                        egui::Align2::CENTER_CENTER,
+                                        (page + 1).to_string(),
+                                        egui::FontId::proportional(12.0),
+                                        ui.visuals().text_color(),
+                                    );
+                                    if response.hovered() {
+                                        ui.painter().rect_stroke(rect, 2.0, ui.visuals().selection.stroke);
+                                    }
+                                    if response.on_hover_text(&reader.pages[page]).clicked() {
+                                        open_page = Some(page);
+                                    }
+                                }
+                            });
+                        }
+                    });
+
+                reader.trim_thumbnails(first_visible);
+                if let Some(page) = open_page {
+                    reader.go_to(page);
+                }
+            }
+            ReaderMode::Page => {
+                ui.input(|i| {
+                    if i.key_pressed(egui::Key::ArrowRight)
+                        || i.key_pressed(egui::Key::PageDown)
+                        || i.key_pressed(egui::Key::Space)
+                    {
+                        reader.next_page();
+                    }
+                    if i.key_pressed(egui::Key::ArrowLeft)
+                        || i.key_pressed(egui::Key::PageUp)
+                        || i.key_pressed(egui::Key::Backspace)
+                    {
+                        reader.previous_page();
+                    }
+                    if i.key_pressed(egui::Key::Home) {
+                        reader.go_to(0);
+                    }
+                    if i.key_pressed(egui::Key::End) {
+                        reader.go_to(reader.pages.len() - 1);
+                    }
+                    if i.key_pressed(egui::Key::Escape) {
+                        reader.mode = ReaderMode::Grid;
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(reader.current > 0, egui::Button::new("◀ Previous")).clicked() {
+                        reader.previous_page();
+                    }
+                    if ui
+                        .add_enabled(reader.current + 1 < reader.pages.len(), egui::Button::new("Next ▶"))
+                        .clicked()
+                    {
+                        reader.next_page();
+                    }
+                    ui.weak(&reader.pages[reader.current]);
+                });
+
+                let current = reader.current;
+                let available = ui.available_size();
+                match reader.page(current) {
+                    Some(Ok(texture)) => {
+                        let size = texture.size_vec2();
+                        let scale = (available.x / size.x).min(available.y / size.y).min(1.0);
+                        ui.centered_and_justified(|ui| {
+                            ui.add(egui::Image::new(&texture).fit_to_exact_size(size * scale));
+                        });
+                    }
+                    Some(Err(e)) => {
+                        ui.colored_label(Color32::RED, format!("Cannot display page: {}", e));
+                    }
+                    None => {
+                        ui.centered_and_justified(|ui| {
+                            ui.spinner();
+                        });
+                    }
+                }
+            }
+        }
+
+        keep_open
+    }
+}
+
 impl eframe::App for ArchiveManager {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Set theme
@@ -104,12 +526,24 @@ impl eframe::App for ArchiveManager {
             self.draw_password_dialog(ctx);
         }
 
+        self.draw_test_report(ctx);
+        self.draw_content_search(ctx);
+        self.draw_safety_dialog(ctx);
+        self.draw_entry_edit_dialog(ctx);
+        self.draw_update_dialog(ctx);
+        self.draw_compression_preview(ctx);
+        self.check_watched_entries(ctx);
+        self.draw_entry_update_dialog(ctx);
+        self.start_pending_reader(ctx);
+
         // Top panel with buttons
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 if ui.button("Add Files").clicked() {
                     if let Some(files) = rfd::FileDialog::new().pick_files() {
-                        self.selected_files.extend(files);
+                        for file in files {
+                            self.staging.add(file);
+                        }
                     }
                 }
 
@@ -119,6 +553,8 @@ impl eframe::App for ArchiveManager {
             });
         });
 
+        self.draw_preview_panel(ctx);
+
         // Main central panel
         egui::CentralPanel::default().show(ctx, |ui| {
             if self.show_settings {
@@ -126,6 +562,16 @@ impl eframe::App for ArchiveManager {
                 ui.group(|ui| {
                     ui.heading("Settings");
                     ui.checkbox(&mut self.dark_mode, "Dark Mode");
+                    ui.separator();
+                    self.draw_signing_settings(ui);
+                    ui.separator();
+                    self.draw_safety_settings(ui);
+                    ui.separator();
+                    self.draw_restore_settings(ui);
+                    ui.separator();
+                    self.draw_link_settings(ui);
+                    ui.separator();
+                    self.draw_output_settings(ui);
                 });
             } else {
                 // Drop zone
@@ -135,71 +581,267 @@ impl eframe::App for ArchiveManager {
                 // Main content group
                 ui.group(|ui| {
                     // Handle archive contents or file list
-                    let mut current_archive_files = None;
-                    if let Some((_, files)) = &self.current_archive {
-                        current_archive_files = Some(files.clone());
-                    }
-
-                    if let Some(files) = current_archive_files {
+                    self.contents_zone.rect = None;
+                    if self.comic_reader.is_some() {
+                        if !self.draw_comic_reader(ui) {
+                            self.comic_reader = None;
+                        }
+                    } else if let Some(mut index) = self.contents_index.take() {
                         // Show archive contents
-                        ui.heading("Archive Contents");
-                        egui::ScrollArea::vertical()
-                            .max_height(200.0)
-                            .show(ui, |ui| {
-                                for file in &files {
-                                    let text = if file.is_directory {
-                                        format!("📁 {}", file.name)
-                                    } else {
-                                        format!("📄 {} ({} bytes)", file.name, file.size)
-                                    };
-
-                                    if !file.is_directory {
-                                        let is_hovered = Some(file.name.clone()) == self.hover_file;
-                                        let response = ui.add(
-                                            Label::new(
-                                                RichText::new(&text)
-                                                    .color(if is_hovered {
-                                                        Color32::YELLOW
-                                                    } else {
-                                                        ui.style().visuals.text_color()
-                                                    })
-                                            )
-                                                .sense(Sense::click())
-                                        );
-
-                                        if response.hovered() {
-                                            self.hover_file = Some(file.name.clone());
-                                            ui.output_mut(|o| o.cursor_icon = egui::CursorIcon::PointingHand);
-                                        } else if Some(file.name.clone()) == self.hover_file && !is_hovered {
-                                            self.hover_file = None;
+                        ui.horizontal(|ui| {
+                            ui.heading("Archive Contents");
+                            draw_signature_badge(ui, &self.signature_status);
+                            if self.opened_read_only() {
+                                ui.label(RichText::new("🔒 Read-only").strong())
+                                    .on_hover_text("Copied from split volumes or a 7z archive; changes cannot be written back to them");
+                            }
+                            if self.recovered_entries.is_some() {
+                                ui.label(RichText::new("⚠ Recovered").color(Color32::YELLOW).strong())
+                                    .on_hover_text("Central directory is damaged; entries were rebuilt from local headers");
+                                if ui.button("Save repaired archive…").clicked() {
+                                    if let Err(e) = self.save_repaired_archive() {
+                                        self.status_message = format!("Error writing repaired archive: {}", e);
+                                    }
+                                }
+                            } else {
+                                if ui.button("Test archive").clicked() {
+                                    if let Err(e) = self.test_archive_with_password(None) {
+                                        self.status_message = format!("Error testing archive: {}", e);
+                                    }
+                                }
+                                if ui.button("Search in contents…").clicked() {
+                                    self.show_content_search = true;
+                                }
+                                if ui.button("Add…").on_hover_text("Add files to this archive, or drop them on the list").clicked() {
+                                    if let Some(files) = rfd::FileDialog::new().pick_files() {
+                                        if let Err(e) = self.add_files_to_archive(files, None) {
+                                            self.status_message = format!("Error adding files: {}", e);
                                         }
+                                    }
+                                }
+                                ui.checkbox(&mut self.replace_existing, "Replace existing")
+                                    .on_hover_text("Replace entries with the same name when adding files");
+                                if ui.button("Update from folder…").on_hover_text("Update, freshen or synchronize against a folder").clicked() {
+                                    if let Some(source) = rfd::FileDialog::new().pick_folder() {
+                                        self.update_request = Some(UpdateRequest {
+                                            source,
+                                            mode: UpdateMode::Update,
+                                            detection: ChangeDetection::SizeAndTime,
+                                        });
+                                    }
+                                }
+                                if self.is_comic && ui.button("📖 Read as comic").clicked() {
+                                    if let Err(e) = self.open_comic_reader(ctx, None) {
+                                        self.status_message = format!("Error opening reader: {}", e);
+                                    }
+                                }
+                            }
+                        });
 
-                                        if response.double_clicked() {
-                                            let _ = self.open_file(file.name.clone());
-                                        }
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "{} files, {}",
+                                index.tree.nodes[ROOT].file_count,
+                                get_formatted_size(index.tree.nodes[ROOT].size)
+                            ));
+                            ui.separator();
+                            ui.selectable_value(&mut self.contents_view, ContentsView::Tree, "Tree");
+                            ui.selectable_value(&mut self.contents_view, ContentsView::Table, "Table");
+                            ui.checkbox(&mut self.show_preview, "Preview");
+                            if self.contents_view == ContentsView::Tree {
+                                if ui.small_button("Expand all").clicked() {
+                                    index.expand_all();
+                                }
+                                if ui.small_button("Collapse all").clicked() {
+                                    index.collapse_all();
+                                }
+                            }
+                        });
 
-                                        response.on_hover_text("Double-click to open");
-                                    } else {
-                                        ui.label(text);
+                        let mut extract_selected = false;
+                        ui.horizontal(|ui| {
+                            let search = ui.add(
+                                egui::TextEdit::singleline(&mut self.filter_text)
+                                    .hint_text("🔍 Filter entries (text, **/*.log or regex)")
+                                    .desired_width(260.0),
+                            );
+                            let mut changed = search.changed();
+                            egui::ComboBox::from_id_salt("filter_mode")
+                                .selected_text(self.filter_mode.title())
+                                .show_ui(ui, |ui| {
+                                    for mode in [FilterMode::Substring, FilterMode::Glob, FilterMode::Regex] {
+                                        changed |= ui.selectable_value(&mut self.filter_mode, mode, mode.title()).changed();
                                     }
+                                });
+                            if changed {
+                                index.set_filter(self.compile_filter());
+                            }
+
+                            if let Some(error) = &self.filter_error {
+                                ui.colored_label(Color32::RED, "Invalid pattern").on_hover_text(error);
+                            } else if index.filter().is_some() {
+                                ui.label(format!("{} of {} match", index.match_count(), index.files.len()));
+                                if ui.small_button("Select all matches").clicked() {
+                                    self.selected_entries = index.matching_files().into_iter().collect();
+                                }
+                            }
+                        });
+
+                        if !self.selected_entries.is_empty() {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("{} selected", self.selected_entries.len()));
+                                if ui.button(format!("Extract selected ({})…", self.selected_entries.len())).clicked() {
+                                    extract_selected = true;
+                                }
+                                if ui.button("Delete selected").clicked() {
+                                    let mut names: Vec<String> =
+                                        self.selected_entries.iter().map(|&file| index.files[file].name.clone()).collect();
+                                    names.sort();
+                                    for name in names {
+                                        if self.pending_view.get(&name).is_some_and(|staged| staged.is_none()) {
+                                            continue;
+                                        }
+                                        if let Err(e) = self.stage_change(EntryChange::Delete(name)) {
+                                            self.status_message = format!("Error staging change: {}", e);
+                                        }
+                                    }
+                                    self.selected_entries.clear();
+                                }
+                                if ui.small_button("Clear selection").clicked() {
+                                    self.selected_entries.clear();
+                                }
+                            });
+                        }
+
+                        let mut apply_changes = false;
+                        if !self.pending_changes.is_empty() {
+                            ui.horizontal(|ui| {
+                                let count = self.pending_changes.changes.len();
+                                let list: Vec<String> = self.pending_changes.changes.iter().map(EntryChange::describe).collect();
+                                ui.colored_label(Color32::LIGHT_RED, format!("✏ {} pending changes", count))
+                                    .on_hover_text(list.join("\n"));
+                                if ui.button("Undo").clicked() {
+                                    self.undo_change();
+                                }
+                                if ui.button("Apply changes").clicked() {
+                                    apply_changes = true;
+                                }
+                                if ui.small_button("Discard").clicked() {
+                                    self.discard_changes();
                                 }
                             });
-                    } else if !self.selected_files.is_empty() {
+                        }
+
+                        let mut action = None;
+                        let max_height = (ui.available_height() - 120.0).max(200.0);
+                        match self.contents_view {
+                            ContentsView::Tree => {
+                                let row_height = ui.spacing().interact_size.y;
+                                let rows = index.tree_rows().len();
+                                egui::ScrollArea::vertical()
+                                    .max_height(max_height)
+                                    .auto_shrink([false, true])
+                                    .show_rows(ui, row_height, rows, |ui, range| {
+                                        for &(node, depth) in &index.tree_rows()[range] {
+                                            if let Some(row_action) = self.draw_tree_row(ui, &index, node, depth) {
+                                                action = Some(row_action);
+                                            }
+                                        }
+                                    });
+                            }
+                            ContentsView::Table => {
+                                action = self.draw_contents_table(ui, &index, max_height);
+                            }
+                        }
+
+                        let mut file_to_open = None;
+                        match action {
+                            Some(RowAction::Toggle(node)) => index.toggle(node),
+                            Some(RowAction::Sort(column)) => {
+                                self.sort_by(column);
+                                index.sort(self.sort_column, self.sort_ascending);
+                            }
+                            Some(RowAction::Select(file)) => {
+                                self.toggle_selected(file);
+                                let entry = &index.files[file];
+                                if self.show_preview && !entry.is_directory && self.selected_entries.contains(&file) {
+                                    if let Err(e) = self.preview_entry(entry.name.clone(), PREVIEW_CHUNK, None) {
+                                        self.status_message = format!("Error previewing entry: {}", e);
+                                    }
+                                }
+                            }
+                            Some(RowAction::Open(name)) => file_to_open = Some(name),
+                            Some(RowAction::Delete(path)) => {
+                                if let Err(e) = self.stage_change(EntryChange::Delete(path)) {
+                                    self.status_message = format!("Error staging change: {}", e);
+                                }
+                            }
+                            Some(RowAction::Edit(kind, path)) => self.entry_edit = Some(EntryEdit::new(kind, path, false)),
+                            None => {}
+                        }
+
+                        self.contents_zone.rect = Some(ui.min_rect());
+
+                        // An action above may have replaced the archive; keep the newer index
+                        if self.contents_index.is_none() {
+                            self.contents_index = Some(index);
+                        }
+
+                        if let Some(file_name) = file_to_open {
+                            let _ = self.open_file(file_name);
+                        }
+                        if extract_selected {
+                            if let Err(e) = self.extract_selected() {
+                                self.status_message = format!("Error extracting entries: {}", e);
+                            }
+                        }
+                        if apply_changes {
+                            if let Err(e) = self.apply_pending_changes() {
+                                self.status_message = format!("Error applying changes: {}", e);
+                            }
+                        }
+                    } else if !self.staging.is_empty() {
                         // Show files to compress
                         ui.heading("Files to Compress");
                         egui::ScrollArea::vertical()
                             .max_height(200.0)
                             .show(ui, |ui| {
-                                draw_file_list(ui, &self.selected_files, &mut self.files_to_remove);
+                                self.draw_staging_tree(ui);
                             });
+                        self.draw_input_filters(ui);
 
-                        if ui.button("Compress Files").clicked() {
-                            let _ = self.compress_files();
-                        }
+                        ui.horizontal(|ui| {
+                            if ui.button("Preview").clicked() {
+                                self.preview_compression();
+                            }
+                            if ui.button("Compress Files").clicked() {
+                                let _ = self.compress_files();
+                            }
+                        });
+                    }
+
+                    let compression_result = self.progress_state.lock().ok().and_then(|mut state| state.compression_result.take());
+                    if let Some(result) = compression_result {
+                        self.status_message = result.unwrap_or_else(|e| e);
+                    }
+
+                    let open_entry_error = self.progress_state.lock().ok().and_then(|mut state| state.open_entry_error.take());
+                    if let Some(error) = open_entry_error {
+                        self.status_message = error;
+                    }
+
+                    let archive_update = self.progress_state.lock().ok().and_then(|mut state| state.archive_update.take());
+                    if let Some(update) = archive_update {
+                        self.finish_archive_update(update);
+                    }
+
+                    let readable_copy = self.progress_state.lock().ok().and_then(|mut state| state.readable_copy.take());
+                    if let Some(copy) = readable_copy {
+                        self.finish_readable_copy(copy);
                     }
 
                     // Handle progress states
-                    let show_action_dialog = if let Ok(state) = self.progress_state.lock() {
+                    let show_action_dialog = if let Ok(mut state) = self.progress_state.lock() {
                         // Show compression progress if any
                         if let Some((progress, stats)) = &state.compression_progress {
                             ui.add_space(10.0);
@@ -210,7 +852,8 @@ impl eframe::App for ArchiveManager {
                             );
 
                             ui.label(format!(
-                                "Original size: {}\nCompressed size: {}\nCompression ratio: {:.1}%\nTime elapsed: {}\nTime remaining: {}\nFiles processed: {}/{}",
+                                "Output: {}\nOriginal size: {}\nCompressed size: {}\nCompression ratio: {:.1}%\nTime elapsed: {}\nTime remaining: {}\nFiles processed: {}/{}",
+                                stats.output_path.display(),
                                 get_formatted_size(stats.original_size),
                                 get_formatted_size(stats.compressed_size),
                                 if stats.original_size > 0 {
@@ -225,6 +868,34 @@ impl eframe::App for ArchiveManager {
                             ));
                         }
 
+                        // Show archive test progress if any
+                        if let Some(progress) = state.test_progress {
+                            ui.add_space(10.0);
+                            ui.add(
+                                egui::ProgressBar::new(progress)
+                                    .text(format!("Testing archive... {:.1}%", progress * 100.0))
+                                    .animate(true)
+                            );
+                        }
+
+                        if let Some((label, progress)) = state.copy_progress {
+                            ui.add_space(10.0);
+                            ui.add(
+                                egui::ProgressBar::new(progress)
+                                    .text(format!("{}... {:.1}%", label, progress * 100.0))
+                                    .animate(true)
+                            );
+                        }
+
+                        if let Some(progress) = state.rewrite_progress {
+                            ui.add_space(10.0);
+                            ui.add(
+                                egui::ProgressBar::new(progress)
+                                    .text(format!("Updating archive... {:.1}%", progress * 100.0))
+                                    .animate(true)
+                            );
+                        }
+
                         // Show extraction progress if any
                         if let Some((progress, stats)) = &state.extraction_progress {
                             ui.add_space(10.0);
@@ -242,6 +913,18 @@ impl eframe::App for ArchiveManager {
                                 format_duration(stats.estimated_time)
                             ));
                         }
+                        if let Some(report) = state.test_report.take() {
+                            self.status_message = if report.is_ok() {
+                                "Archive test passed".to_string()
+                            } else {
+                                format!(
+                                    "Error: Archive test found {} failed entries and {} problems",
+                                    report.failed_count(),
+                                    report.problems.len()
+                                )
+                            };
+                            self.test_report = Some(report);
+                        }
                         self.show_action_dialog
                     } else {
                         false
@@ -268,7 +951,7 @@ impl eframe::App for ArchiveManager {
 
                                 if compress {
                                     info!("Adding archive to compression list");
-                                    self.selected_files.push(path_clone.clone());
+                                    self.staging.add(path_clone.clone());
                                     self.status_message = "Archive added to compression list".to_string();
                                 } else {
                                     info!("Opening archive for viewing");
@@ -301,6 +984,5 @@ impl eframe::App for ArchiveManager {
 
         // Handle drag and drop
         self.handle_drops(ctx);
-        self.cleanup_removed_files();
     }
 }
\ No newline at end of file
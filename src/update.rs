@@ -0,0 +1,303 @@
+use crate::filters::FilterSet;
+use crate::metadata::zip_datetime;
+use crate::rewrite::{
+    collect_named_entries, collect_new_entries, read_raw_entries, rewrite_archive, LinkPolicy, NewEntry,
+    RawEntry,
+};
+use crate::signing;
+use crate::sparse::SparseReader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// AES entries written as AE-2 store a zero CRC, so their content cannot be compared
+const AES_METHOD: u16 = 99;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpdateMode {
+    /// Add new files and replace changed ones
+    Update,
+    /// Only replace entries that are already in the archive
+    Freshen,
+    /// Like update, and also remove entries whose source is gone
+    Sync,
+}
+
+impl UpdateMode {
+    pub const ALL: [UpdateMode; 3] = [UpdateMode::Update, UpdateMode::Freshen, UpdateMode::Sync];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            UpdateMode::Update => "Update",
+            UpdateMode::Freshen => "Freshen",
+            UpdateMode::Sync => "Synchronize",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            UpdateMode::Update => "Add new files and replace changed ones",
+            UpdateMode::Freshen => "Only replace entries already in the archive",
+            UpdateMode::Sync => "Add and replace, and remove entries whose source is gone",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "update" => Some(UpdateMode::Update),
+            "freshen" => Some(UpdateMode::Freshen),
+            "sync" => Some(UpdateMode::Sync),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChangeDetection {
+    SizeAndTime,
+    /// Compares the CRC-32 of the source file with the one stored in the archive
+    ContentHash,
+}
+
+impl ChangeDetection {
+    pub fn title(self) -> &'static str {
+        match self {
+            ChangeDetection::SizeAndTime => "Size and modification time",
+            ChangeDetection::ContentHash => "Content hash (CRC-32)",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UpdateSummary {
+    pub added: usize,
+    pub replaced: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl UpdateSummary {
+    pub fn has_changes(&self) -> bool {
+        self.added + self.replaced + self.removed > 0
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} added, {} replaced, {} removed, {} unchanged",
+            self.added, self.replaced, self.removed, self.unchanged
+        )
+    }
+}
+
+pub struct UpdatePlan {
+    /// Entries raw-copied from the existing archive, in their original order
+    pub keep: Vec<RawEntry>,
+    /// New and changed files, compressed after the kept entries
+    pub add: Vec<NewEntry>,
+    pub summary: UpdateSummary,
+}
+
+/// Name of the folder `source` as compressing it would store it, if it has one.
+fn folder_name(source: &Path) -> Option<String> {
+    let source = source.canonicalize().ok()?;
+    Some(source.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Compares the archive's `entries` with the contents of `source` and decides
+/// what to keep, replace, add and drop. Like compressing the folder (and like
+/// `7z u`), entries are named below the folder's own name; an archive with no
+/// entries under that name was made from the folder's contents, and keeps
+/// names relative to `source`.
+pub fn plan_update(
+    entries: Vec<RawEntry>,
+    source: &Path,
+    mode: UpdateMode,
+    detection: ChangeDetection,
+    encrypt: bool,
+    links: LinkPolicy,
+) -> io::Result<UpdatePlan> {
+    let root = folder_name(source).filter(|name| {
+        let prefix = format!("{}/", name);
+        entries.is_empty() || entries.iter().any(|entry| entry.name.starts_with(&prefix))
+    });
+    let sources = match root {
+        Some(name) => collect_named_entries(&[(source.to_path_buf(), name)], encrypt, links, &FilterSet::default())?,
+        None => {
+            let mut children: Vec<PathBuf> = std::fs::read_dir(source)?
+                .map(|child| child.map(|child| child.path()))
+                .collect::<Result<_, _>>()?;
+            children.sort();
+            collect_new_entries(&children, "", encrypt, links, &FilterSet::default())?
+        }
+    };
+    let mut unmatched: HashMap<String, usize> =
+        sources.iter().enumerate().map(|(index, entry)| (entry.name.clone(), index)).collect();
+
+    let mut keep = Vec::with_capacity(entries.len());
+    let mut add = Vec::new();
+    let mut summary = UpdateSummary::default();
+    for entry in entries {
+        match unmatched.remove(&entry.name) {
+            Some(index) => {
+                let is_directory = entry.name.ends_with('/');
+                if is_directory || is_unchanged(&entry, &sources[index], detection)? {
+                    summary.unchanged += 1;
+                    keep.push(entry);
+                } else {
+                    summary.replaced += 1;
+                    add.push(index);
+                }
+            }
+            None if mode == UpdateMode::Sync => summary.removed += 1,
+            None => keep.push(entry),
+        }
+    }
+    if mode != UpdateMode::Freshen {
+        let mut new: Vec<usize> = unmatched.into_values().collect();
+        summary.added = new.len();
+        add.append(&mut new);
+    }
+
+    // Keep the source walk order so folders are written before their contents
+    add.sort_unstable();
+    let mut sources: Vec<Option<NewEntry>> = sources.into_iter().map(Some).collect();
+    let add = add.into_iter().filter_map(|index| sources[index].take()).collect();
+    Ok(UpdatePlan { keep, add, summary })
+}
+
+fn is_unchanged(entry: &RawEntry, source: &NewEntry, detection: ChangeDetection) -> io::Result<bool> {
+    let metadata = source.metadata()?;
+    // A stored link holds its target path, whose length a link's metadata reports
+    if metadata.len() != entry.size {
+        return Ok(false);
+    }
+
+    if detection == ChangeDetection::ContentHash && entry.method != AES_METHOD {
+        let mut hasher = crc32fast::Hasher::new();
+        if let Some(target) = &source.link_target {
+            hasher.update(target.to_string_lossy().as_bytes());
+            return Ok(hasher.finalize() == entry.crc32);
+        }
+        let mut file = SparseReader::open(&source.source)?;
+        let mut buffer = vec![0; 1024 * 1024];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+        return Ok(hasher.finalize() == entry.crc32);
+    }
+
+    let modified = metadata.modified()?;
+    if let Some(unix) = entry.modified_unix() {
+        let seconds = modified.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64);
+        return Ok(seconds == unix);
+    }
+    Ok(zip_datetime(modified).is_some_and(|time| (time.timepart(), time.datepart()) == (entry.mod_time, entry.mod_date)))
+}
+
+/// Brings `archive_path` up to date with `source`. Unchanged entries are
+/// raw-copied; the archive is only rewritten when something changed. A missing
+/// archive is created unless freshening. New entries are encrypted when a
+/// password is given.
+pub fn update_archive(
+    archive_path: &Path,
+    source: &Path,
+    mode: UpdateMode,
+    detection: ChangeDetection,
+    password: Option<&str>,
+    links: LinkPolicy,
+    progress: impl Fn(f32),
+) -> Result<UpdateSummary, Box<dyn std::error::Error>> {
+    if !source.is_dir() {
+        return Err(format!("{} is not a folder", source.display()).into());
+    }
+    if !archive_path.exists() {
+        if mode == UpdateMode::Freshen {
+            return Err(format!("{} does not exist", archive_path.display()).into());
+        }
+        zip::ZipWriter::new(File::create(archive_path)?).finish()?;
+    }
+
+    let (entries, comment) = read_raw_entries(archive_path)?;
+    let plan = plan_update(entries, source, mode, detection, password.is_some(), links)?;
+    if !plan.summary.has_changes() {
+        return Ok(plan.summary);
+    }
+
+    // Any embedded signature no longer matches the rewritten contents
+    let comment = if signing::is_signature_comment(&comment) { Vec::new() } else { comment };
+    rewrite_archive(archive_path, &plan.keep, &plan.add, &comment, password, progress)?;
+    Ok(plan.summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(archive: &Path) -> Vec<String> {
+        let mut names: Vec<String> = read_raw_entries(archive).unwrap().0.into_iter().map(|entry| entry.name).collect();
+        names.sort();
+        names
+    }
+
+    fn project(dir: &Path) -> PathBuf {
+        let source = dir.join("proj");
+        std::fs::create_dir_all(source.join("src")).unwrap();
+        std::fs::write(source.join("a.txt"), b"a").unwrap();
+        std::fs::write(source.join("src/main.rs"), b"fn main() {}").unwrap();
+        source
+    }
+
+    #[test]
+    fn sync_matches_names_written_by_compress() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = project(dir.path());
+        let archive = dir.path().join("p.zip");
+        // What compressing the folder writes
+        zip::ZipWriter::new(File::create(&archive).unwrap()).finish().unwrap();
+        let entries = collect_new_entries(std::slice::from_ref(&source), "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+        rewrite_archive(&archive, &[], &entries, &[], None, |_| {}).unwrap();
+
+        let mode = UpdateMode::Sync;
+        let summary = update_archive(&archive, &source, mode, ChangeDetection::ContentHash, None, LinkPolicy::Store, |_| {}).unwrap();
+        assert_eq!((summary.added, summary.replaced, summary.removed, summary.unchanged), (0, 0, 0, 4));
+
+        std::fs::write(source.join("b.txt"), b"b").unwrap();
+        std::fs::remove_file(source.join("a.txt")).unwrap();
+        let summary = update_archive(&archive, &source, mode, ChangeDetection::ContentHash, None, LinkPolicy::Store, |_| {}).unwrap();
+        assert_eq!((summary.added, summary.removed), (1, 1));
+        assert_eq!(names(&archive), ["proj/", "proj/b.txt", "proj/src/", "proj/src/main.rs"]);
+    }
+
+    #[test]
+    fn new_archives_are_rooted_at_the_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = project(dir.path());
+        let archive = dir.path().join("new.zip");
+        let summary =
+            update_archive(&archive, &source, UpdateMode::Update, ChangeDetection::SizeAndTime, None, LinkPolicy::Store, |_| {}).unwrap();
+        assert_eq!(summary.added, 4);
+        assert_eq!(names(&archive), ["proj/", "proj/a.txt", "proj/src/", "proj/src/main.rs"]);
+    }
+
+    #[test]
+    fn archives_of_the_folder_contents_keep_their_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = project(dir.path());
+        let archive = dir.path().join("flat.zip");
+        zip::ZipWriter::new(File::create(&archive).unwrap()).finish().unwrap();
+        let children = [source.join("a.txt"), source.join("src")];
+        let entries = collect_new_entries(&children, "", false, LinkPolicy::Store, &FilterSet::default()).unwrap();
+        rewrite_archive(&archive, &[], &entries, &[], None, |_| {}).unwrap();
+
+        let summary =
+            update_archive(&archive, &source, UpdateMode::Sync, ChangeDetection::ContentHash, None, LinkPolicy::Store, |_| {}).unwrap();
+        assert!(!summary.has_changes());
+        assert_eq!(names(&archive), ["a.txt", "src/", "src/main.rs"]);
+    }
+}
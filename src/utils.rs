@@ -1,5 +1,9 @@
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use zip::result::ZipResult;
+use zip::ZipArchive;
 
 pub fn open_system_file(path: &Path) -> std::io::Result<()> {
     #[cfg(target_os = "windows")]
@@ -23,6 +27,33 @@ pub fn open_system_file(path: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Creates an empty file in the same folder as `path` under a name nothing
+/// else uses, for building a replacement that is renamed over `path` once it
+/// is complete.
+pub fn create_temp_beside(path: &Path) -> io::Result<(PathBuf, File)> {
+    let folder = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    for _ in 0..100 {
+        let candidate = folder.join(format!(".{}.{:08x}.partial", name, rand::random::<u32>()));
+        match OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("no free temporary name next to {}", path.display())))
+}
+
+/// Free space available to the current user on the filesystem holding `path`.
+/// Walks up to the nearest existing ancestor so it works for paths not yet created.
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(path);
+    fs2::available_space(existing)
+}
+
 pub fn get_temp_dir() -> std::path::PathBuf {
     std::env::temp_dir().join("archive_viewer")
 }
@@ -41,4 +72,60 @@ pub fn get_formatted_size(size: u64) -> String {
     } else {
         format!("{} bytes", size)
     }
-}
\ No newline at end of file
+}
+/// True if the archive's entries are AES-encrypted, judged by the first one.
+/// An empty archive needs no password.
+pub fn archive_needs_password<R: Read + Seek>(archive: &mut ZipArchive<R>) -> ZipResult<bool> {
+    if archive.is_empty() {
+        return Ok(false);
+    }
+    Ok(archive.get_aes_verification_key_and_salt(0)?.is_some())
+}
+
+pub fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{AesMode, ZipWriter};
+
+    #[test]
+    fn empty_archive_needs_no_password() {
+        let buffer = ZipWriter::new(Cursor::new(Vec::new())).finish().unwrap();
+        assert!(!archive_needs_password(&mut ZipArchive::new(buffer).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn aes_archive_needs_a_password() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().with_aes_encryption(AesMode::Aes256, "secret");
+        writer.start_file("a.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        let mut archive = ZipArchive::new(writer.finish().unwrap()).unwrap();
+        assert!(archive_needs_password(&mut archive).unwrap());
+    }
+
+    #[test]
+    fn temp_files_never_reuse_a_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("foo.zip");
+        std::fs::write(dir.path().join("foo.partial"), b"keep").unwrap();
+        let (first, _) = create_temp_beside(&archive).unwrap();
+        let (second, _) = create_temp_beside(&archive).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(dir.path()));
+        assert_eq!(std::fs::read(dir.path().join("foo.partial")).unwrap(), b"keep");
+    }
+}
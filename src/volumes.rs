@@ -0,0 +1,637 @@
+use crate::utils::{u16_at, u32_at, u64_at};
+use crate::rewrite::{
+    CENTRAL_HEADER_SIGNATURE, END_OF_CENTRAL_DIRECTORY_SIGNATURE, LOCAL_HEADER_SIGNATURE, MAX_32,
+    ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE, ZIP64_EXTRA_ID, ZIP64_LOCATOR_SIGNATURE,
+};
+use crate::utils::create_temp_beside;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Starts the first volume of a spanned set
+const SPANNING_SIGNATURE: &[u8; 4] = b"PK\x07\x08";
+/// Written instead by some tools when a spanned set fit in one volume
+const SINGLE_SEGMENT_SIGNATURE: &[u8; 4] = b"PK00";
+const END_RECORD_SIZE: usize = 22;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+const MAX_16: u64 = 0xFFFF;
+/// Bytes copied between progress reports while joining
+const COPY_BUFFER: usize = 1024 * 1024;
+/// Smallest volume accepted, as with Info-ZIP's `zip -s`
+pub const MIN_VOLUME_SIZE: u64 = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum VolumeStyle {
+    /// The archive cut into `archive.zip.001`, `.002`, …; joined by concatenation
+    #[default]
+    Numbered,
+    /// A PKZIP spanned set, `archive.z01`, `.z02`, … and `archive.zip` last
+    Spanned,
+}
+
+impl VolumeStyle {
+    pub const ALL: [VolumeStyle; 2] = [VolumeStyle::Numbered, VolumeStyle::Spanned];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            VolumeStyle::Numbered => "archive.zip.001, .002, …",
+            VolumeStyle::Spanned => "archive.z01, .z02, …, .zip",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "numbered" => Some(VolumeStyle::Numbered),
+            "spanned" => Some(VolumeStyle::Spanned),
+            _ => None,
+        }
+    }
+}
+
+/// The volumes of a split archive, in order.
+pub struct VolumeSet {
+    pub style: VolumeStyle,
+    pub volumes: Vec<PathBuf>,
+    /// File name for the joined archive
+    pub name: String,
+}
+
+fn numbered_volume(base: &Path, number: usize) -> PathBuf {
+    let mut name = OsString::from(base.as_os_str());
+    name.push(format!(".{:03}", number));
+    PathBuf::from(name)
+}
+
+fn spanned_volume(last: &Path, number: usize) -> PathBuf {
+    last.with_extension(format!("z{:02}", number))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase()
+}
+
+/// True for `.001`-style and `.z01`-style volume names.
+pub fn is_volume_name(path: &Path) -> bool {
+    let ext = extension(path);
+    let digits = |text: &str| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+    (ext.len() == 3 && digits(&ext)) || (ext.len() >= 3 && ext.starts_with('z') && digits(&ext[1..]))
+}
+
+/// Finds the split set `path` belongs to, if it is one. Any volume can be
+/// given; a `.zip` is the last volume of a spanned set when its end record
+/// lies on a later disk than the first. Fails naming the first missing volume.
+pub fn find_volume_set(path: &Path) -> Result<Option<VolumeSet>, Box<dyn std::error::Error>> {
+    let ext = extension(path);
+    let file_name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+    if is_volume_name(path) && !ext.starts_with('z') {
+        let number: usize = ext.parse()?;
+        if number == 0 {
+            return Ok(None);
+        }
+        let base = path.with_extension("");
+        let volumes: Vec<PathBuf> = (1..).map(|n| numbered_volume(&base, n)).take_while(|volume| volume.exists()).collect();
+        if volumes.len() < number {
+            return Err(format!("Missing volume {}", numbered_volume(&base, volumes.len() + 1).display()).into());
+        }
+        return Ok(Some(VolumeSet { style: VolumeStyle::Numbered, volumes, name: file_name(&base) }));
+    }
+
+    let last = if is_volume_name(path) {
+        path.with_extension("zip")
+    } else if ext == "zip" && last_disk_number(path).is_ok_and(|disk| disk > 0) {
+        path.to_path_buf()
+    } else {
+        return Ok(None);
+    };
+    if !last.exists() {
+        return Err(format!("Missing volume {} (the last one of the set)", last.display()).into());
+    }
+
+    let disks = last_disk_number(&last)? as usize;
+    let mut volumes: Vec<PathBuf> = (1..=disks).map(|n| spanned_volume(&last, n)).collect();
+    if let Some(missing) = volumes.iter().find(|volume| !volume.exists()) {
+        return Err(format!("Missing volume {}", missing.display()).into());
+    }
+    volumes.push(last.clone());
+    Ok(Some(VolumeSet { style: VolumeStyle::Spanned, volumes, name: file_name(&last) }))
+}
+
+/// Position of the end of central directory record in `data`, which holds the
+/// end of an archive.
+fn find_end_record(data: &[u8]) -> Option<usize> {
+    (0..=data.len().checked_sub(END_RECORD_SIZE)?).rev().find(|&pos| {
+        &data[pos..pos + 4] == END_OF_CENTRAL_DIRECTORY_SIGNATURE
+            && pos + END_RECORD_SIZE + u16_at(data, pos + 20) as usize == data.len()
+    })
+}
+
+/// Reads the end of `file`, enough of it to hold the end records.
+fn read_end(file: &mut File) -> io::Result<(u64, Vec<u8>)> {
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub((END_RECORD_SIZE + ZIP64_LOCATOR_SIZE + u16::MAX as usize) as u64);
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = Vec::with_capacity((len - start) as usize);
+    file.read_to_end(&mut data)?;
+    Ok((start, data))
+}
+
+/// The number of the disk holding the end records, i.e. the volume count minus one.
+fn last_disk_number(last: &Path) -> Result<u32, Box<dyn std::error::Error>> {
+    let (_, data) = read_end(&mut File::open(last)?)?;
+    let end = find_end_record(&data).ok_or_else(|| format!("{} is not a ZIP volume", last.display()))?;
+    let disk = u16_at(&data, end + 4) as u64;
+    if disk == MAX_16 && end >= ZIP64_LOCATOR_SIZE && &data[end - ZIP64_LOCATOR_SIZE..end - 16] == ZIP64_LOCATOR_SIGNATURE {
+        return Ok(u32_at(&data, end - ZIP64_LOCATOR_SIZE + 16).saturating_sub(1));
+    }
+    Ok(disk as u32)
+}
+
+/// Where the central directory starts, as (disk, offset on that disk).
+fn directory_location(file: &mut File, to_absolute: impl Fn(u32, u64) -> u64) -> Result<(u32, u64), Box<dyn std::error::Error>> {
+    let (_, data) = read_end(file)?;
+    let end = find_end_record(&data).ok_or("End of central directory not found")?;
+    let disk = u16_at(&data, end + 6) as u64;
+    let offset = u32_at(&data, end + 16) as u64;
+    if (disk != MAX_16 && offset != MAX_32) || end < ZIP64_LOCATOR_SIZE {
+        return Ok((disk as u32, offset));
+    }
+    let locator = end - ZIP64_LOCATOR_SIZE;
+    if &data[locator..locator + 4] != ZIP64_LOCATOR_SIGNATURE {
+        return Ok((disk as u32, offset));
+    }
+    let record = to_absolute(u32_at(&data, locator + 4), u64_at(&data, locator + 8));
+    let mut zip64 = [0u8; 56];
+    file.seek(SeekFrom::Start(record))?;
+    file.read_exact(&mut zip64)?;
+    if &zip64[0..4] != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+        return Err("Zip64 end of central directory not found".into());
+    }
+    Ok((u32_at(&zip64, 20), u64_at(&zip64, 48)))
+}
+
+fn put_u16(data: &mut [u8], pos: usize, value: u16) {
+    data[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(data: &mut [u8], pos: usize, value: u32) {
+    data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(data: &mut [u8], pos: usize, value: u64) {
+    data[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Stores `value` in a 16-bit field unless the field defers to Zip64.
+fn update_u16(data: &mut [u8], pos: usize, value: u64) {
+    if u16_at(data, pos) as u64 != MAX_16 {
+        put_u16(data, pos, value.min(MAX_16) as u16);
+    }
+}
+
+fn update_u32(data: &mut [u8], pos: usize, value: u64) {
+    if u32_at(data, pos) as u64 != MAX_32 {
+        put_u32(data, pos, value.min(MAX_32) as u32);
+    }
+}
+
+/// Rewrites the disk numbers and offsets in a central directory and the end
+/// records after it. `tail` starts at the central directory; `locate` gives
+/// the new (disk, offset) of byte `i` of `tail`, and `translate` the new
+/// position of a local header stored as (disk, offset).
+fn patch_directory(
+    tail: &mut [u8],
+    locate: impl Fn(u64) -> (u32, u64),
+    translate: impl Fn(u32, u64) -> (u32, u64),
+    last_disk: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pos = 0;
+    let mut on_last_disk = 0u64;
+    while pos + 46 <= tail.len() && &tail[pos..pos + 4] == CENTRAL_HEADER_SIGNATURE {
+        let name_len = u16_at(tail, pos + 28) as usize;
+        let extra_len = u16_at(tail, pos + 30) as usize;
+        let comment_len = u16_at(tail, pos + 32) as usize;
+        let record_len = 46 + name_len + extra_len + comment_len;
+        if pos + record_len > tail.len() {
+            return Err("Truncated central directory".into());
+        }
+
+        // Zip64 values follow for the saturated fixed fields, in this order
+        let mut zip64 = None;
+        let mut field = pos + 46 + name_len;
+        while field + 4 <= pos + 46 + name_len + extra_len {
+            if u16_at(tail, field) == ZIP64_EXTRA_ID {
+                zip64 = Some(field + 4);
+                break;
+            }
+            field += 4 + u16_at(tail, field + 2) as usize;
+        }
+        let saturated = |pos: usize, width: usize| match width {
+            2 => u16_at(tail, pos) as u64 == MAX_16,
+            _ => u32_at(tail, pos) as u64 == MAX_32,
+        };
+        let mut slot = zip64.unwrap_or(0) + 8 * (saturated(pos + 24, 4) as usize + saturated(pos + 20, 4) as usize);
+        let offset_slot = saturated(pos + 42, 4).then_some(slot);
+        slot += 8 * offset_slot.is_some() as usize;
+        let disk_slot = saturated(pos + 34, 2).then_some(slot);
+        if zip64.is_none() && (offset_slot.is_some() || disk_slot.is_some()) {
+            return Err("Missing Zip64 extra field".into());
+        }
+
+        let offset = match offset_slot {
+            Some(slot) => u64_at(tail, slot),
+            None => u32_at(tail, pos + 42) as u64,
+        };
+        let disk = match disk_slot {
+            Some(slot) => u32_at(tail, slot),
+            None => u16_at(tail, pos + 34) as u32,
+        };
+        let (disk, offset) = translate(disk, offset);
+        match offset_slot {
+            Some(slot) => put_u64(tail, slot, offset),
+            None if offset < MAX_32 => put_u32(tail, pos + 42, offset as u32),
+            None => return Err("Local header offset does not fit the central directory".into()),
+        }
+        match disk_slot {
+            Some(slot) => put_u32(tail, slot, disk),
+            None if (disk as u64) < MAX_16 => put_u16(tail, pos + 34, disk as u16),
+            None => return Err("Too many volumes".into()),
+        }
+
+        if locate(pos as u64).0 == last_disk {
+            on_last_disk += 1;
+        }
+        pos += record_len;
+    }
+
+    let (directory_disk, directory_offset) = locate(0);
+    if tail[pos..].starts_with(ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE) {
+        let record = pos;
+        let (record_disk, record_offset) = locate(record as u64);
+        put_u32(tail, record + 16, last_disk);
+        put_u32(tail, record + 20, directory_disk);
+        put_u64(tail, record + 24, on_last_disk);
+        put_u64(tail, record + 48, directory_offset);
+        pos += 12 + u64_at(tail, record + 4) as usize;
+        if tail[pos..].starts_with(ZIP64_LOCATOR_SIGNATURE) {
+            put_u32(tail, pos + 4, record_disk);
+            put_u64(tail, pos + 8, record_offset);
+            put_u32(tail, pos + 16, last_disk + 1);
+            pos += ZIP64_LOCATOR_SIZE;
+        }
+    }
+    if !tail[pos..].starts_with(END_OF_CENTRAL_DIRECTORY_SIGNATURE) {
+        return Err("End of central directory not found after the central directory".into());
+    }
+    update_u16(tail, pos + 4, last_disk as u64);
+    update_u16(tail, pos + 6, directory_disk as u64);
+    update_u16(tail, pos + 8, on_last_disk);
+    update_u32(tail, pos + 16, directory_offset);
+    Ok(())
+}
+
+/// Writes a stream across volume files, switching to the next one at each
+/// boundary (a position in the whole stream).
+struct VolumeWriter {
+    paths: Vec<PathBuf>,
+    boundaries: Vec<u64>,
+    current: Option<BufWriter<File>>,
+    disk: usize,
+    position: u64,
+}
+
+impl VolumeWriter {
+    fn new(paths: Vec<PathBuf>, boundaries: Vec<u64>) -> Self {
+        Self { paths, boundaries, current: None, disk: 0, position: 0 }
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.current.take() {
+            writer.flush()?;
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = self.boundaries.get(self.disk).copied().unwrap_or(u64::MAX);
+        if self.position == end {
+            if let Some(mut writer) = self.current.take() {
+                writer.flush()?;
+            }
+            self.disk += 1;
+            return self.write(buf);
+        }
+        if self.current.is_none() {
+            self.current = Some(BufWriter::new(File::create(&self.paths[self.disk])?));
+        }
+        let n = (buf.len() as u64).min(end - self.position) as usize;
+        let n = self.current.as_mut().unwrap().write(&buf[..n])?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Splits the finished archive at `path` into volumes of at most
+/// `volume_size` bytes and returns them in order. Numbered volumes replace the
+/// archive; in a spanned set it becomes the last volume. An archive that
+/// already fits is left as a single `.zip` when spanning.
+pub fn split_archive(path: &Path, volume_size: u64, style: VolumeStyle) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if volume_size < MIN_VOLUME_SIZE {
+        return Err(format!("Volumes must be at least {} KiB", MIN_VOLUME_SIZE / 1024).into());
+    }
+    let len = std::fs::metadata(path)?.len();
+    match style {
+        VolumeStyle::Numbered => {
+            let boundaries: Vec<u64> = (1..).map(|n| n * volume_size).take_while(|&end| end < len).collect();
+            let paths: Vec<PathBuf> = (1..=boundaries.len() + 1).map(|n| numbered_volume(path, n)).collect();
+            let mut writer = VolumeWriter::new(paths.clone(), boundaries);
+            io::copy(&mut BufReader::new(File::open(path)?), &mut writer)?;
+            writer.finish()?;
+            std::fs::remove_file(path)?;
+            Ok(paths)
+        }
+        VolumeStyle::Spanned if len <= volume_size => Ok(vec![path.to_path_buf()]),
+        VolumeStyle::Spanned => split_spanned(path, len, volume_size),
+    }
+}
+
+fn split_spanned(path: &Path, len: u64, volume_size: u64) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut file = File::open(path)?;
+    let (directory_disk, directory_start) = directory_location(&mut file, |_, offset| offset)?;
+    if directory_disk != 0 {
+        return Err("The archive is already split".into());
+    }
+    let mut tail = Vec::with_capacity((len - directory_start) as usize);
+    file.seek(SeekFrom::Start(directory_start))?;
+    (&mut file).take(len - directory_start).read_to_end(&mut tail)?;
+
+    // Positions in the spanned stream, which starts with the spanning
+    // signature. Headers and the end records must not be cut in two.
+    let shift = SPANNING_SIGNATURE.len() as u64;
+    let mut unsplittable = Vec::new();
+    let mut pos = 0;
+    while pos + 46 <= tail.len() && &tail[pos..pos + 4] == CENTRAL_HEADER_SIGNATURE {
+        let record_len = 46 + u16_at(&tail, pos + 28) as usize + u16_at(&tail, pos + 30) as usize + u16_at(&tail, pos + 32) as usize;
+        unsplittable.push((directory_start + shift + pos as u64, directory_start + shift + (pos + record_len) as u64));
+        pos += record_len;
+    }
+    unsplittable.push((directory_start + shift + pos as u64, len + shift));
+
+    let entries = zip::ZipArchive::new(File::open(path)?)?.len();
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut header = [0u8; 30];
+    for index in 0..entries {
+        let start = archive.by_index_raw(index)?.header_start();
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut header)?;
+        if &header[0..4] != LOCAL_HEADER_SIGNATURE {
+            return Err(format!("No local header at offset {}", start).into());
+        }
+        let header_len = 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+        unsplittable.push((start + shift, start + shift + header_len));
+    }
+    unsplittable.sort_unstable();
+
+    let total = len + shift;
+    let mut boundaries = Vec::new();
+    let mut volume_start = 0;
+    while volume_start + volume_size < total {
+        let mut cut = volume_start + volume_size;
+        let index = unsplittable.partition_point(|&(start, _)| start < cut);
+        if let Some(&(start, end)) = index.checked_sub(1).map(|index| &unsplittable[index]) {
+            if end > cut {
+                cut = start;
+            }
+        }
+        if cut <= volume_start {
+            return Err(format!("A header at offset {} does not fit in a volume; use larger volumes", cut - shift).into());
+        }
+        boundaries.push(cut);
+        volume_start = cut;
+    }
+
+    let locate = |position: u64| {
+        let disk = boundaries.partition_point(|&boundary| boundary <= position);
+        let start = if disk == 0 { 0 } else { boundaries[disk - 1] };
+        (disk as u32, position - start)
+    };
+    let last_disk = boundaries.len() as u32;
+    patch_directory(
+        &mut tail,
+        |index| locate(directory_start + shift + index),
+        |_, offset| locate(offset + shift),
+        last_disk,
+    )?;
+
+    let (temp_path, _) = create_temp_beside(path)?;
+    let mut paths: Vec<PathBuf> = (1..=boundaries.len()).map(|n| spanned_volume(path, n)).collect();
+    paths.push(temp_path.clone());
+    let mut writer = VolumeWriter::new(paths.clone(), boundaries.clone());
+    let result = (|| -> io::Result<()> {
+        writer.write_all(SPANNING_SIGNATURE)?;
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut BufReader::new(&mut file).take(directory_start), &mut writer)?;
+        writer.write_all(&tail)?;
+        writer.finish()
+    })();
+    if let Err(e) = result {
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        return Err(e.into());
+    }
+    std::fs::rename(&temp_path, path)?;
+    *paths.last_mut().unwrap() = path.to_path_buf();
+    Ok(paths)
+}
+
+/// True if `path` starts with a ZIP local header, as the first volume of a
+/// split ZIP does; other formats split this way cannot be checked.
+fn is_zip(path: &Path) -> io::Result<bool> {
+    let mut signature = [0u8; 4];
+    let read = File::open(path)?.read(&mut signature)?;
+    Ok(read == 4 && &signature == LOCAL_HEADER_SIGNATURE)
+}
+
+/// Joins the volumes of `set` into the single archive `output`. `progress`
+/// receives the fraction of the volumes copied so far.
+pub fn join_volumes(set: &VolumeSet, output: &Path, progress: impl Fn(f32)) -> Result<(), Box<dyn std::error::Error>> {
+    let total: u64 = set.volumes.iter().filter_map(|volume| std::fs::metadata(volume).ok()).map(|meta| meta.len()).sum();
+    let mut buffer = vec![0u8; COPY_BUFFER];
+    let mut copied = 0u64;
+    let mut writer = BufWriter::new(File::create(output)?);
+    // Position in `output` of offset 0 of each volume
+    let mut starts: Vec<i64> = Vec::with_capacity(set.volumes.len());
+    let mut position = 0i64;
+    for (disk, volume) in set.volumes.iter().enumerate() {
+        let mut file = File::open(volume).map_err(|e| format!("Cannot read volume {}: {}", volume.display(), e))?;
+        let mut skipped = 0;
+        if disk == 0 && set.style == VolumeStyle::Spanned {
+            let mut signature = [0u8; 4];
+            file.read_exact(&mut signature)?;
+            if &signature == SPANNING_SIGNATURE || &signature == SINGLE_SEGMENT_SIGNATURE {
+                skipped = signature.len() as i64;
+            } else {
+                file.seek(SeekFrom::Start(0))?;
+            }
+        }
+        starts.push(position - skipped);
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..n])?;
+            position += n as i64;
+            copied += n as u64;
+            progress(copied as f32 / total.max(1) as f32);
+        }
+    }
+    writer.into_inner().map_err(|e| e.into_error())?;
+    let mut file = File::options().read(true).write(true).open(output)?;
+    if set.style == VolumeStyle::Numbered {
+        // Volumes after the last one found leave the end records out
+        let (_, data) = read_end(&mut file)?;
+        if find_end_record(&data).is_none() && is_zip(&set.volumes[0])? {
+            let base = set.volumes[0].with_extension("");
+            return Err(format!("Missing volume {}", numbered_volume(&base, set.volumes.len() + 1).display()).into());
+        }
+        return Ok(());
+    }
+
+    let absolute = |disk: u32, offset: u64| starts.get(disk as usize).map_or(u64::MAX, |start| (start + offset as i64) as u64);
+    let (directory_disk, directory_offset) = directory_location(&mut file, absolute)?;
+    let directory_start = absolute(directory_disk, directory_offset);
+    if directory_start >= position as u64 {
+        return Err(format!("Volume {} is missing or truncated", directory_disk + 1).into());
+    }
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(directory_start))?;
+    file.read_to_end(&mut tail)?;
+    patch_directory(&mut tail, |index| (0, directory_start + index), |disk, offset| (0, absolute(disk, offset)), 0)?;
+    file.seek(SeekFrom::Start(directory_start))?;
+    file.write_all(&tail)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+    /// An archive of incompressible entries spanning several minimum-size volumes
+    fn write_archive(path: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut seed = 0x2545_F491u32;
+        let entries: Vec<(String, Vec<u8>)> = (0..5)
+            .map(|index| {
+                let data = (0..50_000)
+                    .map(|_| {
+                        seed ^= seed << 13;
+                        seed ^= seed >> 17;
+                        seed ^= seed << 5;
+                        seed as u8
+                    })
+                    .collect();
+                (format!("part{}.bin", index), data)
+            })
+            .collect();
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in &entries {
+            writer.start_file(name.as_str(), SimpleFileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+        entries
+    }
+
+    fn assert_joins_back(any_volume: &Path, style: VolumeStyle, entries: &[(String, Vec<u8>)]) {
+        let set = find_volume_set(any_volume).unwrap().unwrap();
+        assert_eq!(set.style, style);
+        assert_eq!(set.name, "data.zip");
+        let joined = any_volume.parent().unwrap().join("joined.zip");
+        join_volumes(&set, &joined, |_| {}).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&joined).unwrap()).unwrap();
+        assert_eq!(archive.len(), entries.len());
+        for (name, data) in entries {
+            let mut read = Vec::new();
+            archive.by_name(name).unwrap().read_to_end(&mut read).unwrap();
+            assert_eq!(&read, data);
+        }
+    }
+
+    #[test]
+    fn numbered_volumes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.zip");
+        let entries = write_archive(&path);
+        let volumes = split_archive(&path, MIN_VOLUME_SIZE, VolumeStyle::Numbered).unwrap();
+        assert_eq!(volumes.len(), 4);
+        assert!(volumes.iter().all(|volume| std::fs::metadata(volume).unwrap().len() <= MIN_VOLUME_SIZE));
+        assert_joins_back(&volumes[2], VolumeStyle::Numbered, &entries);
+
+        std::fs::remove_file(&volumes[3]).unwrap();
+        let set = find_volume_set(&volumes[0]).unwrap().unwrap();
+        let error = join_volumes(&set, &dir.path().join("short.zip"), |_| {}).unwrap_err();
+        assert!(error.to_string().contains("data.zip.004"), "{}", error);
+    }
+
+    #[test]
+    fn spanned_volumes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.zip");
+        let entries = write_archive(&path);
+        let volumes = split_archive(&path, MIN_VOLUME_SIZE, VolumeStyle::Spanned).unwrap();
+        assert_eq!(volumes.last(), Some(&path));
+        assert!(volumes[0].ends_with("data.z01"));
+        assert!(volumes.iter().all(|volume| std::fs::metadata(volume).unwrap().len() <= MIN_VOLUME_SIZE));
+        assert_joins_back(&volumes[0], VolumeStyle::Spanned, &entries);
+
+        std::fs::remove_file(&volumes[1]).unwrap();
+        let error = find_volume_set(&path).err().unwrap();
+        assert!(error.to_string().contains("data.z02"), "{}", error);
+    }
+
+    #[test]
+    fn other_formats_split_into_numbered_volumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.7z");
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        let volumes = split_archive(&path, MIN_VOLUME_SIZE, VolumeStyle::Numbered).unwrap();
+        assert!(volumes[0].ends_with("data.7z.001"));
+
+        let set = find_volume_set(&volumes[1]).unwrap().unwrap();
+        assert_eq!(set.name, "data.7z");
+        let joined = dir.path().join("joined.7z");
+        let reported = std::cell::Cell::new(0.0);
+        join_volumes(&set, &joined, |progress| reported.set(progress)).unwrap();
+        assert!(std::fs::read(&joined).unwrap() == data);
+        assert_eq!(reported.get(), 1.0);
+    }
+
+    #[test]
+    fn recognises_volume_names() {
+        assert!(is_volume_name(Path::new("a.zip.001")));
+        assert!(is_volume_name(Path::new("a.z01")));
+        assert!(is_volume_name(Path::new("a.z100")));
+        assert!(!is_volume_name(Path::new("a.zip")));
+        assert!(!is_volume_name(Path::new("a.zst")));
+    }
+}